@@ -0,0 +1,10 @@
+use xen9pfs::backend::Fs9pBackend;
+use xen9pfs::error::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let mut backend = Fs9pBackend::new().await?;
+    backend.run().await?;
+    Ok(())
+}
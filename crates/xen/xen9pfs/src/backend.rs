@@ -0,0 +1,325 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
+
+use log::{debug, error, info, warn};
+use tokio::task::JoinHandle;
+use xenevtchn::EventChannelService;
+use xengnt::GrantTab;
+use xenstore::{XsdClient, XsdInterface, XsdMultiWatchHandle};
+
+use crate::{
+    error::{Error, Result},
+    ring::{Fs9pRing, XEN_9PFS_MAX_RING_ORDER},
+    server::{Fs9pExport, Fs9pServer, SecurityModel},
+};
+
+const XENBUS_STATE_UNKNOWN: u32 = 0;
+const XENBUS_STATE_INITIALISING: u32 = 1;
+const XENBUS_STATE_INIT_WAIT: u32 = 2;
+const XENBUS_STATE_INITIALISED: u32 = 3;
+const XENBUS_STATE_CONNECTED: u32 = 4;
+const XENBUS_STATE_CLOSING: u32 = 5;
+const XENBUS_STATE_CLOSED: u32 = 6;
+
+const XEN_9PFS_VERSION: &str = "1";
+pub const DEFAULT_MAX_RINGS: u32 = 4;
+
+struct Fs9pDevice {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Fs9pDevice {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// serves every 9pfs device whose backend lives in this domain.
+pub struct Fs9pBackend {
+    store: XsdClient,
+    gnttab: GrantTab,
+    evtchn: EventChannelService,
+    backend_domid: u32,
+    backend_type: String,
+    max_rings: u32,
+    max_ring_order: u32,
+    devices: HashMap<(u32, u32), Fs9pDevice>,
+    watched_frontends: HashSet<String>,
+}
+
+impl Fs9pBackend {
+    pub async fn new() -> Result<Fs9pBackend> {
+        Ok(Fs9pBackend {
+            store: XsdClient::open().await?,
+            gnttab: GrantTab::open()?,
+            evtchn: EventChannelService::open().await?,
+            backend_domid: 0,
+            backend_type: "9pfs".to_string(),
+            max_rings: DEFAULT_MAX_RINGS,
+            max_ring_order: XEN_9PFS_MAX_RING_ORDER,
+            devices: HashMap::new(),
+            watched_frontends: HashSet::new(),
+        })
+    }
+
+    pub fn backend_domid(&mut self, backend_domid: u32) -> &mut Self {
+        self.backend_domid = backend_domid;
+        self
+    }
+
+    pub fn backend_type(&mut self, backend_type: impl AsRef<str>) -> &mut Self {
+        self.backend_type = backend_type.as_ref().to_string();
+        self
+    }
+
+    pub fn max_rings(&mut self, max_rings: u32) -> &mut Self {
+        self.max_rings = max_rings;
+        self
+    }
+
+    pub fn max_ring_order(&mut self, max_ring_order: u32) -> &mut Self {
+        self.max_ring_order = max_ring_order.min(XEN_9PFS_MAX_RING_ORDER);
+        self
+    }
+
+    fn backend_path(&self) -> String {
+        format!(
+            "/local/domain/{}/backend/{}",
+            self.backend_domid, self.backend_type
+        )
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let backend_path = self.backend_path();
+        let mut watch = self.store.create_multi_watch().await?;
+        self.store.bind_watch_id(watch.id, &backend_path).await?;
+        watch.add_path(&backend_path);
+        info!("serving 9pfs devices from {}", backend_path);
+
+        while watch.receiver.recv().await.is_some() {
+            if let Err(error) = self.scan(&mut watch).await {
+                error!("failed to process 9pfs devices: {}", error);
+            }
+        }
+        Ok(())
+    }
+
+    async fn scan(&mut self, watch: &mut XsdMultiWatchHandle) -> Result<()> {
+        let backend_path = self.backend_path();
+        let mut present = HashSet::new();
+        for domid in self.store.list(&backend_path).await? {
+            let Ok(domid) = u32::from_str(&domid) else {
+                continue;
+            };
+            for devid in self
+                .store
+                .list(format!("{}/{}", backend_path, domid))
+                .await?
+            {
+                let Ok(devid) = u32::from_str(&devid) else {
+                    continue;
+                };
+                present.insert((domid, devid));
+                if let Err(error) = self.update(watch, domid, devid).await {
+                    warn!(
+                        "failed to update 9pfs device {} of domain {}: {}",
+                        devid, domid, error
+                    );
+                }
+            }
+        }
+
+        self.devices.retain(|key, _| {
+            let keep = present.contains(key);
+            if !keep {
+                debug!("9pfs device {} of domain {} was removed", key.1, key.0);
+            }
+            keep
+        });
+        Ok(())
+    }
+
+    async fn update(
+        &mut self,
+        watch: &mut XsdMultiWatchHandle,
+        domid: u32,
+        devid: u32,
+    ) -> Result<()> {
+        let path = format!("{}/{}/{}", self.backend_path(), domid, devid);
+        let Some(state) = self.read_u32(format!("{}/state", path)).await? else {
+            return Ok(());
+        };
+        let Some(frontend) = self.store.read_string(format!("{}/frontend", path)).await? else {
+            return Ok(());
+        };
+        if !self.watched_frontends.contains(&frontend) {
+            let frontend_state = format!("{}/state", frontend);
+            self.store.bind_watch_id(watch.id, &frontend_state).await?;
+            watch.add_path(&frontend_state);
+            self.watched_frontends.insert(frontend.clone());
+        }
+        let frontend_state = self
+            .read_u32(format!("{}/state", frontend))
+            .await?
+            .unwrap_or(XENBUS_STATE_UNKNOWN);
+
+        match (state, frontend_state) {
+            (XENBUS_STATE_INITIALISING, _) | (XENBUS_STATE_CLOSED, XENBUS_STATE_INITIALISING) => {
+                self.store
+                    .write_string(format!("{}/versions", path), XEN_9PFS_VERSION)
+                    .await?;
+                self.store
+                    .write_string(format!("{}/max-rings", path), &self.max_rings.to_string())
+                    .await?;
+                self.store
+                    .write_string(
+                        format!("{}/max-ring-page-order", path),
+                        &self.max_ring_order.to_string(),
+                    )
+                    .await?;
+                self.set_state(&path, XENBUS_STATE_INIT_WAIT).await?;
+            }
+
+            (XENBUS_STATE_INIT_WAIT, XENBUS_STATE_INITIALISED) => {
+                match self.connect(domid, &path, &frontend).await {
+                    Ok(device) => {
+                        self.devices.insert((domid, devid), device);
+                        self.set_state(&path, XENBUS_STATE_CONNECTED).await?;
+                        info!("connected 9pfs device {} of domain {}", devid, domid);
+                    }
+                    Err(error) => {
+                        self.set_state(&path, XENBUS_STATE_CLOSED).await?;
+                        return Err(error);
+                    }
+                }
+            }
+
+            (
+                XENBUS_STATE_INIT_WAIT | XENBUS_STATE_CONNECTED,
+                XENBUS_STATE_CLOSING | XENBUS_STATE_CLOSED,
+            ) => {
+                self.devices.remove(&(domid, devid));
+                self.set_state(&path, XENBUS_STATE_CLOSED).await?;
+                info!("disconnected 9pfs device {} of domain {}", devid, domid);
+            }
+
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn connect(&self, domid: u32, path: &str, frontend: &str) -> Result<Fs9pDevice> {
+        let version = self
+            .store
+            .read_string(format!("{}/version", frontend))
+            .await?
+            .ok_or_else(|| Error::FrontendParameterMissing("version".to_string()))?;
+        if version != XEN_9PFS_VERSION {
+            return Err(Error::RingProtocol(format!(
+                "frontend requested unsupported version {}",
+                version
+            )));
+        }
+
+        let num_rings = self
+            .read_u32(format!("{}/num-rings", frontend))
+            .await?
+            .ok_or_else(|| Error::FrontendParameterMissing("num-rings".to_string()))?;
+        if num_rings == 0 || num_rings > self.max_rings {
+            return Err(Error::RingProtocol(format!(
+                "frontend requested {} rings, at most {} are supported",
+                num_rings, self.max_rings
+            )));
+        }
+
+        let root = self
+            .store
+            .read_string(format!("{}/path", path))
+            .await?
+            .ok_or_else(|| Error::BackendParameterMissing("path".to_string()))?;
+        let security_model = match self
+            .store
+            .read_string(format!("{}/security_model", path))
+            .await?
+        {
+            Some(security_model) => SecurityModel::from_str(&security_model)?,
+            None => SecurityModel::None,
+        };
+        let read_only = self
+            .store
+            .read_string(format!("{}/mode", path))
+            .await?
+            .is_some_and(|mode| mode == "r");
+
+        let mut rings = Vec::new();
+        for index in 0..num_rings {
+            let ring_ref = self
+                .read_u32(format!("{}/ring-ref{}", frontend, index))
+                .await?
+                .ok_or_else(|| Error::FrontendParameterMissing(format!("ring-ref{}", index)))?;
+            let port = self
+                .read_u32(format!("{}/event-channel-{}", frontend, index))
+                .await?
+                .ok_or_else(|| {
+                    Error::FrontendParameterMissing(format!("event-channel-{}", index))
+                })?;
+            rings.push(
+                Fs9pRing::map(
+                    &self.gnttab,
+                    &self.evtchn,
+                    domid,
+                    ring_ref,
+                    port,
+                    self.max_ring_order,
+                )
+                .await?,
+            );
+        }
+
+        // every message has to fit within a single half of the smallest ring.
+        let max_msize = rings
+            .iter()
+            .map(|ring| ring.ring_size())
+            .min()
+            .unwrap_or_default() as u32;
+        let mut export = Fs9pExport::new(&root);
+        export.security_model(security_model).read_only(read_only);
+        let server = Arc::new(Fs9pServer::new(export.done(), max_msize)?);
+        debug!(
+            "exporting {} to domain {} with {} rings (security_model={:?} read_only={})",
+            root, domid, num_rings, security_model, read_only
+        );
+
+        let tasks = rings
+            .into_iter()
+            .map(|ring| {
+                let server = server.clone();
+                tokio::task::spawn(async move {
+                    if let Err(error) = ring.serve(server).await {
+                        error!("9pfs ring of domain {} failed: {}", domid, error);
+                    }
+                })
+            })
+            .collect();
+        Ok(Fs9pDevice { tasks })
+    }
+
+    async fn set_state(&self, path: &str, state: u32) -> Result<()> {
+        self.store
+            .write_string(format!("{}/state", path), &state.to_string())
+            .await?;
+        Ok(())
+    }
+
+    async fn read_u32(&self, path: String) -> Result<Option<u32>> {
+        match self.store.read_string(path).await? {
+            Some(value) => Ok(Some(u32::from_str(value.trim())?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::io;
+use std::num::ParseIntError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io issue encountered: {0}")]
+    Io(#[from] io::Error),
+    #[error("xenstore error: {0}")]
+    XenStore(#[from] xenstore::error::Error),
+    #[error("grant table error: {0}")]
+    GrantTable(#[from] xengnt::error::Error),
+    #[error("event channel error: {0}")]
+    EventChannel(#[from] xenevtchn::error::Error),
+    #[error("unable to parse integer: {0}")]
+    ParseInt(#[from] ParseIntError),
+    #[error("invalid 9p message: {0}")]
+    InvalidMessage(String),
+    #[error("ring protocol violation: {0}")]
+    RingProtocol(String),
+    #[error("frontend parameter missing: {0}")]
+    FrontendParameterMissing(String),
+    #[error("backend parameter missing: {0}")]
+    BackendParameterMissing(String),
+    #[error("unsupported security model: {0}")]
+    UnsupportedSecurityModel(String),
+    #[error("failed to join blocking task")]
+    BlockingTaskJoin,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,5 @@
+pub mod backend;
+pub mod error;
+pub mod protocol;
+pub mod ring;
+pub mod server;
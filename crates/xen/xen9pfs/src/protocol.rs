@@ -0,0 +1,1011 @@
+use crate::error::{Error, Result};
+
+pub const P9_PROTO_2000L: &str = "9P2000.L";
+pub const P9_NOTAG: u16 = 0xffff;
+pub const P9_NOFID: u32 = 0xffffffff;
+pub const P9_HEADER_SIZE: usize = 7;
+pub const P9_MAXWELEM: usize = 16;
+
+pub const P9_TLERROR: u8 = 6;
+pub const P9_RLERROR: u8 = 7;
+pub const P9_TSTATFS: u8 = 8;
+pub const P9_RSTATFS: u8 = 9;
+pub const P9_TLOPEN: u8 = 12;
+pub const P9_RLOPEN: u8 = 13;
+pub const P9_TLCREATE: u8 = 14;
+pub const P9_RLCREATE: u8 = 15;
+pub const P9_TSYMLINK: u8 = 16;
+pub const P9_RSYMLINK: u8 = 17;
+pub const P9_TMKNOD: u8 = 18;
+pub const P9_RMKNOD: u8 = 19;
+pub const P9_TRENAME: u8 = 20;
+pub const P9_RRENAME: u8 = 21;
+pub const P9_TREADLINK: u8 = 22;
+pub const P9_RREADLINK: u8 = 23;
+pub const P9_TGETATTR: u8 = 24;
+pub const P9_RGETATTR: u8 = 25;
+pub const P9_TSETATTR: u8 = 26;
+pub const P9_RSETATTR: u8 = 27;
+pub const P9_TXATTRWALK: u8 = 30;
+pub const P9_RXATTRWALK: u8 = 31;
+pub const P9_TXATTRCREATE: u8 = 32;
+pub const P9_RXATTRCREATE: u8 = 33;
+pub const P9_TREADDIR: u8 = 40;
+pub const P9_RREADDIR: u8 = 41;
+pub const P9_TFSYNC: u8 = 50;
+pub const P9_RFSYNC: u8 = 51;
+pub const P9_TLOCK: u8 = 52;
+pub const P9_RLOCK: u8 = 53;
+pub const P9_TGETLOCK: u8 = 54;
+pub const P9_RGETLOCK: u8 = 55;
+pub const P9_TLINK: u8 = 70;
+pub const P9_RLINK: u8 = 71;
+pub const P9_TMKDIR: u8 = 72;
+pub const P9_RMKDIR: u8 = 73;
+pub const P9_TRENAMEAT: u8 = 74;
+pub const P9_RRENAMEAT: u8 = 75;
+pub const P9_TUNLINKAT: u8 = 76;
+pub const P9_RUNLINKAT: u8 = 77;
+pub const P9_TVERSION: u8 = 100;
+pub const P9_RVERSION: u8 = 101;
+pub const P9_TAUTH: u8 = 102;
+pub const P9_RAUTH: u8 = 103;
+pub const P9_TATTACH: u8 = 104;
+pub const P9_RATTACH: u8 = 105;
+pub const P9_TFLUSH: u8 = 108;
+pub const P9_RFLUSH: u8 = 109;
+pub const P9_TWALK: u8 = 110;
+pub const P9_RWALK: u8 = 111;
+pub const P9_TREAD: u8 = 116;
+pub const P9_RREAD: u8 = 117;
+pub const P9_TWRITE: u8 = 118;
+pub const P9_RWRITE: u8 = 119;
+pub const P9_TCLUNK: u8 = 120;
+pub const P9_RCLUNK: u8 = 121;
+pub const P9_TREMOVE: u8 = 122;
+pub const P9_RREMOVE: u8 = 123;
+
+pub const P9_QTDIR: u8 = 0x80;
+pub const P9_QTSYMLINK: u8 = 0x02;
+pub const P9_QTFILE: u8 = 0x00;
+
+pub const P9_GETATTR_BASIC: u64 = 0x000007ff;
+
+pub const P9_SETATTR_MODE: u32 = 0x00000001;
+pub const P9_SETATTR_UID: u32 = 0x00000002;
+pub const P9_SETATTR_GID: u32 = 0x00000004;
+pub const P9_SETATTR_SIZE: u32 = 0x00000008;
+pub const P9_SETATTR_ATIME: u32 = 0x00000010;
+pub const P9_SETATTR_MTIME: u32 = 0x00000020;
+pub const P9_SETATTR_ATIME_SET: u32 = 0x00000080;
+pub const P9_SETATTR_MTIME_SET: u32 = 0x00000100;
+
+pub const P9_LOCK_SUCCESS: u8 = 0;
+pub const P9_LOCK_TYPE_UNLCK: u8 = 2;
+
+// qid + offset + type + name length, excluding the name itself.
+pub const P9_READDIR_ENTRY_HEADER_SIZE: usize = 13 + 8 + 1 + 2;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Qid {
+    pub kind: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Attributes {
+    pub valid: u64,
+    pub qid: Qid,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u64,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+    pub ctime_sec: u64,
+    pub ctime_nsec: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SetAttributes {
+    pub valid: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FsStats {
+    pub kind: u32,
+    pub bsize: u32,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: u64,
+    pub namelen: u32,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirEntry {
+    pub qid: Qid,
+    pub offset: u64,
+    pub kind: u8,
+    pub name: String,
+}
+
+impl DirEntry {
+    pub fn encoded_size(&self) -> usize {
+        P9_READDIR_ENTRY_HEADER_SIZE + self.name.len()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Request {
+    Version {
+        msize: u32,
+        version: String,
+    },
+    Auth {
+        afid: u32,
+        uname: String,
+        aname: String,
+        n_uname: u32,
+    },
+    Attach {
+        fid: u32,
+        afid: u32,
+        uname: String,
+        aname: String,
+        n_uname: u32,
+    },
+    Flush {
+        oldtag: u16,
+    },
+    Walk {
+        fid: u32,
+        newfid: u32,
+        names: Vec<String>,
+    },
+    Read {
+        fid: u32,
+        offset: u64,
+        count: u32,
+    },
+    Write {
+        fid: u32,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Clunk {
+        fid: u32,
+    },
+    Remove {
+        fid: u32,
+    },
+    Statfs {
+        fid: u32,
+    },
+    Lopen {
+        fid: u32,
+        flags: u32,
+    },
+    Lcreate {
+        fid: u32,
+        name: String,
+        flags: u32,
+        mode: u32,
+        gid: u32,
+    },
+    Symlink {
+        fid: u32,
+        name: String,
+        target: String,
+        gid: u32,
+    },
+    Mknod {
+        dfid: u32,
+        name: String,
+        mode: u32,
+        major: u32,
+        minor: u32,
+        gid: u32,
+    },
+    Rename {
+        fid: u32,
+        dfid: u32,
+        name: String,
+    },
+    Readlink {
+        fid: u32,
+    },
+    Getattr {
+        fid: u32,
+        request_mask: u64,
+    },
+    Setattr {
+        fid: u32,
+        attributes: SetAttributes,
+    },
+    Xattrwalk {
+        fid: u32,
+        newfid: u32,
+        name: String,
+    },
+    Xattrcreate {
+        fid: u32,
+        name: String,
+        size: u64,
+        flags: u32,
+    },
+    Readdir {
+        fid: u32,
+        offset: u64,
+        count: u32,
+    },
+    Fsync {
+        fid: u32,
+        datasync: u32,
+    },
+    Lock {
+        fid: u32,
+        kind: u8,
+        flags: u32,
+        start: u64,
+        length: u64,
+        proc_id: u32,
+        client_id: String,
+    },
+    Getlock {
+        fid: u32,
+        kind: u8,
+        start: u64,
+        length: u64,
+        proc_id: u32,
+        client_id: String,
+    },
+    Link {
+        dfid: u32,
+        fid: u32,
+        name: String,
+    },
+    Mkdir {
+        dfid: u32,
+        name: String,
+        mode: u32,
+        gid: u32,
+    },
+    Renameat {
+        olddirfid: u32,
+        oldname: String,
+        newdirfid: u32,
+        newname: String,
+    },
+    Unlinkat {
+        dirfid: u32,
+        name: String,
+        flags: u32,
+    },
+    Unknown {
+        kind: u8,
+    },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Response {
+    Lerror {
+        ecode: u32,
+    },
+    Version {
+        msize: u32,
+        version: String,
+    },
+    Attach {
+        qid: Qid,
+    },
+    Flush,
+    Walk {
+        qids: Vec<Qid>,
+    },
+    Read {
+        data: Vec<u8>,
+    },
+    Write {
+        count: u32,
+    },
+    Clunk,
+    Remove,
+    Statfs {
+        stats: FsStats,
+    },
+    Lopen {
+        qid: Qid,
+        iounit: u32,
+    },
+    Lcreate {
+        qid: Qid,
+        iounit: u32,
+    },
+    Symlink {
+        qid: Qid,
+    },
+    Mknod {
+        qid: Qid,
+    },
+    Rename,
+    Readlink {
+        target: String,
+    },
+    Getattr {
+        attributes: Attributes,
+    },
+    Setattr,
+    Xattrwalk {
+        size: u64,
+    },
+    Xattrcreate,
+    Readdir {
+        entries: Vec<DirEntry>,
+    },
+    Fsync,
+    Lock {
+        status: u8,
+    },
+    Getlock {
+        kind: u8,
+        start: u64,
+        length: u64,
+        proc_id: u32,
+        client_id: String,
+    },
+    Link,
+    Mkdir {
+        qid: Qid,
+    },
+    Renameat,
+    Unlinkat,
+}
+
+pub struct MessageReader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> MessageReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            position: 0,
+        }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.buffer.len() - self.position < count {
+            return Err(Error::InvalidMessage(format!(
+                "message truncated at offset {}",
+                self.position
+            )));
+        }
+        let slice = &self.buffer[self.position..self.position + count];
+        self.position += count;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64> {
+        let mut value = [0u8; 8];
+        value.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        let length = self.u16()? as usize;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| Error::InvalidMessage("string is not valid utf-8".to_string()))
+    }
+
+    pub fn data(&mut self) -> Result<Vec<u8>> {
+        let length = self.u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+}
+
+pub struct MessageWriter {
+    buffer: Vec<u8>,
+}
+
+impl MessageWriter {
+    pub fn new(kind: u8, tag: u16) -> Self {
+        let mut buffer = Vec::with_capacity(64);
+        buffer.extend_from_slice(&[0, 0, 0, 0]);
+        buffer.push(kind);
+        buffer.extend_from_slice(&tag.to_le_bytes());
+        Self { buffer }
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.buffer.push(value);
+        self
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, value: u32) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn string(&mut self, value: &str) -> &mut Self {
+        self.u16(value.len() as u16);
+        self.buffer.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    pub fn data(&mut self, value: &[u8]) -> &mut Self {
+        self.u32(value.len() as u32);
+        self.buffer.extend_from_slice(value);
+        self
+    }
+
+    pub fn qid(&mut self, qid: &Qid) -> &mut Self {
+        self.u8(qid.kind).u32(qid.version).u64(qid.path)
+    }
+
+    pub fn done(mut self) -> Vec<u8> {
+        let size = self.buffer.len() as u32;
+        self.buffer[0..4].copy_from_slice(&size.to_le_bytes());
+        self.buffer
+    }
+}
+
+// reads the size, type and tag from the start of a message.
+pub fn decode_header(buffer: &[u8]) -> Result<(u32, u8, u16)> {
+    let mut reader = MessageReader::new(buffer);
+    Ok((reader.u32()?, reader.u8()?, reader.u16()?))
+}
+
+impl Request {
+    pub fn decode(buffer: &[u8]) -> Result<(u16, Request)> {
+        let (size, kind, tag) = decode_header(buffer)?;
+        if size as usize != buffer.len() {
+            return Err(Error::InvalidMessage(format!(
+                "message size {} does not match buffer length {}",
+                size,
+                buffer.len()
+            )));
+        }
+
+        let mut r = MessageReader::new(&buffer[P9_HEADER_SIZE..]);
+        let request = match kind {
+            P9_TVERSION => Request::Version {
+                msize: r.u32()?,
+                version: r.string()?,
+            },
+
+            P9_TAUTH => Request::Auth {
+                afid: r.u32()?,
+                uname: r.string()?,
+                aname: r.string()?,
+                n_uname: r.u32()?,
+            },
+
+            P9_TATTACH => Request::Attach {
+                fid: r.u32()?,
+                afid: r.u32()?,
+                uname: r.string()?,
+                aname: r.string()?,
+                n_uname: r.u32()?,
+            },
+
+            P9_TFLUSH => Request::Flush { oldtag: r.u16()? },
+
+            P9_TWALK => {
+                let fid = r.u32()?;
+                let newfid = r.u32()?;
+                let count = r.u16()?;
+                let mut names = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    names.push(r.string()?);
+                }
+                Request::Walk { fid, newfid, names }
+            }
+
+            P9_TREAD => Request::Read {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                count: r.u32()?,
+            },
+
+            P9_TWRITE => Request::Write {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                data: r.data()?,
+            },
+
+            P9_TCLUNK => Request::Clunk { fid: r.u32()? },
+            P9_TREMOVE => Request::Remove { fid: r.u32()? },
+            P9_TSTATFS => Request::Statfs { fid: r.u32()? },
+
+            P9_TLOPEN => Request::Lopen {
+                fid: r.u32()?,
+                flags: r.u32()?,
+            },
+
+            P9_TLCREATE => Request::Lcreate {
+                fid: r.u32()?,
+                name: r.string()?,
+                flags: r.u32()?,
+                mode: r.u32()?,
+                gid: r.u32()?,
+            },
+
+            P9_TSYMLINK => Request::Symlink {
+                fid: r.u32()?,
+                name: r.string()?,
+                target: r.string()?,
+                gid: r.u32()?,
+            },
+
+            P9_TMKNOD => Request::Mknod {
+                dfid: r.u32()?,
+                name: r.string()?,
+                mode: r.u32()?,
+                major: r.u32()?,
+                minor: r.u32()?,
+                gid: r.u32()?,
+            },
+
+            P9_TRENAME => Request::Rename {
+                fid: r.u32()?,
+                dfid: r.u32()?,
+                name: r.string()?,
+            },
+
+            P9_TREADLINK => Request::Readlink { fid: r.u32()? },
+
+            P9_TGETATTR => Request::Getattr {
+                fid: r.u32()?,
+                request_mask: r.u64()?,
+            },
+
+            P9_TSETATTR => Request::Setattr {
+                fid: r.u32()?,
+                attributes: SetAttributes {
+                    valid: r.u32()?,
+                    mode: r.u32()?,
+                    uid: r.u32()?,
+                    gid: r.u32()?,
+                    size: r.u64()?,
+                    atime_sec: r.u64()?,
+                    atime_nsec: r.u64()?,
+                    mtime_sec: r.u64()?,
+                    mtime_nsec: r.u64()?,
+                },
+            },
+
+            P9_TXATTRWALK => Request::Xattrwalk {
+                fid: r.u32()?,
+                newfid: r.u32()?,
+                name: r.string()?,
+            },
+
+            P9_TXATTRCREATE => Request::Xattrcreate {
+                fid: r.u32()?,
+                name: r.string()?,
+                size: r.u64()?,
+                flags: r.u32()?,
+            },
+
+            P9_TREADDIR => Request::Readdir {
+                fid: r.u32()?,
+                offset: r.u64()?,
+                count: r.u32()?,
+            },
+
+            P9_TFSYNC => Request::Fsync {
+                fid: r.u32()?,
+                datasync: r.u32()?,
+            },
+
+            P9_TLOCK => Request::Lock {
+                fid: r.u32()?,
+                kind: r.u8()?,
+                flags: r.u32()?,
+                start: r.u64()?,
+                length: r.u64()?,
+                proc_id: r.u32()?,
+                client_id: r.string()?,
+            },
+
+            P9_TGETLOCK => Request::Getlock {
+                fid: r.u32()?,
+                kind: r.u8()?,
+                start: r.u64()?,
+                length: r.u64()?,
+                proc_id: r.u32()?,
+                client_id: r.string()?,
+            },
+
+            P9_TLINK => Request::Link {
+                dfid: r.u32()?,
+                fid: r.u32()?,
+                name: r.string()?,
+            },
+
+            P9_TMKDIR => Request::Mkdir {
+                dfid: r.u32()?,
+                name: r.string()?,
+                mode: r.u32()?,
+                gid: r.u32()?,
+            },
+
+            P9_TRENAMEAT => Request::Renameat {
+                olddirfid: r.u32()?,
+                oldname: r.string()?,
+                newdirfid: r.u32()?,
+                newname: r.string()?,
+            },
+
+            P9_TUNLINKAT => Request::Unlinkat {
+                dirfid: r.u32()?,
+                name: r.string()?,
+                flags: r.u32()?,
+            },
+
+            kind => Request::Unknown { kind },
+        };
+        Ok((tag, request))
+    }
+}
+
+impl Response {
+    pub fn encode(&self, tag: u16) -> Vec<u8> {
+        match self {
+            Response::Lerror { ecode } => {
+                let mut w = MessageWriter::new(P9_RLERROR, tag);
+                w.u32(*ecode);
+                w.done()
+            }
+
+            Response::Version { msize, version } => {
+                let mut w = MessageWriter::new(P9_RVERSION, tag);
+                w.u32(*msize).string(version);
+                w.done()
+            }
+
+            Response::Attach { qid } => {
+                let mut w = MessageWriter::new(P9_RATTACH, tag);
+                w.qid(qid);
+                w.done()
+            }
+
+            Response::Flush => MessageWriter::new(P9_RFLUSH, tag).done(),
+
+            Response::Walk { qids } => {
+                let mut w = MessageWriter::new(P9_RWALK, tag);
+                w.u16(qids.len() as u16);
+                for qid in qids {
+                    w.qid(qid);
+                }
+                w.done()
+            }
+
+            Response::Read { data } => {
+                let mut w = MessageWriter::new(P9_RREAD, tag);
+                w.data(data);
+                w.done()
+            }
+
+            Response::Write { count } => {
+                let mut w = MessageWriter::new(P9_RWRITE, tag);
+                w.u32(*count);
+                w.done()
+            }
+
+            Response::Clunk => MessageWriter::new(P9_RCLUNK, tag).done(),
+            Response::Remove => MessageWriter::new(P9_RREMOVE, tag).done(),
+
+            Response::Statfs { stats } => {
+                let mut w = MessageWriter::new(P9_RSTATFS, tag);
+                w.u32(stats.kind)
+                    .u32(stats.bsize)
+                    .u64(stats.blocks)
+                    .u64(stats.bfree)
+                    .u64(stats.bavail)
+                    .u64(stats.files)
+                    .u64(stats.ffree)
+                    .u64(stats.fsid)
+                    .u32(stats.namelen);
+                w.done()
+            }
+
+            Response::Lopen { qid, iounit } => {
+                let mut w = MessageWriter::new(P9_RLOPEN, tag);
+                w.qid(qid).u32(*iounit);
+                w.done()
+            }
+
+            Response::Lcreate { qid, iounit } => {
+                let mut w = MessageWriter::new(P9_RLCREATE, tag);
+                w.qid(qid).u32(*iounit);
+                w.done()
+            }
+
+            Response::Symlink { qid } => {
+                let mut w = MessageWriter::new(P9_RSYMLINK, tag);
+                w.qid(qid);
+                w.done()
+            }
+
+            Response::Mknod { qid } => {
+                let mut w = MessageWriter::new(P9_RMKNOD, tag);
+                w.qid(qid);
+                w.done()
+            }
+
+            Response::Rename => MessageWriter::new(P9_RRENAME, tag).done(),
+
+            Response::Readlink { target } => {
+                let mut w = MessageWriter::new(P9_RREADLINK, tag);
+                w.string(target);
+                w.done()
+            }
+
+            Response::Getattr { attributes: a } => {
+                let mut w = MessageWriter::new(P9_RGETATTR, tag);
+                w.u64(a.valid)
+                    .qid(&a.qid)
+                    .u32(a.mode)
+                    .u32(a.uid)
+                    .u32(a.gid)
+                    .u64(a.nlink)
+                    .u64(a.rdev)
+                    .u64(a.size)
+                    .u64(a.blksize)
+                    .u64(a.blocks)
+                    .u64(a.atime_sec)
+                    .u64(a.atime_nsec)
+                    .u64(a.mtime_sec)
+                    .u64(a.mtime_nsec)
+                    .u64(a.ctime_sec)
+                    .u64(a.ctime_nsec)
+                    // btime, gen and data_version are not reported.
+                    .u64(0)
+                    .u64(0)
+                    .u64(0)
+                    .u64(0);
+                w.done()
+            }
+
+            Response::Setattr => MessageWriter::new(P9_RSETATTR, tag).done(),
+
+            Response::Xattrwalk { size } => {
+                let mut w = MessageWriter::new(P9_RXATTRWALK, tag);
+                w.u64(*size);
+                w.done()
+            }
+
+            Response::Xattrcreate => MessageWriter::new(P9_RXATTRCREATE, tag).done(),
+
+            Response::Readdir { entries } => {
+                let mut w = MessageWriter::new(P9_RREADDIR, tag);
+                let count: usize = entries.iter().map(|entry| entry.encoded_size()).sum();
+                w.u32(count as u32);
+                for entry in entries {
+                    w.qid(&entry.qid)
+                        .u64(entry.offset)
+                        .u8(entry.kind)
+                        .string(&entry.name);
+                }
+                w.done()
+            }
+
+            Response::Fsync => MessageWriter::new(P9_RFSYNC, tag).done(),
+
+            Response::Lock { status } => {
+                let mut w = MessageWriter::new(P9_RLOCK, tag);
+                w.u8(*status);
+                w.done()
+            }
+
+            Response::Getlock {
+                kind,
+                start,
+                length,
+                proc_id,
+                client_id,
+            } => {
+                let mut w = MessageWriter::new(P9_RGETLOCK, tag);
+                w.u8(*kind)
+                    .u64(*start)
+                    .u64(*length)
+                    .u32(*proc_id)
+                    .string(client_id);
+                w.done()
+            }
+
+            Response::Link => MessageWriter::new(P9_RLINK, tag).done(),
+
+            Response::Mkdir { qid } => {
+                let mut w = MessageWriter::new(P9_RMKDIR, tag);
+                w.qid(qid);
+                w.done()
+            }
+
+            Response::Renameat => MessageWriter::new(P9_RRENAMEAT, tag).done(),
+            Response::Unlinkat => MessageWriter::new(P9_RUNLINKAT, tag).done(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_header, DirEntry, MessageReader, MessageWriter, Qid, Request, Response,
+        P9_HEADER_SIZE, P9_QTDIR, P9_RREADDIR, P9_RWALK, P9_TLCREATE, P9_TWALK, P9_TWRITE,
+    };
+
+    const QID: Qid = Qid {
+        kind: P9_QTDIR,
+        version: 7,
+        path: 0x1122334455667788,
+    };
+
+    fn walk(names: &[&str]) -> Vec<u8> {
+        let mut w = MessageWriter::new(P9_TWALK, 3);
+        w.u32(1).u32(2).u16(names.len() as u16);
+        for name in names {
+            w.string(name);
+        }
+        w.done()
+    }
+
+    #[test]
+    fn decodes_written_requests() {
+        let (tag, request) = Request::decode(&walk(&["usr", "lib"])).unwrap();
+        assert_eq!(tag, 3);
+        assert_eq!(
+            request,
+            Request::Walk {
+                fid: 1,
+                newfid: 2,
+                names: vec!["usr".to_string(), "lib".to_string()],
+            }
+        );
+
+        let mut w = MessageWriter::new(P9_TLCREATE, 9);
+        w.u32(4).string("file").u32(0o2).u32(0o644).u32(100);
+        assert_eq!(
+            Request::decode(&w.done()).unwrap(),
+            (
+                9,
+                Request::Lcreate {
+                    fid: 4,
+                    name: "file".to_string(),
+                    flags: 0o2,
+                    mode: 0o644,
+                    gid: 100,
+                }
+            )
+        );
+
+        let mut w = MessageWriter::new(P9_TWRITE, 1);
+        w.u32(5).u64(4096).data(b"hello");
+        assert_eq!(
+            Request::decode(&w.done()).unwrap().1,
+            Request::Write {
+                fid: 5,
+                offset: 4096,
+                data: b"hello".to_vec(),
+            }
+        );
+
+        let w = MessageWriter::new(0xff, 1);
+        assert_eq!(
+            Request::decode(&w.done()).unwrap().1,
+            Request::Unknown { kind: 0xff }
+        );
+    }
+
+    #[test]
+    fn encodes_responses_readably() {
+        let message = Response::Walk {
+            qids: vec![QID, QID],
+        }
+        .encode(3);
+        assert_eq!(
+            decode_header(&message).unwrap(),
+            (message.len() as u32, P9_RWALK, 3)
+        );
+        let mut r = MessageReader::new(&message[P9_HEADER_SIZE..]);
+        assert_eq!(r.u16().unwrap(), 2);
+        for _ in 0..2 {
+            assert_eq!(r.u8().unwrap(), QID.kind);
+            assert_eq!(r.u32().unwrap(), QID.version);
+            assert_eq!(r.u64().unwrap(), QID.path);
+        }
+        assert!(r.u8().is_err());
+
+        let entry = DirEntry {
+            qid: QID,
+            offset: 1,
+            kind: 4,
+            name: "dir".to_string(),
+        };
+        let message = Response::Readdir {
+            entries: vec![entry.clone()],
+        }
+        .encode(5);
+        assert_eq!(decode_header(&message).unwrap().1, P9_RREADDIR);
+        // the entries are sent as one counted blob.
+        let mut r = MessageReader::new(&message[P9_HEADER_SIZE..]);
+        assert_eq!(r.data().unwrap().len(), entry.encoded_size());
+    }
+
+    #[test]
+    fn rejects_truncated_requests() {
+        let message = walk(&["usr", "lib"]);
+        for length in [0, 3, P9_HEADER_SIZE, message.len() - 1] {
+            let mut truncated = message[..length].to_vec();
+            if truncated.len() >= 4 {
+                // a consistent size field, so only the body is short.
+                truncated[..4].copy_from_slice(&(length as u32).to_le_bytes());
+            }
+            assert!(Request::decode(&truncated).is_err(), "{}", length);
+        }
+
+        // a string claiming more bytes than the message holds.
+        let mut w = MessageWriter::new(P9_TWALK, 3);
+        w.u32(1).u32(2).u16(1).u16(64);
+        assert!(Request::decode(&w.done()).is_err());
+    }
+
+    #[test]
+    fn rejects_mismatched_frame_sizes() {
+        let mut message = walk(&["usr"]);
+        let size = message.len() as u32;
+        message[..4].copy_from_slice(&(size + 1).to_le_bytes());
+        assert!(Request::decode(&message).is_err());
+        message[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Request::decode(&message).is_err());
+
+        let mut message = walk(&["usr"]);
+        message.extend_from_slice(&[0; 8]);
+        assert!(Request::decode(&message).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::{
+    atomic::{fence, AtomicU32, Ordering},
+    Arc,
+};
+
+use log::debug;
+use xenevtchn::{BoundEventChannel, EventChannelService};
+use xengnt::{sys::GrantRef, GrantTab, MappedMemory};
+
+use crate::{
+    error::{Error, Result},
+    protocol::{decode_header, P9_HEADER_SIZE},
+    server::Fs9pServer,
+};
+
+pub const XEN_PAGE_SHIFT: u32 = 12;
+pub const XEN_9PFS_MAX_RING_ORDER: u32 = 9;
+
+// layout of struct xen_9pfs_data_intf.
+const INTF_IN_CONS: usize = 0;
+const INTF_IN_PROD: usize = 4;
+const INTF_OUT_CONS: usize = 64;
+const INTF_OUT_PROD: usize = 68;
+const INTF_RING_ORDER: usize = 128;
+const INTF_REF: usize = 132;
+
+// a single xen_9pfs ring: the frontend produces requests into the 'out'
+// half of the data area and the backend replies through the 'in' half.
+pub struct Fs9pRing {
+    interface: MappedMemory<'static>,
+    data: MappedMemory<'static>,
+    ring_size: usize,
+    channel: BoundEventChannel,
+}
+
+impl Fs9pRing {
+    pub async fn map(
+        gnttab: &GrantTab,
+        evtchn: &EventChannelService,
+        domid: u32,
+        ring_ref: u32,
+        port: u32,
+        max_ring_order: u32,
+    ) -> Result<Fs9pRing> {
+        let interface = gnttab
+            .map_grant_refs(
+                vec![GrantRef {
+                    domid,
+                    reference: ring_ref,
+                }],
+                true,
+                true,
+            )
+            .await?;
+
+        let order = interface.read::<u32>(INTF_RING_ORDER)?;
+        if order == 0 || order > max_ring_order {
+            return Err(Error::RingProtocol(format!(
+                "ring order {} is outside of 1..={}",
+                order, max_ring_order
+            )));
+        }
+
+        let refs = (0..1usize << order)
+            .map(|index| {
+                let reference = interface.read::<u32>(INTF_REF + index * 4)?;
+                Ok(GrantRef { domid, reference })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let data = gnttab.map_grant_refs(refs, true, true).await?;
+        let channel = evtchn.bind(domid, port).await?;
+
+        Ok(Fs9pRing {
+            interface,
+            data,
+            ring_size: 1 << (order + XEN_PAGE_SHIFT - 1),
+            channel,
+        })
+    }
+
+    pub fn ring_size(&self) -> usize {
+        self.ring_size
+    }
+
+    fn index(&self, offset: usize) -> &AtomicU32 {
+        self.interface
+            .atomic_u32(offset)
+            .expect("ring indexes are within the interface page")
+    }
+
+    fn copy_from_ring(&self, half: usize, position: u32, buffer: &mut [u8]) -> Result<()> {
+        let start = position as usize & (self.ring_size - 1);
+        let first = buffer.len().min(self.ring_size - start);
+        let (head, tail) = buffer.split_at_mut(first);
+        self.data.read_bytes(half + start, head)?;
+        self.data.read_bytes(half, tail)?;
+        Ok(())
+    }
+
+    fn copy_to_ring(&self, half: usize, position: u32, buffer: &[u8]) -> Result<()> {
+        let start = position as usize & (self.ring_size - 1);
+        let first = buffer.len().min(self.ring_size - start);
+        let (head, tail) = buffer.split_at(first);
+        self.data.write_bytes(half + start, head)?;
+        self.data.write_bytes(half, tail)?;
+        Ok(())
+    }
+
+    // takes the next complete request off the out ring, if one is available.
+    pub fn read_request(&self) -> Result<Option<Vec<u8>>> {
+        let cons = self.index(INTF_OUT_CONS).load(Ordering::Relaxed);
+        let prod = self.index(INTF_OUT_PROD).load(Ordering::Acquire);
+        let available = prod.wrapping_sub(cons) as usize;
+        if available > self.ring_size {
+            return Err(Error::RingProtocol(format!(
+                "frontend produced {} bytes into a {} byte ring",
+                available, self.ring_size
+            )));
+        }
+        if available < P9_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; P9_HEADER_SIZE];
+        self.copy_from_ring(self.ring_size, cons, &mut header)?;
+        let (size, _, _) = decode_header(&header)?;
+        let size = size as usize;
+        if !(P9_HEADER_SIZE..=self.ring_size).contains(&size) {
+            return Err(Error::RingProtocol(format!(
+                "request of {} bytes does not fit the ring",
+                size
+            )));
+        }
+        if available < size {
+            return Ok(None);
+        }
+
+        let mut request = vec![0u8; size];
+        self.copy_from_ring(self.ring_size, cons, &mut request)?;
+        fence(Ordering::SeqCst);
+        self.index(INTF_OUT_CONS)
+            .store(cons.wrapping_add(size as u32), Ordering::Release);
+        Ok(Some(request))
+    }
+
+    // places a response on the in ring, returning false when there is not yet space for it.
+    pub fn write_response(&self, response: &[u8]) -> Result<bool> {
+        if response.len() > self.ring_size {
+            return Err(Error::RingProtocol(format!(
+                "response of {} bytes does not fit the ring",
+                response.len()
+            )));
+        }
+        let prod = self.index(INTF_IN_PROD).load(Ordering::Relaxed);
+        let cons = self.index(INTF_IN_CONS).load(Ordering::Acquire);
+        let used = prod.wrapping_sub(cons) as usize;
+        if self.ring_size.saturating_sub(used) < response.len() {
+            return Ok(false);
+        }
+        self.copy_to_ring(0, prod, response)?;
+        fence(Ordering::SeqCst);
+        self.index(INTF_IN_PROD)
+            .store(prod.wrapping_add(response.len() as u32), Ordering::Release);
+        Ok(true)
+    }
+
+    pub async fn notify(&self) -> Result<()> {
+        self.channel.service.notify(self.channel.local_port).await?;
+        Ok(())
+    }
+
+    async fn wait(&self) -> Result<()> {
+        self.channel.receiver.notified().await;
+        self.channel.unmask().await?;
+        Ok(())
+    }
+
+    pub async fn serve(self, server: Arc<Fs9pServer>) -> Result<()> {
+        debug!(
+            "serving 9pfs ring on port {} with {} byte halves",
+            self.channel.local_port, self.ring_size
+        );
+        loop {
+            while let Some(request) = self.read_request()? {
+                self.notify().await?;
+                let handler = server.clone();
+                let response = tokio::task::spawn_blocking(move || handler.handle(&request))
+                    .await
+                    .map_err(|_| Error::BlockingTaskJoin)?;
+                while !self.write_response(&response)? {
+                    self.wait().await?;
+                }
+                self.notify().await?;
+            }
+            self.wait().await?;
+        }
+    }
+}
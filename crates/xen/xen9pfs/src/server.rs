@@ -0,0 +1,1326 @@
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString, OsStr},
+    fs::File,
+    io::{self, Write},
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
+        raw::c_int,
+        unix::{ffi::OsStrExt, fs::FileExt},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+};
+
+use log::{debug, trace};
+
+use crate::{
+    error::{Error, Result},
+    protocol::{
+        decode_header, Attributes, DirEntry, FsStats, Qid, Request, Response, SetAttributes,
+        P9_GETATTR_BASIC, P9_HEADER_SIZE, P9_LOCK_SUCCESS, P9_LOCK_TYPE_UNLCK, P9_MAXWELEM,
+        P9_NOFID, P9_NOTAG, P9_PROTO_2000L, P9_QTDIR, P9_QTFILE, P9_QTSYMLINK, P9_SETATTR_ATIME,
+        P9_SETATTR_ATIME_SET, P9_SETATTR_GID, P9_SETATTR_MODE, P9_SETATTR_MTIME,
+        P9_SETATTR_MTIME_SET, P9_SETATTR_SIZE, P9_SETATTR_UID,
+    },
+};
+
+const P9_DOTL_ACCMODE: u32 = 0o3;
+const P9_DOTL_WRONLY: u32 = 0o1;
+const P9_DOTL_RDWR: u32 = 0o2;
+const P9_DOTL_CREATE: u32 = 0o100;
+const P9_DOTL_TRUNC: u32 = 0o1000;
+const P9_DOTL_APPEND: u32 = 0o2000;
+const P9_DOTL_NONBLOCK: u32 = 0o4000;
+const P9_DOTL_DSYNC: u32 = 0o10000;
+const P9_DOTL_DIRECTORY: u32 = 0o200000;
+const P9_DOTL_NOATIME: u32 = 0o1000000;
+const P9_DOTL_SYNC: u32 = 0o4000000;
+
+const P9_DOTL_AT_REMOVEDIR: u32 = 0x200;
+
+// size of the Rread and Rreaddir headers, including the count field.
+const P9_IOHDR_SIZE: u32 = P9_HEADER_SIZE as u32 + 4;
+
+// the xattrs qemu uses to store credentials under the mapped security model.
+const XATTR_UID: &CStr = c"user.virtfs.uid";
+const XATTR_GID: &CStr = c"user.virtfs.gid";
+const XATTR_MODE: &CStr = c"user.virtfs.mode";
+const XATTR_RDEV: &CStr = c"user.virtfs.rdev";
+
+// permissions given to files on the host under the mapped security model.
+const MAPPED_FILE_MODE: u32 = 0o600;
+const MAPPED_DIR_MODE: u32 = 0o700;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SecurityModel {
+    // like passthrough, but failures to change ownership or permissions are ignored.
+    #[default]
+    None,
+    Passthrough,
+    MappedXattr,
+}
+
+impl FromStr for SecurityModel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(SecurityModel::None),
+            "passthrough" => Ok(SecurityModel::Passthrough),
+            "mapped" | "mapped-xattr" => Ok(SecurityModel::MappedXattr),
+            _ => Err(Error::UnsupportedSecurityModel(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Fs9pExport {
+    root: PathBuf,
+    security_model: SecurityModel,
+    read_only: bool,
+}
+
+impl Fs9pExport {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            security_model: SecurityModel::None,
+            read_only: false,
+        }
+    }
+
+    pub fn security_model(&mut self, security_model: SecurityModel) -> &mut Self {
+        self.security_model = security_model;
+        self
+    }
+
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn done(self) -> Self {
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn get_security_model(&self) -> SecurityModel {
+        self.security_model
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+struct Fid {
+    path: PathBuf,
+    uid: u32,
+    file: Option<File>,
+    entries: Option<Vec<DirEntry>>,
+}
+
+struct ServerState {
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl ServerState {
+    fn fid(&self, fid: u32) -> io::Result<&Fid> {
+        self.fids.get(&fid).ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn fid_mut(&mut self, fid: u32) -> io::Result<&mut Fid> {
+        self.fids.get_mut(&fid).ok_or_else(|| errno(libc::EBADF))
+    }
+
+    fn rename_fids(&mut self, from: &Path, to: &Path) {
+        for fid in self.fids.values_mut() {
+            if let Ok(rest) = fid.path.strip_prefix(from) {
+                fid.path = to.join(rest);
+            }
+        }
+    }
+}
+
+struct Credentials {
+    uid: u32,
+    gid: u32,
+    mode: u32,
+    rdev: Option<u64>,
+}
+
+// serves a single 9P2000.L session against a directory on the host.
+// every path is resolved beneath the export root, so neither '..' nor
+// symlinks created by the guest can reach outside of it.
+pub struct Fs9pServer {
+    export: Fs9pExport,
+    root: OwnedFd,
+    max_msize: u32,
+    state: Mutex<ServerState>,
+}
+
+impl Fs9pServer {
+    pub fn new(export: Fs9pExport, max_msize: u32) -> Result<Fs9pServer> {
+        let path = cstring(export.root.as_os_str())?;
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Fs9pServer {
+            export,
+            root: unsafe { OwnedFd::from_raw_fd(fd) },
+            max_msize,
+            state: Mutex::new(ServerState {
+                msize: max_msize,
+                fids: HashMap::new(),
+            }),
+        })
+    }
+
+    pub fn export(&self) -> &Fs9pExport {
+        &self.export
+    }
+
+    pub fn handle(&self, message: &[u8]) -> Vec<u8> {
+        match Request::decode(message) {
+            Ok((tag, request)) => self.process(request).encode(tag),
+            Err(error) => {
+                debug!("rejecting malformed 9p message: {}", error);
+                let tag = decode_header(message)
+                    .map(|(_, _, tag)| tag)
+                    .unwrap_or(P9_NOTAG);
+                Response::Lerror {
+                    ecode: libc::EINVAL as u32,
+                }
+                .encode(tag)
+            }
+        }
+    }
+
+    pub fn process(&self, request: Request) -> Response {
+        trace!("9p request {:?}", request);
+        let mut state = self.lock();
+        let result = match request {
+            Request::Version { msize, version } => Ok(self.version(&mut state, msize, &version)),
+
+            Request::Attach {
+                fid, afid, n_uname, ..
+            } => self.attach(&mut state, fid, afid, n_uname),
+
+            Request::Flush { .. } => Ok(Response::Flush),
+            Request::Walk { fid, newfid, names } => self.walk(&mut state, fid, newfid, &names),
+            Request::Read { fid, offset, count } => self.read(&state, fid, offset, count),
+            Request::Write { fid, offset, data } => self.write(&state, fid, offset, &data),
+
+            Request::Clunk { fid } => state
+                .fids
+                .remove(&fid)
+                .map(|_| Response::Clunk)
+                .ok_or_else(|| errno(libc::EBADF)),
+
+            Request::Remove { fid } => self.remove(&mut state, fid),
+            Request::Statfs { .. } => self.statfs(),
+            Request::Lopen { fid, flags } => self.lopen(&mut state, fid, flags),
+
+            Request::Lcreate {
+                fid,
+                name,
+                flags,
+                mode,
+                gid,
+            } => self.lcreate(&mut state, fid, &name, flags, mode, gid),
+
+            Request::Symlink {
+                fid,
+                name,
+                target,
+                gid,
+            } => self.symlink(&state, fid, &name, &target, gid),
+
+            Request::Mknod {
+                dfid,
+                name,
+                mode,
+                major,
+                minor,
+                gid,
+            } => self.mknod(&state, dfid, &name, mode, major, minor, gid),
+
+            Request::Rename { fid, dfid, name } => self.rename(&mut state, fid, dfid, &name),
+            Request::Readlink { fid } => self.readlink(&state, fid),
+            Request::Getattr { fid, .. } => self.getattr(&state, fid),
+            Request::Setattr { fid, attributes } => self.setattr(&state, fid, &attributes),
+            Request::Readdir { fid, offset, count } => self.readdir(&mut state, fid, offset, count),
+            Request::Fsync { fid, datasync } => self.fsync(&state, fid, datasync != 0),
+
+            // locks are advisory and only ever held by the one guest, so they always succeed.
+            Request::Lock { .. } => Ok(Response::Lock {
+                status: P9_LOCK_SUCCESS,
+            }),
+
+            Request::Getlock {
+                start,
+                length,
+                proc_id,
+                client_id,
+                ..
+            } => Ok(Response::Getlock {
+                kind: P9_LOCK_TYPE_UNLCK,
+                start,
+                length,
+                proc_id,
+                client_id,
+            }),
+
+            Request::Link { dfid, fid, name } => self.link(&state, dfid, fid, &name),
+
+            Request::Mkdir {
+                dfid,
+                name,
+                mode,
+                gid,
+            } => self.mkdir(&state, dfid, &name, mode, gid),
+
+            Request::Renameat {
+                olddirfid,
+                oldname,
+                newdirfid,
+                newname,
+            } => self.renameat(&mut state, olddirfid, &oldname, newdirfid, &newname),
+
+            Request::Unlinkat {
+                dirfid,
+                name,
+                flags,
+            } => self.unlinkat(&state, dirfid, &name, flags),
+
+            Request::Auth { .. }
+            | Request::Xattrwalk { .. }
+            | Request::Xattrcreate { .. }
+            | Request::Unknown { .. } => Err(errno(libc::EOPNOTSUPP)),
+        };
+
+        match result {
+            Ok(response) => response,
+            Err(error) => Response::Lerror {
+                ecode: error.raw_os_error().unwrap_or(libc::EIO) as u32,
+            },
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ServerState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn version(&self, state: &mut ServerState, msize: u32, version: &str) -> Response {
+        state.fids.clear();
+        state.msize = msize.min(self.max_msize);
+        let version = if version.starts_with(P9_PROTO_2000L) {
+            P9_PROTO_2000L
+        } else {
+            "unknown"
+        };
+        Response::Version {
+            msize: state.msize,
+            version: version.to_string(),
+        }
+    }
+
+    fn attach(
+        &self,
+        state: &mut ServerState,
+        fid: u32,
+        afid: u32,
+        n_uname: u32,
+    ) -> io::Result<Response> {
+        if afid != P9_NOFID {
+            return Err(errno(libc::EOPNOTSUPP));
+        }
+        if state.fids.contains_key(&fid) {
+            return Err(errno(libc::EBADF));
+        }
+        let path = PathBuf::new();
+        let qid = self.qid(&self.stat(&path)?);
+        state.fids.insert(
+            fid,
+            Fid {
+                path,
+                uid: if n_uname == P9_NOFID { 0 } else { n_uname },
+                file: None,
+                entries: None,
+            },
+        );
+        Ok(Response::Attach { qid })
+    }
+
+    fn walk(
+        &self,
+        state: &mut ServerState,
+        fid: u32,
+        newfid: u32,
+        names: &[String],
+    ) -> io::Result<Response> {
+        let (mut path, uid) = {
+            let fid = state.fid(fid)?;
+            if fid.file.is_some() {
+                return Err(errno(libc::EBADF));
+            }
+            (fid.path.clone(), fid.uid)
+        };
+        if newfid != fid && state.fids.contains_key(&newfid) {
+            return Err(errno(libc::EBADF));
+        }
+        if names.len() > P9_MAXWELEM {
+            return Err(errno(libc::EINVAL));
+        }
+
+        let mut qids = Vec::new();
+        let mut stat = self.stat(&path)?;
+        for name in names {
+            // only directories can be walked through, which stops a walk at a symlink.
+            let next = if !is_mode(stat.st_mode, libc::S_IFDIR) {
+                Err(errno(libc::ENOTDIR))
+            } else if name == ".." {
+                let mut parent = path.clone();
+                parent.pop();
+                Ok(parent)
+            } else {
+                validate_name(name).map(|name| path.join(name))
+            }
+            .and_then(|next| self.stat(&next).map(|stat| (next, stat)));
+
+            match next {
+                Ok((next, next_stat)) => {
+                    qids.push(self.qid(&next_stat));
+                    path = next;
+                    stat = next_stat;
+                }
+                Err(error) if qids.is_empty() => return Err(error),
+                Err(_) => break,
+            }
+        }
+
+        if qids.len() == names.len() {
+            state.fids.insert(
+                newfid,
+                Fid {
+                    path,
+                    uid,
+                    file: None,
+                    entries: None,
+                },
+            );
+        }
+        Ok(Response::Walk { qids })
+    }
+
+    fn read(&self, state: &ServerState, fid: u32, offset: u64, count: u32) -> io::Result<Response> {
+        let file = state
+            .fid(fid)?
+            .file
+            .as_ref()
+            .ok_or_else(|| errno(libc::EBADF))?;
+        let count = count.min(state.msize.saturating_sub(P9_IOHDR_SIZE)) as usize;
+        let mut data = vec![0u8; count];
+        let mut total = 0;
+        while total < count {
+            let size = file.read_at(&mut data[total..], offset + total as u64)?;
+            if size == 0 {
+                break;
+            }
+            total += size;
+        }
+        data.truncate(total);
+        Ok(Response::Read { data })
+    }
+
+    fn write(
+        &self,
+        state: &ServerState,
+        fid: u32,
+        offset: u64,
+        data: &[u8],
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let file = state
+            .fid(fid)?
+            .file
+            .as_ref()
+            .ok_or_else(|| errno(libc::EBADF))?;
+        file.write_all_at(data, offset)?;
+        Ok(Response::Write {
+            count: data.len() as u32,
+        })
+    }
+
+    fn remove(&self, state: &mut ServerState, fid: u32) -> io::Result<Response> {
+        // the fid is clunked even when the removal fails.
+        let fid = state.fids.remove(&fid).ok_or_else(|| errno(libc::EBADF))?;
+        self.check_writable()?;
+        let stat = self.stat(&fid.path)?;
+        let (parent, name) = split_path(&fid.path)?;
+        let parent = self.open_dir(&parent)?;
+        let flags = if is_mode(stat.st_mode, libc::S_IFDIR) {
+            libc::AT_REMOVEDIR
+        } else {
+            0
+        };
+        check(unsafe { libc::unlinkat(parent.as_raw_fd(), name.as_ptr(), flags) })?;
+        Ok(Response::Remove)
+    }
+
+    fn statfs(&self) -> io::Result<Response> {
+        let mut fs = MaybeUninit::<libc::statfs>::uninit();
+        check(unsafe { libc::fstatfs(self.root.as_raw_fd(), fs.as_mut_ptr()) })?;
+        let fs = unsafe { fs.assume_init() };
+        let mut vfs = MaybeUninit::<libc::statvfs>::uninit();
+        check(unsafe { libc::fstatvfs(self.root.as_raw_fd(), vfs.as_mut_ptr()) })?;
+        let vfs = unsafe { vfs.assume_init() };
+        Ok(Response::Statfs {
+            stats: FsStats {
+                kind: fs.f_type as u32,
+                bsize: vfs.f_bsize as u32,
+                blocks: vfs.f_blocks,
+                bfree: vfs.f_bfree,
+                bavail: vfs.f_bavail,
+                files: vfs.f_files,
+                ffree: vfs.f_ffree,
+                fsid: vfs.f_fsid,
+                namelen: vfs.f_namemax as u32,
+            },
+        })
+    }
+
+    fn lopen(&self, state: &mut ServerState, fid: u32, flags: u32) -> io::Result<Response> {
+        let path = {
+            let fid = state.fid(fid)?;
+            if fid.file.is_some() {
+                return Err(errno(libc::EBADF));
+            }
+            fid.path.clone()
+        };
+        if is_write_flags(flags) {
+            self.check_writable()?;
+        }
+
+        let stat = self.stat(&path)?;
+        let file = if is_mode(stat.st_mode, libc::S_IFDIR) {
+            if is_write_flags(flags) {
+                return Err(errno(libc::EISDIR));
+            }
+            self.resolve(&path, libc::O_RDONLY | libc::O_DIRECTORY, 0)?
+        } else if is_mode(stat.st_mode, libc::S_IFREG) {
+            // opened without blocking, as a fifo swapped in after the stat would
+            // otherwise stall every request behind the state lock.
+            let flags = open_flags(flags);
+            let file = self.resolve(&path, flags | libc::O_NOFOLLOW | libc::O_NONBLOCK, 0)?;
+            if !is_mode(fstat(&file)?.st_mode, libc::S_IFREG) {
+                return Err(errno(libc::ENXIO));
+            }
+            if flags & libc::O_NONBLOCK == 0 {
+                let status = check(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) })?;
+                check(unsafe {
+                    libc::fcntl(file.as_raw_fd(), libc::F_SETFL, status & !libc::O_NONBLOCK)
+                })?;
+            }
+            file
+        } else if is_mode(stat.st_mode, libc::S_IFLNK) {
+            return Err(errno(libc::ELOOP));
+        } else {
+            // device nodes, fifos and sockets are never opened on behalf of the guest.
+            return Err(errno(libc::ENXIO));
+        };
+
+        let fid = state.fid_mut(fid)?;
+        fid.file = Some(File::from(file));
+        fid.entries = None;
+        Ok(Response::Lopen {
+            qid: self.qid(&stat),
+            iounit: 0,
+        })
+    }
+
+    fn lcreate(
+        &self,
+        state: &mut ServerState,
+        fid: u32,
+        name: &str,
+        flags: u32,
+        mode: u32,
+        gid: u32,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let (directory, uid) = {
+            let fid = state.fid(fid)?;
+            if fid.file.is_some() {
+                return Err(errno(libc::EBADF));
+            }
+            (fid.path.clone(), fid.uid)
+        };
+        let name = validate_name(name)?;
+        let dir = self.open_dir(&directory)?;
+        let cname = cstring(OsStr::new(name))?;
+        let host_mode = match self.export.security_model {
+            SecurityModel::MappedXattr => MAPPED_FILE_MODE,
+            _ => mode & 0o7777,
+        };
+        let fd = check(unsafe {
+            libc::openat(
+                dir.as_raw_fd(),
+                cname.as_ptr(),
+                open_flags(flags) | libc::O_CREAT | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                host_mode,
+            )
+        })?;
+        let file = unsafe { OwnedFd::from_raw_fd(fd) };
+        self.apply_credentials(
+            &file,
+            &Credentials {
+                uid,
+                gid,
+                mode: libc::S_IFREG | (mode & 0o7777),
+                rdev: None,
+            },
+        )?;
+        let qid = self.qid(&self.attributes(&file)?);
+
+        let fid = state.fid_mut(fid)?;
+        fid.path = directory.join(name);
+        fid.file = Some(File::from(file));
+        fid.entries = None;
+        Ok(Response::Lcreate { qid, iounit: 0 })
+    }
+
+    fn symlink(
+        &self,
+        state: &ServerState,
+        fid: u32,
+        name: &str,
+        target: &str,
+        gid: u32,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let fid = state.fid(fid)?;
+        let name = validate_name(name)?;
+        let dir = self.open_dir(&fid.path)?;
+        let cname = cstring(OsStr::new(name))?;
+        let credentials = Credentials {
+            uid: fid.uid,
+            gid,
+            mode: libc::S_IFLNK | 0o777,
+            rdev: None,
+        };
+
+        if self.export.security_model == SecurityModel::MappedXattr {
+            // mapped symlinks are regular files holding the target, as qemu stores them.
+            let file = self.create_mapped_file(&dir, &cname)?;
+            let mut writer = File::from(file.try_clone()?);
+            writer.write_all(target.as_bytes())?;
+            self.apply_credentials(&file, &credentials)?;
+        } else {
+            let ctarget = cstring(OsStr::new(target))?;
+            check(unsafe { libc::symlinkat(ctarget.as_ptr(), dir.as_raw_fd(), cname.as_ptr()) })?;
+            self.apply_credentials(&self.open_node(&fid.path.join(name))?, &credentials)?;
+        }
+        let qid = self.qid(&self.stat(&fid.path.join(name))?);
+        Ok(Response::Symlink { qid })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn mknod(
+        &self,
+        state: &ServerState,
+        dfid: u32,
+        name: &str,
+        mode: u32,
+        major: u32,
+        minor: u32,
+        gid: u32,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let fid = state.fid(dfid)?;
+        let name = validate_name(name)?;
+        let dir = self.open_dir(&fid.path)?;
+        let cname = cstring(OsStr::new(name))?;
+        // only the mapped model keeps device nodes as plain files, the others
+        // would hand the guest real host devices.
+        if self.export.security_model != SecurityModel::MappedXattr
+            && (is_mode(mode, libc::S_IFBLK) || is_mode(mode, libc::S_IFCHR))
+        {
+            return Err(errno(libc::EPERM));
+        }
+        let rdev = libc::makedev(major, minor);
+        let credentials = Credentials {
+            uid: fid.uid,
+            gid,
+            mode,
+            rdev: Some(rdev),
+        };
+
+        if self.export.security_model == SecurityModel::MappedXattr {
+            let file = self.create_mapped_file(&dir, &cname)?;
+            self.apply_credentials(&file, &credentials)?;
+        } else {
+            check(unsafe { libc::mknodat(dir.as_raw_fd(), cname.as_ptr(), mode, rdev) })?;
+            self.apply_credentials(&self.open_node(&fid.path.join(name))?, &credentials)?;
+        }
+        let qid = self.qid(&self.stat(&fid.path.join(name))?);
+        Ok(Response::Mknod { qid })
+    }
+
+    fn rename(
+        &self,
+        state: &mut ServerState,
+        fid: u32,
+        dfid: u32,
+        name: &str,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let from = state.fid(fid)?.path.clone();
+        let directory = state.fid(dfid)?.path.clone();
+        let name = validate_name(name)?;
+        let (parent, old_name) = split_path(&from)?;
+        self.rename_at(&parent, &old_name, &directory, name)?;
+        state.rename_fids(&from, &directory.join(name));
+        Ok(Response::Rename)
+    }
+
+    fn renameat(
+        &self,
+        state: &mut ServerState,
+        olddirfid: u32,
+        oldname: &str,
+        newdirfid: u32,
+        newname: &str,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let old_directory = state.fid(olddirfid)?.path.clone();
+        let new_directory = state.fid(newdirfid)?.path.clone();
+        let oldname = validate_name(oldname)?;
+        let newname = validate_name(newname)?;
+        self.rename_at(
+            &old_directory,
+            &cstring(OsStr::new(oldname))?,
+            &new_directory,
+            newname,
+        )?;
+        state.rename_fids(&old_directory.join(oldname), &new_directory.join(newname));
+        Ok(Response::Renameat)
+    }
+
+    fn rename_at(
+        &self,
+        old_directory: &Path,
+        old_name: &CStr,
+        new_directory: &Path,
+        new_name: &str,
+    ) -> io::Result<()> {
+        let old_dir = self.open_dir(old_directory)?;
+        let new_dir = self.open_dir(new_directory)?;
+        let new_name = cstring(OsStr::new(new_name))?;
+        check(unsafe {
+            libc::renameat(
+                old_dir.as_raw_fd(),
+                old_name.as_ptr(),
+                new_dir.as_raw_fd(),
+                new_name.as_ptr(),
+            )
+        })?;
+        Ok(())
+    }
+
+    fn readlink(&self, state: &ServerState, fid: u32) -> io::Result<Response> {
+        let path = &state.fid(fid)?.path;
+        let node = self.open_node(path)?;
+        let stat = fstat(&node)?;
+        if !is_mode(self.apply_mapped(&node, stat)?.st_mode, libc::S_IFLNK) {
+            return Err(errno(libc::EINVAL));
+        }
+
+        let target = if is_mode(stat.st_mode, libc::S_IFLNK) {
+            let (parent, name) = split_path(path)?;
+            let parent = self.open_dir(&parent)?;
+            let mut buffer = vec![0u8; libc::PATH_MAX as usize];
+            let size = unsafe {
+                libc::readlinkat(
+                    parent.as_raw_fd(),
+                    name.as_ptr(),
+                    buffer.as_mut_ptr() as *mut libc::c_char,
+                    buffer.len(),
+                )
+            };
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            buffer.truncate(size as usize);
+            buffer
+        } else {
+            let file = File::from(self.resolve(path, libc::O_RDONLY | libc::O_NOFOLLOW, 0)?);
+            let mut buffer = vec![0u8; libc::PATH_MAX as usize];
+            let size = file.read_at(&mut buffer, 0)?;
+            buffer.truncate(size);
+            buffer
+        };
+        Ok(Response::Readlink {
+            target: String::from_utf8_lossy(&target).to_string(),
+        })
+    }
+
+    fn getattr(&self, state: &ServerState, fid: u32) -> io::Result<Response> {
+        let stat = self.stat(&state.fid(fid)?.path)?;
+        Ok(Response::Getattr {
+            attributes: Attributes {
+                valid: P9_GETATTR_BASIC,
+                qid: self.qid(&stat),
+                mode: stat.st_mode,
+                uid: stat.st_uid,
+                gid: stat.st_gid,
+                nlink: stat.st_nlink,
+                rdev: stat.st_rdev,
+                size: stat.st_size as u64,
+                blksize: stat.st_blksize as u64,
+                blocks: stat.st_blocks as u64,
+                atime_sec: stat.st_atime as u64,
+                atime_nsec: stat.st_atime_nsec as u64,
+                mtime_sec: stat.st_mtime as u64,
+                mtime_nsec: stat.st_mtime_nsec as u64,
+                ctime_sec: stat.st_ctime as u64,
+                ctime_nsec: stat.st_ctime_nsec as u64,
+            },
+        })
+    }
+
+    fn setattr(
+        &self,
+        state: &ServerState,
+        fid: u32,
+        attributes: &SetAttributes,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let path = &state.fid(fid)?.path;
+        let node = self.open_node(path)?;
+        let stat = fstat(&node)?;
+        let mapped = self.export.security_model == SecurityModel::MappedXattr;
+        let ignore_failures = self.export.security_model == SecurityModel::None;
+
+        if attributes.valid & P9_SETATTR_MODE != 0 {
+            let result = if mapped {
+                let current = self.apply_mapped(&node, stat)?;
+                set_xattr(
+                    &node,
+                    XATTR_MODE,
+                    &((current.st_mode & libc::S_IFMT) | (attributes.mode & 0o7777)).to_ne_bytes(),
+                )
+            } else if is_mode(stat.st_mode, libc::S_IFLNK) {
+                Ok(())
+            } else {
+                let path = proc_path(&node)?;
+                check(unsafe { libc::chmod(path.as_ptr(), attributes.mode & 0o7777) }).map(|_| ())
+            };
+            if !ignore_failures {
+                result?;
+            }
+        }
+
+        if attributes.valid & (P9_SETATTR_UID | P9_SETATTR_GID) != 0 {
+            let uid = (attributes.valid & P9_SETATTR_UID != 0).then_some(attributes.uid);
+            let gid = (attributes.valid & P9_SETATTR_GID != 0).then_some(attributes.gid);
+            let result = if mapped {
+                uid.map(|uid| set_xattr(&node, XATTR_UID, &uid.to_ne_bytes()))
+                    .transpose()
+                    .and_then(|_| {
+                        gid.map(|gid| set_xattr(&node, XATTR_GID, &gid.to_ne_bytes()))
+                            .transpose()
+                    })
+                    .map(|_| ())
+            } else {
+                chown(&node, uid.unwrap_or(u32::MAX), gid.unwrap_or(u32::MAX))
+            };
+            if !ignore_failures {
+                result?;
+            }
+        }
+
+        if attributes.valid & P9_SETATTR_SIZE != 0 {
+            if is_mode(stat.st_mode, libc::S_IFDIR) {
+                return Err(errno(libc::EISDIR));
+            }
+            if !is_mode(stat.st_mode, libc::S_IFREG) {
+                return Err(errno(libc::EINVAL));
+            }
+            let file = File::from(self.resolve(
+                path,
+                libc::O_WRONLY | libc::O_NOFOLLOW | libc::O_NONBLOCK,
+                0,
+            )?);
+            file.set_len(attributes.size)?;
+        }
+
+        if attributes.valid & (P9_SETATTR_ATIME | P9_SETATTR_MTIME) != 0 {
+            let times = [
+                timespec(
+                    attributes.valid & P9_SETATTR_ATIME != 0,
+                    attributes.valid & P9_SETATTR_ATIME_SET != 0,
+                    attributes.atime_sec,
+                    attributes.atime_nsec,
+                ),
+                timespec(
+                    attributes.valid & P9_SETATTR_MTIME != 0,
+                    attributes.valid & P9_SETATTR_MTIME_SET != 0,
+                    attributes.mtime_sec,
+                    attributes.mtime_nsec,
+                ),
+            ];
+            let (parent, name) = split_path(path)?;
+            let parent = self.open_dir(&parent)?;
+            check(unsafe {
+                libc::utimensat(
+                    parent.as_raw_fd(),
+                    name.as_ptr(),
+                    times.as_ptr(),
+                    libc::AT_SYMLINK_NOFOLLOW,
+                )
+            })?;
+        }
+        Ok(Response::Setattr)
+    }
+
+    fn readdir(
+        &self,
+        state: &mut ServerState,
+        fid: u32,
+        offset: u64,
+        count: u32,
+    ) -> io::Result<Response> {
+        let count = count.min(state.msize.saturating_sub(P9_IOHDR_SIZE)) as usize;
+        let path = {
+            let fid = state.fid(fid)?;
+            if fid.file.is_none() {
+                return Err(errno(libc::EBADF));
+            }
+            fid.path.clone()
+        };
+
+        // the directory is listed once when reading starts, and offsets index into that listing.
+        let fid = state.fid_mut(fid)?;
+        if offset == 0 || fid.entries.is_none() {
+            fid.entries = Some(self.list_directory(&path)?);
+        }
+        let listing = fid.entries.as_deref().unwrap_or(&[]);
+
+        let mut entries = Vec::new();
+        let mut size = 0;
+        for entry in listing.iter().skip(offset as usize) {
+            if size + entry.encoded_size() > count {
+                break;
+            }
+            size += entry.encoded_size();
+            entries.push(entry.clone());
+        }
+        Ok(Response::Readdir { entries })
+    }
+
+    fn list_directory(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let fd = self.resolve(path, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+        let dir = unsafe { libc::fdopendir(fd.into_raw_fd()) };
+        if dir.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut entries = Vec::new();
+        loop {
+            let entry = unsafe { libc::readdir(dir) };
+            if entry.is_null() {
+                break;
+            }
+            let entry = unsafe { &*entry };
+            let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+            let kind = match entry.d_type {
+                libc::DT_DIR => P9_QTDIR,
+                libc::DT_LNK => P9_QTSYMLINK,
+                _ => P9_QTFILE,
+            };
+            entries.push(DirEntry {
+                qid: Qid {
+                    kind,
+                    version: 0,
+                    path: entry.d_ino,
+                },
+                offset: entries.len() as u64 + 1,
+                kind: entry.d_type,
+                name: name.to_string_lossy().to_string(),
+            });
+        }
+        unsafe { libc::closedir(dir) };
+        Ok(entries)
+    }
+
+    fn fsync(&self, state: &ServerState, fid: u32, datasync: bool) -> io::Result<Response> {
+        let file = state
+            .fid(fid)?
+            .file
+            .as_ref()
+            .ok_or_else(|| errno(libc::EBADF))?;
+        if datasync {
+            file.sync_data()?;
+        } else {
+            file.sync_all()?;
+        }
+        Ok(Response::Fsync)
+    }
+
+    fn link(&self, state: &ServerState, dfid: u32, fid: u32, name: &str) -> io::Result<Response> {
+        self.check_writable()?;
+        let directory = &state.fid(dfid)?.path;
+        let (source_parent, source_name) = split_path(&state.fid(fid)?.path)?;
+        let name = validate_name(name)?;
+        let source_dir = self.open_dir(&source_parent)?;
+        let dir = self.open_dir(directory)?;
+        let cname = cstring(OsStr::new(name))?;
+        check(unsafe {
+            libc::linkat(
+                source_dir.as_raw_fd(),
+                source_name.as_ptr(),
+                dir.as_raw_fd(),
+                cname.as_ptr(),
+                0,
+            )
+        })?;
+        Ok(Response::Link)
+    }
+
+    fn mkdir(
+        &self,
+        state: &ServerState,
+        dfid: u32,
+        name: &str,
+        mode: u32,
+        gid: u32,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let fid = state.fid(dfid)?;
+        let name = validate_name(name)?;
+        let dir = self.open_dir(&fid.path)?;
+        let cname = cstring(OsStr::new(name))?;
+        let host_mode = match self.export.security_model {
+            SecurityModel::MappedXattr => MAPPED_DIR_MODE,
+            _ => mode & 0o7777,
+        };
+        check(unsafe { libc::mkdirat(dir.as_raw_fd(), cname.as_ptr(), host_mode) })?;
+        let path = fid.path.join(name);
+        self.apply_credentials(
+            &self.open_node(&path)?,
+            &Credentials {
+                uid: fid.uid,
+                gid,
+                mode: libc::S_IFDIR | (mode & 0o7777),
+                rdev: None,
+            },
+        )?;
+        let qid = self.qid(&self.stat(&path)?);
+        Ok(Response::Mkdir { qid })
+    }
+
+    fn unlinkat(
+        &self,
+        state: &ServerState,
+        dirfid: u32,
+        name: &str,
+        flags: u32,
+    ) -> io::Result<Response> {
+        self.check_writable()?;
+        let dir = self.open_dir(&state.fid(dirfid)?.path)?;
+        let cname = cstring(OsStr::new(validate_name(name)?))?;
+        let flags = if flags & P9_DOTL_AT_REMOVEDIR != 0 {
+            libc::AT_REMOVEDIR
+        } else {
+            0
+        };
+        check(unsafe { libc::unlinkat(dir.as_raw_fd(), cname.as_ptr(), flags) })?;
+        Ok(Response::Unlinkat)
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.export.read_only {
+            return Err(errno(libc::EROFS));
+        }
+        Ok(())
+    }
+
+    // opens a path relative to the export root, refusing to resolve outside of it.
+    fn resolve(&self, path: &Path, flags: c_int, mode: u32) -> io::Result<OwnedFd> {
+        let path = if path.as_os_str().is_empty() {
+            c".".to_owned()
+        } else {
+            cstring(path.as_os_str())?
+        };
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = (flags | libc::O_CLOEXEC) as u64;
+        how.mode = mode as u64;
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                self.root.as_raw_fd(),
+                path.as_ptr(),
+                &how as *const libc::open_how,
+                size_of::<libc::open_how>(),
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as c_int) })
+    }
+
+    fn open_node(&self, path: &Path) -> io::Result<OwnedFd> {
+        self.resolve(path, libc::O_PATH | libc::O_NOFOLLOW, 0)
+    }
+
+    fn open_dir(&self, path: &Path) -> io::Result<OwnedFd> {
+        self.resolve(path, libc::O_PATH | libc::O_DIRECTORY, 0)
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<libc::stat> {
+        self.attributes(&self.open_node(path)?)
+    }
+
+    fn attributes(&self, fd: &OwnedFd) -> io::Result<libc::stat> {
+        self.apply_mapped(fd, fstat(fd)?)
+    }
+
+    // overlays the credentials stored in xattrs under the mapped security model.
+    fn apply_mapped(&self, fd: &OwnedFd, mut stat: libc::stat) -> io::Result<libc::stat> {
+        if self.export.security_model != SecurityModel::MappedXattr
+            || is_mode(stat.st_mode, libc::S_IFLNK)
+        {
+            return Ok(stat);
+        }
+        if let Some(uid) = get_xattr::<4>(fd, XATTR_UID)? {
+            stat.st_uid = u32::from_ne_bytes(uid);
+        }
+        if let Some(gid) = get_xattr::<4>(fd, XATTR_GID)? {
+            stat.st_gid = u32::from_ne_bytes(gid);
+        }
+        if let Some(mode) = get_xattr::<4>(fd, XATTR_MODE)? {
+            let mode = u32::from_ne_bytes(mode);
+            stat.st_mode = if mode & libc::S_IFMT != 0 {
+                mode
+            } else {
+                (stat.st_mode & libc::S_IFMT) | mode
+            };
+        }
+        if let Some(rdev) = get_xattr::<8>(fd, XATTR_RDEV)? {
+            stat.st_rdev = u64::from_ne_bytes(rdev);
+        }
+        Ok(stat)
+    }
+
+    fn apply_credentials(&self, fd: &OwnedFd, credentials: &Credentials) -> io::Result<()> {
+        match self.export.security_model {
+            SecurityModel::Passthrough => chown(fd, credentials.uid, credentials.gid),
+
+            SecurityModel::None => {
+                if let Err(error) = chown(fd, credentials.uid, credentials.gid) {
+                    debug!("ignoring failure to set ownership: {}", error);
+                }
+                Ok(())
+            }
+
+            SecurityModel::MappedXattr => {
+                set_xattr(fd, XATTR_UID, &credentials.uid.to_ne_bytes())?;
+                set_xattr(fd, XATTR_GID, &credentials.gid.to_ne_bytes())?;
+                set_xattr(fd, XATTR_MODE, &credentials.mode.to_ne_bytes())?;
+                if let Some(rdev) = credentials.rdev {
+                    set_xattr(fd, XATTR_RDEV, &rdev.to_ne_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn create_mapped_file(&self, dir: &OwnedFd, name: &CStr) -> io::Result<OwnedFd> {
+        let fd = check(unsafe {
+            libc::openat(
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_WRONLY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                MAPPED_FILE_MODE,
+            )
+        })?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    fn qid(&self, stat: &libc::stat) -> Qid {
+        let kind = if is_mode(stat.st_mode, libc::S_IFDIR) {
+            P9_QTDIR
+        } else if is_mode(stat.st_mode, libc::S_IFLNK) {
+            P9_QTSYMLINK
+        } else {
+            P9_QTFILE
+        };
+        Qid {
+            kind,
+            version: 0,
+            path: stat.st_ino,
+        }
+    }
+}
+
+fn errno(code: c_int) -> io::Error {
+    io::Error::from_raw_os_error(code)
+}
+
+fn check(result: c_int) -> io::Result<c_int> {
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+fn cstring(value: &OsStr) -> io::Result<CString> {
+    CString::new(value.as_bytes()).map_err(|_| errno(libc::EINVAL))
+}
+
+fn is_mode(mode: u32, kind: u32) -> bool {
+    mode & libc::S_IFMT == kind
+}
+
+fn validate_name(name: &str) -> io::Result<&str> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\0']) {
+        return Err(errno(libc::EINVAL));
+    }
+    Ok(name)
+}
+
+// splits a path into its parent directory and final component,
+// where the export root is addressed as '.' within itself.
+fn split_path(path: &Path) -> io::Result<(PathBuf, CString)> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((parent.to_path_buf(), cstring(name)?)),
+        _ => Ok((PathBuf::new(), c".".to_owned())),
+    }
+}
+
+fn is_write_flags(flags: u32) -> bool {
+    matches!(flags & P9_DOTL_ACCMODE, P9_DOTL_WRONLY | P9_DOTL_RDWR)
+        || flags & (P9_DOTL_CREATE | P9_DOTL_TRUNC | P9_DOTL_APPEND) != 0
+}
+
+// translates 9P2000.L open flags, which use the x86 values, into host flags.
+fn open_flags(flags: u32) -> c_int {
+    let mut result = match flags & P9_DOTL_ACCMODE {
+        P9_DOTL_WRONLY => libc::O_WRONLY,
+        P9_DOTL_RDWR => libc::O_RDWR,
+        _ => libc::O_RDONLY,
+    };
+    for (flag, host) in [
+        (P9_DOTL_TRUNC, libc::O_TRUNC),
+        (P9_DOTL_APPEND, libc::O_APPEND),
+        (P9_DOTL_NONBLOCK, libc::O_NONBLOCK),
+        (P9_DOTL_DSYNC, libc::O_DSYNC),
+        (P9_DOTL_DIRECTORY, libc::O_DIRECTORY),
+        (P9_DOTL_NOATIME, libc::O_NOATIME),
+        (P9_DOTL_SYNC, libc::O_SYNC),
+    ] {
+        if flags & flag != 0 {
+            result |= host;
+        }
+    }
+    result
+}
+
+fn fstat(fd: &OwnedFd) -> io::Result<libc::stat> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    check(unsafe {
+        libc::fstatat(
+            fd.as_raw_fd(),
+            c"".as_ptr(),
+            stat.as_mut_ptr(),
+            libc::AT_EMPTY_PATH | libc::AT_SYMLINK_NOFOLLOW,
+        )
+    })?;
+    Ok(unsafe { stat.assume_init() })
+}
+
+fn chown(fd: &OwnedFd, uid: u32, gid: u32) -> io::Result<()> {
+    check(unsafe {
+        libc::fchownat(
+            fd.as_raw_fd(),
+            c"".as_ptr(),
+            uid,
+            gid,
+            libc::AT_EMPTY_PATH | libc::AT_SYMLINK_NOFOLLOW,
+        )
+    })?;
+    Ok(())
+}
+
+// O_PATH descriptors can't be used with the f* syscalls, but their /proc
+// entry names exactly the inode that was opened.
+fn proc_path(fd: &OwnedFd) -> io::Result<CString> {
+    cstring(OsStr::new(&format!("/proc/self/fd/{}", fd.as_raw_fd())))
+}
+
+fn set_xattr(fd: &OwnedFd, name: &CStr, value: &[u8]) -> io::Result<()> {
+    let path = proc_path(fd)?;
+    check(unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+fn get_xattr<const N: usize>(fd: &OwnedFd, name: &CStr) -> io::Result<Option<[u8; N]>> {
+    let path = proc_path(fd)?;
+    let mut value = [0u8; N];
+    let size = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_mut_ptr() as *mut libc::c_void,
+            N,
+        )
+    };
+    if size < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => Ok(None),
+            _ => Err(error),
+        };
+    }
+    if size as usize != N {
+        return Ok(None);
+    }
+    Ok(Some(value))
+}
+
+fn timespec(set: bool, explicit: bool, sec: u64, nsec: u64) -> libc::timespec {
+    libc::timespec {
+        tv_sec: if explicit { sec as libc::time_t } else { 0 },
+        tv_nsec: match (set, explicit) {
+            (false, _) => libc::UTIME_OMIT,
+            (true, false) => libc::UTIME_NOW,
+            (true, true) => nsec as libc::c_long,
+        },
+    }
+}
@@ -1,3 +1,6 @@
+use std::env::args;
+
+use xencall::console::HypervisorConsole;
 use xencall::error::Result;
 use xencall::XenCall;
 
@@ -5,14 +8,12 @@ use xencall::XenCall;
 async fn main() -> Result<()> {
     env_logger::init();
 
+    let follow = args().any(|arg| arg == "-f");
     let call = XenCall::open(0)?;
-    let index = 0_u32;
-    let (buf, newindex) = call.read_console_ring_raw(false, index).await?;
-
-    match std::str::from_utf8(&buf[..newindex as usize]) {
-        Ok(v) => print!("{}", v),
-        _ => panic!("unable to decode Xen console messages"),
-    };
-
+    let mut console = HypervisorConsole::new(call);
+    console.follow(follow);
+    while let Some(line) = console.next_line().await? {
+        println!("{:?} {:?}: {}", line.level, line.timestamp, line.message);
+    }
     Ok(())
 }
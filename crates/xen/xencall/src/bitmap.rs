@@ -0,0 +1,186 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::{Error, Result};
+
+// bounds parsed ids, so that a typo can not ask for a map of gigabytes.
+const BITMAP_MAX_ID: u32 = u16::MAX as u32;
+
+// a set of ids in the layout of struct xenctl_bitmap, where bit n is set in
+// byte n / 8 when id n is present.
+macro_rules! xen_bitmap {
+    ($name:ident) => {
+        #[derive(Clone, Debug, Default)]
+        pub struct $name {
+            bytes: Vec<u8>,
+        }
+
+        impl $name {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            // a map that has room for nr_bits ids, as the hypervisor expects when filling it.
+            pub fn with_capacity(nr_bits: u32) -> Self {
+                $name {
+                    bytes: vec![0u8; nr_bits.div_ceil(8) as usize],
+                }
+            }
+
+            pub fn insert(&mut self, id: u32) -> &mut Self {
+                let byte = (id / 8) as usize;
+                if byte >= self.bytes.len() {
+                    self.bytes.resize(byte + 1, 0);
+                }
+                self.bytes[byte] |= 1 << (id % 8);
+                self
+            }
+
+            pub fn remove(&mut self, id: u32) -> &mut Self {
+                if let Some(byte) = self.bytes.get_mut((id / 8) as usize) {
+                    *byte &= !(1 << (id % 8));
+                }
+                self
+            }
+
+            pub fn contains(&self, id: u32) -> bool {
+                self.bytes
+                    .get((id / 8) as usize)
+                    .is_some_and(|byte| byte & (1 << (id % 8)) != 0)
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.bytes.iter().all(|byte| *byte == 0)
+            }
+
+            pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+                (0..self.nr_bits()).filter(|id| self.contains(*id))
+            }
+
+            pub fn nr_bits(&self) -> u32 {
+                (self.bytes.len() * 8) as u32
+            }
+
+            pub(crate) fn bytes_mut(&mut self) -> &mut Vec<u8> {
+                &mut self.bytes
+            }
+        }
+
+        // trailing empty bytes do not make two maps different.
+        impl PartialEq for $name {
+            fn eq(&self, other: &Self) -> bool {
+                self.iter().eq(other.iter())
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl FromIterator<u32> for $name {
+            fn from_iter<T: IntoIterator<Item = u32>>(iter: T) -> Self {
+                let mut map = $name::new();
+                for id in iter {
+                    map.insert(id);
+                }
+                map
+            }
+        }
+
+        // parses lists like "0-3,8,10-11", as taken by xl.
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(value: &str) -> Result<Self> {
+                let invalid = || Error::InvalidBitmap(value.to_string());
+                let mut map = $name::new();
+                for part in value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|part| !part.is_empty())
+                {
+                    let (start, end) = match part.split_once('-') {
+                        Some((start, end)) => (start.trim(), end.trim()),
+                        None => (part, part),
+                    };
+                    let start = u32::from_str(start).map_err(|_| invalid())?;
+                    let end = u32::from_str(end).map_err(|_| invalid())?;
+                    if start > end || end > BITMAP_MAX_ID {
+                        return Err(invalid());
+                    }
+                    for id in start..=end {
+                        map.insert(id);
+                    }
+                }
+                Ok(map)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut ids = self.iter().peekable();
+                let mut first = true;
+                while let Some(start) = ids.next() {
+                    let mut end = start;
+                    while ids.peek() == Some(&(end + 1)) {
+                        end = ids.next().unwrap_or(end);
+                    }
+                    if !first {
+                        f.write_str(",")?;
+                    }
+                    first = false;
+                    if start == end {
+                        write!(f, "{}", start)?;
+                    } else {
+                        write!(f, "{}-{}", start, end)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+xen_bitmap!(CpuMap);
+xen_bitmap!(NodeMap);
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{CpuMap, NodeMap};
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        let map = CpuMap::from_str("0-3,8,10-11").unwrap();
+        assert_eq!(map.iter().collect::<Vec<_>>(), [0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(map.to_string(), "0-3,8,10-11");
+        assert_eq!(CpuMap::from_str(&map.to_string()).unwrap(), map);
+    }
+
+    #[test]
+    fn parse_rejects_bad_ranges() {
+        assert!(CpuMap::from_str("3-1").is_err());
+        assert!(CpuMap::from_str("a").is_err());
+        assert!(CpuMap::from_str("1-").is_err());
+        assert!(CpuMap::from_str("1 2").is_err());
+        assert!(CpuMap::from_str("65535").is_ok());
+        assert!(CpuMap::from_str("65536").is_err());
+        assert!(NodeMap::from_str("0-4294967295").is_err());
+    }
+
+    #[test]
+    fn parse_ignores_whitespace_and_empty_parts() {
+        let map = CpuMap::from_str(" 0 - 1 , 3 ,").unwrap();
+        assert_eq!(map.to_string(), "0-1,3");
+        assert!(CpuMap::from_str("").unwrap().is_empty());
+        assert!(CpuMap::from_str("  ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn equality_ignores_trailing_empty_bytes() {
+        let mut map = CpuMap::with_capacity(64);
+        map.insert(2);
+        assert_eq!(map, [2].into_iter().collect::<CpuMap>());
+        map.remove(2);
+        assert_eq!(map, CpuMap::new());
+        assert_ne!(map, [0].into_iter().collect::<CpuMap>());
+    }
+}
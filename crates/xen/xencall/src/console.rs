@@ -0,0 +1,344 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use log::warn;
+use tokio_stream::Stream;
+
+use crate::{error::Result, XenCall, CONSOLE_RING_READ_SIZE};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+type ConsoleRead = Pin<Box<dyn Future<Output = Result<(Vec<u8>, u32)>> + Send>>;
+
+// the levels printk prefixes messages with, see xen/include/xen/lib.h.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HypervisorLogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+    Guest,
+}
+
+impl HypervisorLogLevel {
+    fn from_prefix(prefix: &str) -> Option<HypervisorLogLevel> {
+        match prefix {
+            "0" => Some(HypervisorLogLevel::Error),
+            "1" => Some(HypervisorLogLevel::Warning),
+            "2" => Some(HypervisorLogLevel::Info),
+            "3" => Some(HypervisorLogLevel::Debug),
+            "G" => Some(HypervisorLogLevel::Guest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HypervisorConsoleSource {
+    Xen,
+    Domain(u32),
+}
+
+// one line of the hypervisor console, such as "(XEN) [   1.000000] <1>message".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HypervisorConsoleLine {
+    pub source: Option<HypervisorConsoleSource>,
+    pub timestamp: Option<String>,
+    pub level: Option<HypervisorLogLevel>,
+    pub message: String,
+}
+
+impl HypervisorConsoleLine {
+    pub fn parse(line: &str) -> HypervisorConsoleLine {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+
+        let mut source = None;
+        if let Some((prefix, remaining)) = bracketed(rest, '(', ')') {
+            let parsed = match prefix {
+                "XEN" => Some(HypervisorConsoleSource::Xen),
+                _ => prefix
+                    .strip_prefix('d')
+                    .and_then(|domid| domid.parse().ok())
+                    .map(HypervisorConsoleSource::Domain),
+            };
+            if parsed.is_some() {
+                source = parsed;
+                rest = remaining.strip_prefix(' ').unwrap_or(remaining);
+            }
+        }
+
+        let mut timestamp = None;
+        if let Some((value, remaining)) = bracketed(rest, '[', ']') {
+            timestamp = Some(value.trim().to_string());
+            rest = remaining.strip_prefix(' ').unwrap_or(remaining);
+        }
+
+        // a guest message carries <G> ahead of its own level, the last one wins.
+        let mut level = None;
+        while let Some(parsed) = bracketed(rest, '<', '>').and_then(|(prefix, remaining)| {
+            HypervisorLogLevel::from_prefix(prefix).map(|level| (level, remaining))
+        }) {
+            level = Some(parsed.0);
+            rest = parsed.1;
+        }
+
+        HypervisorConsoleLine {
+            source,
+            timestamp,
+            level,
+            message: rest.to_string(),
+        }
+    }
+}
+
+fn bracketed(value: &str, open: char, close: char) -> Option<(&str, &str)> {
+    let value = value.strip_prefix(open)?;
+    let end = value.find(close)?;
+    Some((&value[..end], &value[end + close.len_utf8()..]))
+}
+
+// follows the hypervisor console ring like 'xl dmesg -f', yielding a line at a
+// time. the ring is polled, as xen does not signal new messages to dom0.
+pub struct HypervisorConsole {
+    call: XenCall,
+    index: u32,
+    clear: bool,
+    follow: bool,
+    poll_interval: Duration,
+    started: bool,
+    idle: bool,
+    finished: bool,
+    lines: ConsoleLines,
+    read: Option<ConsoleRead>,
+}
+
+impl HypervisorConsole {
+    pub fn new(call: XenCall) -> HypervisorConsole {
+        HypervisorConsole {
+            call,
+            index: 0,
+            clear: false,
+            follow: true,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            started: false,
+            idle: false,
+            finished: false,
+            lines: ConsoleLines::default(),
+            read: None,
+        }
+    }
+
+    // clears what has been read from the ring, like 'xl dmesg -c'.
+    pub fn clear(&mut self, clear: bool) -> &mut Self {
+        self.clear = clear;
+        self
+    }
+
+    // keeps waiting for new messages instead of ending once the ring is drained.
+    pub fn follow(&mut self, follow: bool) -> &mut Self {
+        self.follow = follow;
+        self
+    }
+
+    pub fn poll_interval(&mut self, poll_interval: Duration) -> &mut Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    // starts at an index returned by a previous reader, instead of the oldest message.
+    pub fn index(&mut self, index: u32) -> &mut Self {
+        self.index = index;
+        self.started = true;
+        self
+    }
+
+    pub fn current_index(&self) -> u32 {
+        self.index
+    }
+
+    pub async fn next_line(&mut self) -> Result<Option<HypervisorConsoleLine>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+            .await
+            .transpose()
+    }
+
+    fn receive(&mut self, data: Vec<u8>, index: u32) {
+        // xen restarts from the oldest message when ours was overwritten or cleared.
+        let start = index.wrapping_sub(data.len() as u32);
+        if self.started && start != self.index {
+            warn!(
+                "hypervisor console ring skipped {} bytes",
+                start.wrapping_sub(self.index)
+            );
+        }
+        self.started = true;
+        self.index = index;
+        self.lines.receive(&data);
+    }
+
+    fn finish(&mut self) {
+        self.lines.finish();
+        self.finished = true;
+    }
+}
+
+// splits console data into lines, holding back a line until its newline arrives.
+#[derive(Default)]
+struct ConsoleLines {
+    partial: Vec<u8>,
+    lines: VecDeque<HypervisorConsoleLine>,
+}
+
+impl ConsoleLines {
+    fn receive(&mut self, data: &[u8]) {
+        self.partial.extend_from_slice(data);
+        let Some(end) = self.partial.iter().rposition(|byte| *byte == b'\n') else {
+            return;
+        };
+        let complete: Vec<u8> = self.partial.drain(..=end).collect();
+        for line in String::from_utf8_lossy(&complete).lines() {
+            self.lines.push_back(HypervisorConsoleLine::parse(line));
+        }
+    }
+
+    fn finish(&mut self) {
+        if !self.partial.is_empty() {
+            let line = String::from_utf8_lossy(&self.partial).into_owned();
+            self.lines.push_back(HypervisorConsoleLine::parse(&line));
+            self.partial.clear();
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<HypervisorConsoleLine> {
+        self.lines.pop_front()
+    }
+}
+
+// clearing the ring also drops whatever did not fit in the buffer, so the
+// ring is read without clearing until a read comes back short.
+async fn read_ring(call: XenCall, clear: bool, mut index: u32) -> Result<(Vec<u8>, u32)> {
+    let mut data = Vec::new();
+    if clear {
+        loop {
+            let (chunk, next) = call.read_console_ring(false, index).await?;
+            let full = chunk.len() == CONSOLE_RING_READ_SIZE;
+            data.extend_from_slice(&chunk);
+            index = next;
+            if !full {
+                break;
+            }
+        }
+    }
+    let (chunk, next) = call.read_console_ring(clear, index).await?;
+    data.extend_from_slice(&chunk);
+    Ok((data, next))
+}
+
+impl Stream for HypervisorConsole {
+    type Item = Result<HypervisorConsoleLine>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(line) = this.lines.pop_front() {
+                return Poll::Ready(Some(Ok(line)));
+            }
+            if this.finished {
+                return Poll::Ready(None);
+            }
+
+            let call = this.call.clone();
+            let (clear, index) = (this.clear, this.index);
+            let delay = this.idle.then_some(this.poll_interval);
+            let read = this.read.get_or_insert_with(|| {
+                Box::pin(async move {
+                    if let Some(delay) = delay {
+                        tokio::time::sleep(delay).await;
+                    }
+                    read_ring(call, clear, index).await
+                })
+            });
+            let result = match read.as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.read = None;
+            match result {
+                Ok((data, index)) => {
+                    this.idle = data.is_empty();
+                    if this.idle && !this.follow {
+                        this.finish();
+                    } else {
+                        this.receive(data, index);
+                    }
+                }
+                Err(error) => return Poll::Ready(Some(Err(error))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConsoleLines, HypervisorConsoleLine, HypervisorConsoleSource, HypervisorLogLevel};
+
+    #[test]
+    fn parses_xen_lines() {
+        let line = HypervisorConsoleLine::parse("(XEN) [   1.000000] <1>bad thing\n");
+        assert_eq!(line.source, Some(HypervisorConsoleSource::Xen));
+        assert_eq!(line.timestamp.as_deref(), Some("1.000000"));
+        assert_eq!(line.level, Some(HypervisorLogLevel::Warning));
+        assert_eq!(line.message, "bad thing");
+
+        let line = HypervisorConsoleLine::parse("(d3) <G><0>guest oops");
+        assert_eq!(line.source, Some(HypervisorConsoleSource::Domain(3)));
+        assert_eq!(line.level, Some(HypervisorLogLevel::Error));
+        assert_eq!(line.message, "guest oops");
+    }
+
+    #[test]
+    fn parses_lines_without_timestamp_or_level() {
+        let line = HypervisorConsoleLine::parse("(XEN) Xen version 4.18");
+        assert_eq!(line.source, Some(HypervisorConsoleSource::Xen));
+        assert_eq!(line.timestamp, None);
+        assert_eq!(line.level, None);
+        assert_eq!(line.message, "Xen version 4.18");
+
+        // an unknown level stays part of the message.
+        let line = HypervisorConsoleLine::parse("(XEN) <x>message");
+        assert_eq!(line.level, None);
+        assert_eq!(line.message, "<x>message");
+    }
+
+    #[test]
+    fn keeps_other_lines_as_they_are() {
+        let line = HypervisorConsoleLine::parse(" __  __            _  _    _  ___  ");
+        assert_eq!(line.source, None);
+        assert_eq!(line.message, " __  __            _  _    _  ___  ");
+
+        let line = HypervisorConsoleLine::parse("(dx) not a domain");
+        assert_eq!(line.source, None);
+        assert_eq!(line.message, "(dx) not a domain");
+    }
+
+    #[test]
+    fn carries_partial_lines_across_reads() {
+        let mut lines = ConsoleLines::default();
+        lines.receive(b"(XEN) first\n(XEN) sec");
+        assert_eq!(lines.pop_front().unwrap().message, "first");
+        assert!(lines.pop_front().is_none());
+
+        lines.receive(b"ond\n(XEN) third");
+        assert_eq!(lines.pop_front().unwrap().message, "second");
+        assert!(lines.pop_front().is_none());
+
+        lines.finish();
+        assert_eq!(lines.pop_front().unwrap().message, "third");
+        assert!(lines.pop_front().is_none());
+    }
+}
@@ -18,6 +18,10 @@ pub enum Error {
     MmapBatchFailed(nix::errno::Errno),
     #[error("specified value is too long")]
     ValueTooLong,
+    #[error("invalid vnuma layout: {0}")]
+    InvalidVnuma(&'static str),
+    #[error("invalid bitmap: {0}")]
+    InvalidBitmap(String),
     #[error("failed to join async task: {0}")]
     JoinError(JoinError),
 }
@@ -1,23 +1,32 @@
+pub mod bitmap;
+pub mod console;
 pub mod error;
 pub mod sys;
 
+use crate::bitmap::{CpuMap, NodeMap};
 use crate::error::{Error, Result};
 use crate::sys::{
     AddToPhysmap, AddressSize, AssignDevice, CreateDomain, DomCtl, DomCtlValue, DomCtlVcpuContext,
-    EvtChnAllocUnbound, GetDomainInfo, GetPageFrameInfo3, HvmContext, HvmParam, Hypercall,
-    HypercallInit, IoMemPermission, IoPortPermission, IrqPermission, MaxMem, MaxVcpus, MemoryMap,
-    MemoryReservation, MmapBatch, MmapResource, MmuExtOp, MultiCallEntry, PagingMempool,
+    EvtChnAllocUnbound, GetDeviceGroup, GetDomainInfo, GetPageFrameInfo3, HvmContext, HvmParam,
+    Hypercall, HypercallInit, IoMemPermission, IoPortPermission, IrqPermission, MaxMem, MaxVcpus,
+    MemoryMap, MemoryReservation, MmapBatch, MmapResource, MmuExtOp, MultiCallEntry, PagingMempool,
     PciAssignDevice, XenCapabilitiesInfo, DOMCTL_DEV_PCI, HYPERVISOR_DOMCTL,
     HYPERVISOR_EVENT_CHANNEL_OP, HYPERVISOR_HVM_OP, HYPERVISOR_MEMORY_OP, HYPERVISOR_MMUEXT_OP,
     HYPERVISOR_MULTICALL, HYPERVISOR_XEN_VERSION, XENVER_CAPABILITIES, XEN_DOMCTL_ASSIGN_DEVICE,
     XEN_DOMCTL_CREATEDOMAIN, XEN_DOMCTL_CREATE_DOMAIN2_INTERFACE_THRESHOLD,
-    XEN_DOMCTL_DESTROYDOMAIN, XEN_DOMCTL_GETDOMAININFO, XEN_DOMCTL_GETHVMCONTEXT,
-    XEN_DOMCTL_GETPAGEFRAMEINFO3, XEN_DOMCTL_HYPERCALL_INIT, XEN_DOMCTL_IOMEM_PERMISSION,
-    XEN_DOMCTL_IOPORT_PERMISSION, XEN_DOMCTL_IRQ_PERMISSION, XEN_DOMCTL_MAX_MEM,
-    XEN_DOMCTL_MAX_VCPUS, XEN_DOMCTL_PAUSEDOMAIN, XEN_DOMCTL_SETHVMCONTEXT,
-    XEN_DOMCTL_SETVCPUCONTEXT, XEN_DOMCTL_SET_ADDRESS_SIZE, XEN_DOMCTL_SET_PAGING_MEMPOOL_SIZE,
-    XEN_DOMCTL_UNPAUSEDOMAIN, XEN_MEM_ADD_TO_PHYSMAP, XEN_MEM_CLAIM_PAGES, XEN_MEM_MEMORY_MAP,
-    XEN_MEM_POPULATE_PHYSMAP,
+    XEN_DOMCTL_DEASSIGN_DEVICE, XEN_DOMCTL_DESTROYDOMAIN, XEN_DOMCTL_GETDOMAININFO,
+    XEN_DOMCTL_GETHVMCONTEXT, XEN_DOMCTL_GETPAGEFRAMEINFO3, XEN_DOMCTL_GET_DEVICE_GROUP,
+    XEN_DOMCTL_HYPERCALL_INIT, XEN_DOMCTL_IOMEM_PERMISSION, XEN_DOMCTL_IOPORT_PERMISSION,
+    XEN_DOMCTL_IRQ_PERMISSION, XEN_DOMCTL_MAX_MEM, XEN_DOMCTL_MAX_VCPUS, XEN_DOMCTL_PAUSEDOMAIN,
+    XEN_DOMCTL_SETHVMCONTEXT, XEN_DOMCTL_SETVCPUCONTEXT, XEN_DOMCTL_SET_ADDRESS_SIZE,
+    XEN_DOMCTL_SET_PAGING_MEMPOOL_SIZE, XEN_DOMCTL_UNPAUSEDOMAIN, XEN_MEM_ADD_TO_PHYSMAP,
+    XEN_MEM_CLAIM_PAGES, XEN_MEM_MEMORY_MAP, XEN_MEM_POPULATE_PHYSMAP,
+};
+use crate::sys::{
+    NodeAffinity, NumaNodeInfo, SysctlMeminfo, SysctlNumainfo, VcpuAffinity, VmemRange, Vnuma,
+    XenctlBitmap, XEN_DOMCTL_GETNODEAFFINITY, XEN_DOMCTL_GETVCPUAFFINITY,
+    XEN_DOMCTL_SETNODEAFFINITY, XEN_DOMCTL_SETVCPUAFFINITY, XEN_DOMCTL_SETVNUMAINFO,
+    XEN_SYSCTL_NUMAINFO, XEN_VCPUAFFINITY_HARD, XEN_VCPUAFFINITY_SOFT,
 };
 use libc::{c_int, mmap, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
 use log::trace;
@@ -26,14 +35,15 @@ use std::ffi::{c_long, c_uint, c_ulong, c_void};
 use std::sync::Arc;
 use std::time::Duration;
 use sys::{
-    CpuId, E820Entry, ForeignMemoryMap, PhysdevMapPirq, SetDomainHandle, Sysctl, SysctlCputopo,
-    SysctlCputopoinfo, SysctlPhysinfo, SysctlPmOp, SysctlPmOpValue, SysctlReadconsole,
-    SysctlSetCpuFreqGov, SysctlValue, VcpuGuestContextAny, HYPERVISOR_PHYSDEV_OP,
-    HYPERVISOR_SYSCTL, PHYSDEVOP_MAP_PIRQ, XEN_DOMCTL_MAX_INTERFACE_VERSION,
-    XEN_DOMCTL_MIN_INTERFACE_VERSION, XEN_DOMCTL_SETDOMAINHANDLE, XEN_MEM_SET_MEMORY_MAP,
-    XEN_SYSCTL_CPUTOPOINFO, XEN_SYSCTL_MAX_INTERFACE_VERSION, XEN_SYSCTL_MIN_INTERFACE_VERSION,
-    XEN_SYSCTL_PHYSINFO, XEN_SYSCTL_PM_OP, XEN_SYSCTL_PM_OP_DISABLE_TURBO,
-    XEN_SYSCTL_PM_OP_ENABLE_TURBO, XEN_SYSCTL_PM_OP_SET_CPUFREQ_GOV, XEN_SYSCTL_READCONSOLE,
+    CpuId, E820Entry, ForeignMemoryMap, PhysdevMapPirq, PhysdevUnmapPirq, SetDomainHandle, Sysctl,
+    SysctlCputopo, SysctlCputopoinfo, SysctlGetDomainInfoList, SysctlPhysinfo, SysctlPmOp,
+    SysctlPmOpValue, SysctlReadconsole, SysctlSetCpuFreqGov, SysctlValue, VcpuGuestContextAny,
+    HYPERVISOR_PHYSDEV_OP, HYPERVISOR_SYSCTL, PHYSDEVOP_MAP_PIRQ, PHYSDEVOP_UNMAP_PIRQ,
+    XEN_DOMCTL_MAX_INTERFACE_VERSION, XEN_DOMCTL_MIN_INTERFACE_VERSION, XEN_DOMCTL_SETDOMAINHANDLE,
+    XEN_MEM_SET_MEMORY_MAP, XEN_SYSCTL_CPUTOPOINFO, XEN_SYSCTL_GETDOMAININFOLIST,
+    XEN_SYSCTL_MAX_INTERFACE_VERSION, XEN_SYSCTL_MIN_INTERFACE_VERSION, XEN_SYSCTL_PHYSINFO,
+    XEN_SYSCTL_PM_OP, XEN_SYSCTL_PM_OP_DISABLE_TURBO, XEN_SYSCTL_PM_OP_ENABLE_TURBO,
+    XEN_SYSCTL_PM_OP_SET_CPUFREQ_GOV, XEN_SYSCTL_READCONSOLE,
 };
 use tokio::time::sleep;
 
@@ -42,6 +52,8 @@ use std::os::fd::AsRawFd;
 use std::ptr::{addr_of_mut, null_mut};
 use std::slice;
 
+pub(crate) const CONSOLE_RING_READ_SIZE: usize = 16384;
+
 #[derive(Clone)]
 pub struct XenCall {
     pub handle: Arc<File>,
@@ -417,6 +429,38 @@ impl XenCall {
         Ok(unsafe { domctl.value.get_domain_info })
     }
 
+    pub async fn get_domain_info_list(
+        &self,
+        first_domain: u32,
+        max_domains: u32,
+    ) -> Result<Vec<GetDomainInfo>> {
+        trace!(
+            "sysctl fd={} get_domain_info_list first_domain={} max_domains={}",
+            self.handle.as_raw_fd(),
+            first_domain,
+            max_domains
+        );
+        let mut domains = vec![GetDomainInfo::default(); max_domains as usize];
+        let mut sysctl = Sysctl {
+            cmd: XEN_SYSCTL_GETDOMAININFOLIST,
+            interface_version: self.sysctl_interface_version,
+            value: SysctlValue {
+                get_domain_info_list: SysctlGetDomainInfoList {
+                    first_domain: first_domain as u16,
+                    pad: 0,
+                    max_domains,
+                    buffer: domains.as_mut_ptr() as c_ulong,
+                    num_domains: 0,
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_SYSCTL, addr_of_mut!(sysctl) as c_ulong)
+            .await?;
+        let num_domains = unsafe { sysctl.value.get_domain_info_list.num_domains };
+        domains.truncate(num_domains as usize);
+        Ok(domains)
+    }
+
     pub async fn create_domain(&self, create_domain: CreateDomain) -> Result<u32> {
         trace!(
             "domctl fd={} create_domain create_domain={:?}",
@@ -515,6 +559,185 @@ impl XenCall {
         Ok(())
     }
 
+    // pins a vcpu to the hard map and prefers the soft map, leaving whichever is None as it is.
+    pub async fn set_vcpu_affinity(
+        &self,
+        domid: u32,
+        vcpu: u32,
+        hard: Option<&CpuMap>,
+        soft: Option<&CpuMap>,
+    ) -> Result<()> {
+        trace!(
+            "domctl fd={} set_vcpu_affinity domid={} vcpu={} hard={:?} soft={:?}",
+            self.handle.as_raw_fd(),
+            domid,
+            vcpu,
+            hard.map(|map| map.to_string()),
+            soft.map(|map| map.to_string())
+        );
+        let mut flags = 0;
+        if hard.is_some() {
+            flags |= XEN_VCPUAFFINITY_HARD;
+        }
+        if soft.is_some() {
+            flags |= XEN_VCPUAFFINITY_SOFT;
+        }
+        if flags == 0 {
+            return Ok(());
+        }
+        let mut hard = hard.cloned().unwrap_or_default();
+        let mut soft = soft.cloned().unwrap_or_default();
+        let mut domctl = DomCtl {
+            cmd: XEN_DOMCTL_SETVCPUAFFINITY,
+            interface_version: self.domctl_interface_version,
+            domid,
+            value: DomCtlValue {
+                vcpu_affinity: VcpuAffinity {
+                    vcpu,
+                    flags,
+                    cpumap_hard: xenctl_bitmap(hard.bytes_mut()),
+                    cpumap_soft: xenctl_bitmap(soft.bytes_mut()),
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_DOMCTL, addr_of_mut!(domctl) as c_ulong)
+            .await?;
+        Ok(())
+    }
+
+    // returns the hard and soft affinity of a vcpu.
+    pub async fn get_vcpu_affinity(&self, domid: u32, vcpu: u32) -> Result<(CpuMap, CpuMap)> {
+        trace!(
+            "domctl fd={} get_vcpu_affinity domid={} vcpu={}",
+            self.handle.as_raw_fd(),
+            domid,
+            vcpu
+        );
+        let nr_cpus = self.phys_info().await?.max_cpu_id + 1;
+        let mut hard = CpuMap::with_capacity(nr_cpus);
+        let mut soft = CpuMap::with_capacity(nr_cpus);
+        let mut domctl = DomCtl {
+            cmd: XEN_DOMCTL_GETVCPUAFFINITY,
+            interface_version: self.domctl_interface_version,
+            domid,
+            value: DomCtlValue {
+                vcpu_affinity: VcpuAffinity {
+                    vcpu,
+                    flags: XEN_VCPUAFFINITY_HARD | XEN_VCPUAFFINITY_SOFT,
+                    cpumap_hard: xenctl_bitmap(hard.bytes_mut()),
+                    cpumap_soft: xenctl_bitmap(soft.bytes_mut()),
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_DOMCTL, addr_of_mut!(domctl) as c_ulong)
+            .await?;
+        Ok((hard, soft))
+    }
+
+    pub async fn set_node_affinity(&self, domid: u32, nodes: &NodeMap) -> Result<()> {
+        trace!(
+            "domctl fd={} set_node_affinity domid={} nodes={}",
+            self.handle.as_raw_fd(),
+            domid,
+            nodes
+        );
+        let mut nodes = nodes.clone();
+        let mut domctl = DomCtl {
+            cmd: XEN_DOMCTL_SETNODEAFFINITY,
+            interface_version: self.domctl_interface_version,
+            domid,
+            value: DomCtlValue {
+                node_affinity: NodeAffinity {
+                    nodemap: xenctl_bitmap(nodes.bytes_mut()),
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_DOMCTL, addr_of_mut!(domctl) as c_ulong)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_node_affinity(&self, domid: u32) -> Result<NodeMap> {
+        trace!(
+            "domctl fd={} get_node_affinity domid={}",
+            self.handle.as_raw_fd(),
+            domid
+        );
+        let nr_nodes = self.phys_info().await?.max_node_id + 1;
+        let mut nodes = NodeMap::with_capacity(nr_nodes);
+        let mut domctl = DomCtl {
+            cmd: XEN_DOMCTL_GETNODEAFFINITY,
+            interface_version: self.domctl_interface_version,
+            domid,
+            value: DomCtlValue {
+                node_affinity: NodeAffinity {
+                    nodemap: xenctl_bitmap(nodes.bytes_mut()),
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_DOMCTL, addr_of_mut!(domctl) as c_ulong)
+            .await?;
+        Ok(nodes)
+    }
+
+    // describes the virtual numa nodes of a domain. vdistance holds the distance
+    // between every pair of vnodes and vmemranges carry the vnode in nid.
+    pub async fn set_vnuma_info(
+        &self,
+        domid: u32,
+        vdistance: &[u32],
+        vcpu_to_vnode: &[u32],
+        vnode_to_pnode: &[u32],
+        vmemranges: &[VmemRange],
+    ) -> Result<()> {
+        trace!(
+            "domctl fd={} set_vnuma_info domid={} vdistance={:?} vcpu_to_vnode={:?} vnode_to_pnode={:?} vmemranges={:?}",
+            self.handle.as_raw_fd(),
+            domid,
+            vdistance,
+            vcpu_to_vnode,
+            vnode_to_pnode,
+            vmemranges
+        );
+        let nr_vnodes = vnode_to_pnode.len();
+        if vdistance.len() != nr_vnodes * nr_vnodes {
+            return Err(Error::InvalidVnuma(
+                "vdistance must cover every pair of vnodes",
+            ));
+        }
+        if vcpu_to_vnode
+            .iter()
+            .chain(vmemranges.iter().map(|range| &range.nid))
+            .any(|vnode| *vnode as usize >= nr_vnodes)
+        {
+            return Err(Error::InvalidVnuma("vnode out of range"));
+        }
+        let mut vdistance = vdistance.to_vec();
+        let mut vcpu_to_vnode = vcpu_to_vnode.to_vec();
+        let mut vnode_to_pnode = vnode_to_pnode.to_vec();
+        let mut vmemranges = vmemranges.to_vec();
+        let mut domctl = DomCtl {
+            cmd: XEN_DOMCTL_SETVNUMAINFO,
+            interface_version: self.domctl_interface_version,
+            domid,
+            value: DomCtlValue {
+                vnuma: Vnuma {
+                    nr_vnodes: nr_vnodes as u32,
+                    nr_vmemranges: vmemranges.len() as u32,
+                    nr_vcpus: vcpu_to_vnode.len() as u32,
+                    pad: 0,
+                    vdistance: vdistance.as_mut_ptr() as u64,
+                    vcpu_to_vnode: vcpu_to_vnode.as_mut_ptr() as u64,
+                    vnode_to_pnode: vnode_to_pnode.as_mut_ptr() as u64,
+                    vmemrange: vmemranges.as_mut_ptr() as u64,
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_DOMCTL, addr_of_mut!(domctl) as c_ulong)
+            .await?;
+        Ok(())
+    }
+
     pub async fn set_address_size(&self, domid: u32, size: u32) -> Result<()> {
         trace!(
             "domctl fd={} set_address_size domid={} size={}",
@@ -913,6 +1136,84 @@ impl XenCall {
         Ok(())
     }
 
+    pub async fn unmap_pirq(&self, domid: u32, pirq: u32) -> Result<()> {
+        trace!(
+            "physdev fd={} unmap_pirq domid={} pirq={}",
+            self.handle.as_raw_fd(),
+            domid,
+            pirq,
+        );
+        let mut physdev = PhysdevUnmapPirq {
+            domid: domid as u16,
+            pirq: pirq as c_int,
+        };
+        self.hypercall2(
+            HYPERVISOR_PHYSDEV_OP,
+            PHYSDEVOP_UNMAP_PIRQ,
+            addr_of_mut!(physdev) as c_ulong,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn deassign_device(&self, domid: u32, sbdf: u32) -> Result<()> {
+        trace!(
+            "domctl fd={} deassign_device domid={} sbdf={}",
+            self.handle.as_raw_fd(),
+            domid,
+            sbdf,
+        );
+        let mut domctl = DomCtl {
+            cmd: XEN_DOMCTL_DEASSIGN_DEVICE,
+            interface_version: self.domctl_interface_version,
+            domid,
+            value: DomCtlValue {
+                assign_device: AssignDevice {
+                    device: DOMCTL_DEV_PCI,
+                    flags: 0,
+                    pci_assign_device: PciAssignDevice { sbdf, padding: 0 },
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_DOMCTL, addr_of_mut!(domctl) as c_ulong)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_device_group(
+        &self,
+        domid: u32,
+        sbdf: u32,
+        max_sdevs: u32,
+    ) -> Result<Vec<u32>> {
+        trace!(
+            "domctl fd={} get_device_group domid={} sbdf={} max_sdevs={}",
+            self.handle.as_raw_fd(),
+            domid,
+            sbdf,
+            max_sdevs,
+        );
+        let mut sdevs = vec![0u32; max_sdevs as usize];
+        let mut domctl = DomCtl {
+            cmd: XEN_DOMCTL_GET_DEVICE_GROUP,
+            interface_version: self.domctl_interface_version,
+            domid,
+            value: DomCtlValue {
+                get_device_group: GetDeviceGroup {
+                    machine_sbdf: sbdf,
+                    max_sdevs,
+                    num_sdevs: 0,
+                    sdev_array: sdevs.as_mut_ptr() as c_ulong,
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_DOMCTL, addr_of_mut!(domctl) as c_ulong)
+            .await?;
+        let num_sdevs = unsafe { domctl.value.get_device_group.num_sdevs };
+        sdevs.truncate(num_sdevs.min(max_sdevs) as usize);
+        Ok(sdevs)
+    }
+
     #[allow(clippy::field_reassign_with_default)]
     pub async fn set_hvm_param(&self, domid: u32, index: u32, value: u64) -> Result<()> {
         trace!(
@@ -1032,6 +1333,48 @@ impl XenCall {
         Ok(topos)
     }
 
+    pub async fn numa_info(&self) -> Result<Vec<NumaNodeInfo>> {
+        let mut sysctl = Sysctl {
+            cmd: XEN_SYSCTL_NUMAINFO,
+            interface_version: self.sysctl_interface_version,
+            value: SysctlValue {
+                numainfo: SysctlNumainfo {
+                    num_nodes: 0,
+                    meminfo: 0,
+                    distance: 0,
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_SYSCTL, addr_of_mut!(sysctl) as c_ulong)
+            .await?;
+        let nodes = unsafe { sysctl.value.numainfo.num_nodes } as usize;
+        let mut meminfo = vec![SysctlMeminfo::default(); nodes];
+        let mut distance = vec![0u32; nodes * nodes];
+        let mut sysctl = Sysctl {
+            cmd: XEN_SYSCTL_NUMAINFO,
+            interface_version: self.sysctl_interface_version,
+            value: SysctlValue {
+                numainfo: SysctlNumainfo {
+                    num_nodes: nodes as u32,
+                    meminfo: meminfo.as_mut_ptr() as u64,
+                    distance: distance.as_mut_ptr() as u64,
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_SYSCTL, addr_of_mut!(sysctl) as c_ulong)
+            .await?;
+        Ok(meminfo
+            .iter()
+            .enumerate()
+            .map(|(node, info)| NumaNodeInfo {
+                node: node as u32,
+                memsize: info.memsize,
+                memfree: info.memfree,
+                distances: distance[node * nodes..(node + 1) * nodes].to_vec(),
+            })
+            .collect())
+    }
+
     pub async fn phys_info(&self) -> Result<SysctlPhysinfo> {
         let mut sysctl = Sysctl {
             cmd: XEN_SYSCTL_PHYSINFO,
@@ -1160,4 +1503,38 @@ impl XenCall {
         let newindex = unsafe { sysctl.value.console.index };
         Ok((u8buf, newindex))
     }
+
+    // reads what was written to the console ring since index, returning the data
+    // and the index to continue from.
+    pub async fn read_console_ring(&self, clear: bool, index: u32) -> Result<(Vec<u8>, u32)> {
+        let mut buffer = vec![0u8; CONSOLE_RING_READ_SIZE];
+        let mut sysctl = Sysctl {
+            cmd: XEN_SYSCTL_READCONSOLE,
+            interface_version: self.sysctl_interface_version,
+            value: SysctlValue {
+                console: SysctlReadconsole {
+                    clear: clear as u8,
+                    incremental: 1,
+                    pad: 0,
+                    index,
+                    buffer: buffer.as_mut_ptr() as u64,
+                    count: buffer.len() as u32,
+                },
+            },
+        };
+        self.hypercall1(HYPERVISOR_SYSCTL, addr_of_mut!(sysctl) as c_ulong)
+            .await?;
+        // Safety: the hypercall only updates the index and count of the SysctlReadconsole.
+        let (newindex, count) = unsafe { (sysctl.value.console.index, sysctl.value.console.count) };
+        buffer.truncate(count as usize);
+        Ok((buffer, newindex))
+    }
+}
+
+fn xenctl_bitmap(bytes: &mut [u8]) -> XenctlBitmap {
+    XenctlBitmap {
+        bitmap: bytes.as_mut_ptr() as u64,
+        nr_bits: (bytes.len() * 8) as u32,
+        pad: 0,
+    }
 }
@@ -256,6 +256,10 @@ pub union DomCtlValue {
     pub hvm_context: HvmContext,
     pub paging_mempool: PagingMempool,
     pub set_domain_handle: SetDomainHandle,
+    pub get_device_group: GetDeviceGroup,
+    pub vcpu_affinity: VcpuAffinity,
+    pub node_affinity: NodeAffinity,
+    pub vnuma: Vnuma,
     pub pad: [u8; 128],
 }
 
@@ -426,6 +430,55 @@ pub struct MaxVcpus {
     pub max_vcpus: u32,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct XenctlBitmap {
+    pub bitmap: u64,
+    pub nr_bits: u32,
+    pub pad: u32,
+}
+
+pub const XEN_VCPUAFFINITY_HARD: u32 = 1 << 0;
+pub const XEN_VCPUAFFINITY_SOFT: u32 = 1 << 1;
+pub const XEN_VCPUAFFINITY_FORCE: u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VcpuAffinity {
+    pub vcpu: u32,
+    pub flags: u32,
+    pub cpumap_hard: XenctlBitmap,
+    pub cpumap_soft: XenctlBitmap,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NodeAffinity {
+    pub nodemap: XenctlBitmap,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VmemRange {
+    pub start: u64,
+    pub end: u64,
+    pub flags: u32,
+    pub nid: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Vnuma {
+    pub nr_vnodes: u32,
+    pub nr_vmemranges: u32,
+    pub nr_vcpus: u32,
+    pub pad: u32,
+    pub vdistance: u64,
+    pub vcpu_to_vnode: u64,
+    pub vnode_to_pnode: u64,
+    pub vmemrange: u64,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct HypercallInit {
@@ -704,6 +757,7 @@ pub const E820_NVS: u32 = 4;
 pub const E820_UNUSABLE: u32 = 5;
 
 pub const PHYSDEVOP_MAP_PIRQ: u64 = 13;
+pub const PHYSDEVOP_UNMAP_PIRQ: u64 = 14;
 
 #[repr(C)]
 #[derive(Default, Clone, Copy, Debug)]
@@ -718,6 +772,13 @@ pub struct PhysdevMapPirq {
     pub table_base: u64,
 }
 
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct PhysdevUnmapPirq {
+    pub domid: u16,
+    pub pirq: c_int,
+}
+
 pub const DOMCTL_DEV_RDM_RELAXED: u32 = 1;
 pub const DOMCTL_DEV_PCI: u32 = 0;
 pub const DOMCTL_DEV_DT: u32 = 1;
@@ -737,8 +798,27 @@ pub struct AssignDevice {
     pub pci_assign_device: PciAssignDevice,
 }
 
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct GetDeviceGroup {
+    pub machine_sbdf: u32,
+    pub max_sdevs: u32,
+    pub num_sdevs: u32,
+    pub sdev_array: c_ulong,
+}
+
 pub const DOMID_IO: u32 = 0x7FF1;
 pub const MEMFLAGS_POPULATE_ON_DEMAND: u32 = 1 << 16;
+pub const MEMFLAGS_EXACT_NODE_REQUEST: u32 = 1 << 17;
+
+// asks for memory from a numa node, falling back to others unless exact.
+pub const fn memflags_node(node: u32) -> u32 {
+    ((node + 1) & 0xff) << 8
+}
+
+pub const fn memflags_exact_node(node: u32) -> u32 {
+    memflags_node(node) | MEMFLAGS_EXACT_NODE_REQUEST
+}
 
 pub struct PodTarget {
     pub target_pages: u64,
@@ -807,12 +887,57 @@ pub struct SysctlCputopoinfo {
     pub handle: c_ulong,
 }
 
+pub const XEN_INVALID_MEM_SIZE: u64 = u64::MAX;
+pub const XEN_INVALID_NODE_DISTANCE: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SysctlMeminfo {
+    pub memsize: u64,
+    pub memfree: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SysctlNumainfo {
+    pub num_nodes: u32,
+    pub meminfo: u64,
+    pub distance: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct NumaNodeInfo {
+    pub node: u32,
+    pub memsize: u64,
+    pub memfree: u64,
+    // distance to every node, indexed by node id.
+    pub distances: Vec<u32>,
+}
+
+impl NumaNodeInfo {
+    pub fn is_online(&self) -> bool {
+        self.memsize != XEN_INVALID_MEM_SIZE
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SysctlGetDomainInfoList {
+    pub first_domain: u16,
+    pub pad: u16,
+    pub max_domains: u32,
+    pub buffer: c_ulong,
+    pub num_domains: u32,
+}
+
 #[repr(C)]
 pub union SysctlValue {
     pub console: SysctlReadconsole,
+    pub get_domain_info_list: SysctlGetDomainInfoList,
     pub cputopoinfo: SysctlCputopoinfo,
     pub pm_op: SysctlPmOp,
     pub phys_info: SysctlPhysinfo,
+    pub numainfo: SysctlNumainfo,
     pub pad: [u8; 128],
 }
 
@@ -825,8 +950,10 @@ pub struct Sysctl {
 
 pub const XEN_SYSCTL_READCONSOLE: u32 = 1;
 pub const XEN_SYSCTL_PHYSINFO: u32 = 3;
+pub const XEN_SYSCTL_GETDOMAININFOLIST: u32 = 6;
 pub const XEN_SYSCTL_PM_OP: u32 = 12;
 pub const XEN_SYSCTL_CPUTOPOINFO: u32 = 16;
+pub const XEN_SYSCTL_NUMAINFO: u32 = 17;
 
 pub const XEN_SYSCTL_MIN_INTERFACE_VERSION: u32 = 0x00000015;
 pub const XEN_SYSCTL_MAX_INTERFACE_VERSION: u32 = 0x00000020;
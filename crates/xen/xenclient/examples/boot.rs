@@ -9,6 +9,7 @@ use xenplatform::domain::{
     KernelFormat, PlatformDomainConfig, PlatformKernelConfig, PlatformOptions,
     PlatformResourcesConfig,
 };
+use xenplatform::numa::PlacementPolicy;
 use xenplatform::RuntimePlatformType;
 
 #[tokio::main]
@@ -39,6 +40,11 @@ async fn main() -> Result<()> {
             assigned_vcpus: 1,
             max_memory_mb: 512,
             assigned_memory_mb: 512,
+            cpu_affinity: None,
+            soft_cpu_affinity: None,
+            node_affinity: None,
+            placement: PlacementPolicy::BestEffort,
+            vnuma: false,
         },
         options: PlatformOptions { iommu: true },
     });
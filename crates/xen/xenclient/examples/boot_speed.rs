@@ -11,6 +11,7 @@ use xenplatform::domain::{
     PlatformResourcesConfig,
 };
 use xenplatform::elfloader::ElfImageLoader;
+use xenplatform::numa::PlacementPolicy;
 use xenplatform::RuntimePlatformType;
 
 #[tokio::main]
@@ -33,7 +34,16 @@ async fn main() -> Result<()> {
         let end = std::time::Instant::now();
         let duration = end - start;
         println!("boot setup time: {:?}", duration);
-        client.destroy(domain.platform.domid).await?;
+        let start = std::time::Instant::now();
+        let report = client.destroy(domain.platform.domid).await?;
+        let end = std::time::Instant::now();
+        let duration = end - start;
+        println!(
+            "teardown time: {:?} (forced backends: {}, failures: {})",
+            duration,
+            report.forced_backends.len(),
+            report.failures.len()
+        );
     }
     Ok(())
 }
@@ -54,6 +64,11 @@ async fn create_domain(client: &XenClient, kernel: Arc<Vec<u8>>, i: u32) -> Resu
             assigned_vcpus: 1,
             max_memory_mb: 512,
             assigned_memory_mb: 512,
+            cpu_affinity: None,
+            soft_cpu_affinity: None,
+            node_affinity: None,
+            placement: PlacementPolicy::BestEffort,
+            vnuma: false,
         },
         options: PlatformOptions { iommu: true },
     });
@@ -6,6 +6,7 @@ use xenplatform::domain::PlatformDomainInfo;
 
 use crate::{
     error::Result,
+    pci::XenPciBackend,
     tx::{
         channel::ChannelDeviceConfig,
         fs9p::Fs9pDeviceConfig,
@@ -161,18 +162,31 @@ impl DomainConfig {
         &mut self,
         domid: u32,
         call: &XenCall,
+        pci_backend: &XenPciBackend,
         platform: &PlatformDomainInfo,
     ) -> Result<()> {
         if let Some(pci) = self.pci.as_mut() {
-            pci.prepare(domid, call).await?;
+            pci.prepare(domid, call, pci_backend).await?;
         }
 
         for channel in &mut self.channels {
             channel.prepare(platform).await?;
         }
 
+        if let Some(platform) = self.platform.as_ref() {
+            for vbd in &mut self.vbds {
+                vbd.prepare(&platform.platform)?;
+            }
+        }
+
         Ok(())
     }
+
+    pub(crate) async fn release(&self, pci_backend: &XenPciBackend) {
+        if let Some(pci) = self.pci.as_ref() {
+            pci.release(pci_backend).await;
+        }
+    }
 }
 
 pub struct DomainResult {
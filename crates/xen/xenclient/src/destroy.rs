@@ -0,0 +1,148 @@
+use std::{str::FromStr, time::Duration};
+
+use tokio::time::timeout;
+use xenstore::{XsdClient, XsdInterface};
+
+use crate::{
+    error::{Error, Result},
+    pci::PciBdf,
+};
+
+pub const DEFAULT_BACKEND_CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub(crate) struct BackendPciDevice {
+    pub bdf: PciBdf,
+    pub seized: bool,
+    pub driver: Option<String>,
+    pub sriov_parent: Option<(PciBdf, u32)>,
+    pub sriov_mac: bool,
+    pub sriov_vlan: bool,
+}
+
+#[derive(Debug)]
+pub struct DestroyFailure {
+    pub target: String,
+    pub error: Error,
+}
+
+#[derive(Debug, Default)]
+pub struct DestroyReport {
+    pub domid: u32,
+    pub closed_backends: Vec<String>,
+    pub forced_backends: Vec<String>,
+    pub released_loop_devices: Vec<String>,
+    pub released_pci_devices: Vec<PciBdf>,
+    pub removed_paths: Vec<String>,
+    pub store_released: bool,
+    pub failures: Vec<DestroyFailure>,
+}
+
+impl DestroyReport {
+    pub fn new(domid: u32) -> Self {
+        Self {
+            domid,
+            ..Default::default()
+        }
+    }
+
+    pub fn failed(&mut self, target: impl AsRef<str>, error: Error) {
+        self.failures.push(DestroyFailure {
+            target: target.as_ref().to_string(),
+            error,
+        });
+    }
+
+    // records the outcome of close_backend, where false means the backend
+    // had to be removed without closing.
+    pub(crate) fn backend_closed(&mut self, backend: String, result: Result<bool>) {
+        match result {
+            Ok(true) => self.closed_backends.push(backend),
+            Ok(false) => self.forced_backends.push(backend),
+            Err(error) => self.failed(backend, error),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.forced_backends.is_empty() && self.failures.is_empty()
+    }
+}
+
+// asks the backend to close and waits for it to reach the closed state.
+// the backend is removed regardless, and the result indicates whether
+// it closed before the deadline.
+pub(crate) async fn close_backend(
+    store: &XsdClient,
+    backend: &str,
+    deadline: Duration,
+) -> Result<bool> {
+    let state_path = format!("{}/state", backend);
+    let mut watch = store.create_watch(&state_path).await?;
+    let online_path = format!("{}/online", backend);
+    let tx = store.transaction().await?;
+    let state = tx.read_string(&state_path).await?.unwrap_or(String::new());
+    if state.is_empty() {
+        // nothing is left to close, but whatever remains of the backend goes.
+        tx.abort().await?;
+        store.rm(backend).await?;
+        return Ok(true);
+    }
+    tx.write_string(&online_path, "0").await?;
+    if u32::from_str(&state).unwrap_or(0) != 6 {
+        tx.write_string(&state_path, "5").await?;
+    }
+    store.bind_watch(&watch).await?;
+    tx.commit().await?;
+
+    let closed = timeout(deadline, async {
+        loop {
+            let state = store
+                .read_string(&state_path)
+                .await?
+                .unwrap_or_else(|| "6".to_string());
+            if i64::from_str(&state).unwrap_or(-1) == 6 {
+                return Ok::<bool, Error>(true);
+            }
+            if watch.receiver.recv().await.is_none() {
+                return Ok(false);
+            }
+        }
+    })
+    .await
+    .unwrap_or(Ok(false))?;
+    store.rm(backend).await?;
+    Ok(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DestroyReport;
+    use crate::error::Error;
+
+    #[test]
+    fn report_aggregates_backends() {
+        let mut report = DestroyReport::new(5);
+        report.backend_closed("backend/vbd/5/51712".to_string(), Ok(true));
+        assert!(report.is_clean());
+
+        report.backend_closed("backend/vif/5/0".to_string(), Ok(false));
+        assert!(!report.is_clean());
+        report.backend_closed(
+            "backend/console/5/0".to_string(),
+            Err(Error::GenericError("gone".to_string())),
+        );
+
+        assert_eq!(report.domid, 5);
+        assert_eq!(report.closed_backends, vec!["backend/vbd/5/51712"]);
+        assert_eq!(report.forced_backends, vec!["backend/vif/5/0"]);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].target, "backend/console/5/0");
+    }
+
+    #[test]
+    fn report_is_clean_without_failures() {
+        let mut report = DestroyReport::new(1);
+        assert!(report.is_clean());
+        report.failed("domain 1", Error::DomainNonExistent);
+        assert!(!report.is_clean());
+    }
+}
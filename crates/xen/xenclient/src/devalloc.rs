@@ -1,7 +1,17 @@
 use bit_vec::BitVec;
 
-const DEVICE_COUNT: usize = 4096;
-const BYTE_COUNT: usize = DEVICE_COUNT / 8;
+use crate::error::{Error, Result};
+
+pub const DEFAULT_DEVICE_COUNT: u32 = 4096;
+
+// the original format had no header: a 4-byte cursor followed by a bitmap
+// of exactly 4096 entries.
+const LEGACY_DEVICE_COUNT: usize = 4096;
+const LEGACY_BYTE_COUNT: usize = LEGACY_DEVICE_COUNT / 8;
+
+const STATE_MAGIC: &[u8; 4] = b"XDIA";
+const STATE_VERSION: u32 = 1;
+const STATE_HEADER_SIZE: usize = 16;
 
 pub struct DeviceIdAllocator {
     states: BitVec,
@@ -16,37 +26,120 @@ impl Default for DeviceIdAllocator {
 
 impl DeviceIdAllocator {
     pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_DEVICE_COUNT)
+    }
+
+    pub fn with_capacity(capacity: u32) -> Self {
         Self {
-            states: BitVec::from_elem(DEVICE_COUNT, false),
+            states: BitVec::from_elem(capacity as usize, false),
             cursor: 0,
         }
     }
 
-    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
-        if bytes.len() != BYTE_COUNT + 4 {
-            return None;
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(STATE_MAGIC) {
+            return DeviceIdAllocator::deserialize_legacy(bytes);
         }
 
-        let cursor = bytes[0] as u32
-            | ((bytes[1] as u32) << 8)
-            | ((bytes[2] as u32) << 16)
-            | ((bytes[3] as u32) << 24);
-        let slice = &bytes[4..BYTE_COUNT + 4];
-        if slice.len() != BYTE_COUNT {
-            return None;
+        if bytes.len() < STATE_HEADER_SIZE {
+            return Err(Error::InvalidDeviceIdAllocatorState(
+                "header is truncated".to_string(),
+            ));
+        }
+
+        let version = read_u32(&bytes[4..8]);
+        if version != STATE_VERSION {
+            return Err(Error::InvalidDeviceIdAllocatorState(format!(
+                "unsupported version {}",
+                version
+            )));
         }
-        let states = BitVec::from_bytes(slice);
 
-        Some(Self { states, cursor })
+        let capacity = read_u32(&bytes[8..12]);
+        let cursor = read_u32(&bytes[12..16]);
+        let bitmap = &bytes[STATE_HEADER_SIZE..];
+        if bitmap.len() != (capacity as usize).div_ceil(8) {
+            return Err(Error::InvalidDeviceIdAllocatorState(format!(
+                "bitmap length {} does not match capacity {}",
+                bitmap.len(),
+                capacity
+            )));
+        }
+
+        if capacity > 0 && cursor >= capacity {
+            return Err(Error::InvalidDeviceIdAllocatorState(format!(
+                "cursor {} is out of range",
+                cursor
+            )));
+        }
+
+        let mut states = BitVec::from_bytes(bitmap);
+        states.truncate(capacity as usize);
+        Ok(Self { states, cursor })
+    }
+
+    // loads the stored state, checking it against the ids of the devices that
+    // exist, or rebuilds it from those ids when no state has been written yet.
+    pub fn restore(allocator_type: &str, state: Option<&[u8]>, in_use: &[u32]) -> Result<Self> {
+        let Some(state) = state else {
+            let mut allocator = DeviceIdAllocator::new();
+            for &id in in_use {
+                if id >= allocator.capacity() {
+                    return Err(Error::DeviceIdOutOfRange(allocator_type.to_string(), id));
+                }
+                allocator.reserve(id);
+            }
+            return Ok(allocator);
+        };
+
+        let mut allocator = DeviceIdAllocator::deserialize(state)?;
+        allocator.grow(DEFAULT_DEVICE_COUNT);
+        for &id in in_use {
+            if !allocator.is_allocated(id) {
+                return Err(Error::DeviceIdAllocatorMismatch(
+                    allocator_type.to_string(),
+                    id,
+                ));
+            }
+        }
+        Ok(allocator)
+    }
+
+    fn deserialize_legacy(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != LEGACY_BYTE_COUNT + 4 {
+            return Err(Error::InvalidDeviceIdAllocatorState(format!(
+                "unrecognized state of length {}",
+                bytes.len()
+            )));
+        }
+
+        let cursor = read_u32(&bytes[0..4]) % LEGACY_DEVICE_COUNT as u32;
+        let states = BitVec::from_bytes(&bytes[4..]);
+        Ok(Self { states, cursor })
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.states.len() as u32
+    }
+
+    pub fn grow(&mut self, capacity: u32) {
+        if capacity as usize > self.states.len() {
+            self.states
+                .grow(capacity as usize - self.states.len(), false);
+        }
     }
 
     pub fn allocate(&mut self) -> Option<u32> {
+        if self.states.is_empty() {
+            return None;
+        }
+
         let start = self.cursor;
         loop {
             let id = self.cursor;
             let value = self.states.get(self.cursor as usize)?;
 
-            self.cursor = (self.cursor + 1) % DEVICE_COUNT as u32;
+            self.cursor = (self.cursor + 1) % self.capacity();
 
             if !value {
                 self.states.set(id as usize, true);
@@ -59,8 +152,24 @@ impl DeviceIdAllocator {
         }
     }
 
+    pub fn reserve(&mut self, id: u32) -> bool {
+        match self.states.get(id as usize) {
+            Some(false) => {
+                self.states.set(id as usize, true);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_allocated(&self, id: u32) -> bool {
+        self.states.get(id as usize).unwrap_or(false)
+    }
+
     pub fn release(&mut self, id: u32) {
-        self.states.set(id as usize, false);
+        if (id as usize) < self.states.len() {
+            self.states.set(id as usize, false);
+        }
     }
 
     pub fn count_free(&mut self) -> u32 {
@@ -68,12 +177,120 @@ impl DeviceIdAllocator {
     }
 
     pub fn serialize(&mut self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(BYTE_COUNT + 4);
-        bytes.push((self.cursor & 0xff) as u8);
-        bytes.push(((self.cursor >> 8) & 0xff) as u8);
-        bytes.push(((self.cursor >> 16) & 0xff) as u8);
-        bytes.push(((self.cursor >> 24) & 0xff) as u8);
-        bytes.extend_from_slice(&self.states.to_bytes());
+        let bitmap = self.states.to_bytes();
+        let mut bytes = Vec::with_capacity(STATE_HEADER_SIZE + bitmap.len());
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.capacity().to_le_bytes());
+        bytes.extend_from_slice(&self.cursor.to_le_bytes());
+        bytes.extend_from_slice(&bitmap);
         bytes
     }
 }
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceIdAllocator, DEFAULT_DEVICE_COUNT, LEGACY_BYTE_COUNT};
+    use crate::error::Error;
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut allocator = DeviceIdAllocator::with_capacity(20);
+        assert_eq!(allocator.allocate(), Some(0));
+        assert!(allocator.reserve(17));
+        let bytes = allocator.serialize();
+        assert!(bytes.starts_with(b"XDIA"));
+
+        let mut restored = DeviceIdAllocator::deserialize(&bytes).unwrap();
+        assert_eq!(restored.capacity(), 20);
+        assert!(restored.is_allocated(0));
+        assert!(restored.is_allocated(17));
+        assert_eq!(restored.count_free(), 18);
+        // the cursor is kept, so the next id follows the last one handed out.
+        assert_eq!(restored.allocate(), Some(1));
+    }
+
+    #[test]
+    fn deserialize_rejects_bad_state() {
+        let mut bytes = DeviceIdAllocator::with_capacity(16).serialize();
+        bytes[4] = 2;
+        assert!(DeviceIdAllocator::deserialize(&bytes).is_err());
+
+        let mut bytes = DeviceIdAllocator::with_capacity(16).serialize();
+        bytes[12] = 16;
+        assert!(DeviceIdAllocator::deserialize(&bytes).is_err());
+
+        let bytes = DeviceIdAllocator::with_capacity(16).serialize();
+        assert!(DeviceIdAllocator::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        assert!(DeviceIdAllocator::deserialize(&bytes[..8]).is_err());
+        assert!(DeviceIdAllocator::deserialize(&[0u8; 12]).is_err());
+    }
+
+    #[test]
+    fn legacy_state_is_migrated() {
+        let mut bytes = vec![0u8; LEGACY_BYTE_COUNT + 4];
+        bytes[..4].copy_from_slice(&3u32.to_le_bytes());
+        // bit_vec stores the first id in the most significant bit.
+        bytes[4] = 0b1010_0000;
+
+        let mut allocator = DeviceIdAllocator::deserialize(&bytes).unwrap();
+        assert_eq!(allocator.capacity(), 4096);
+        assert!(allocator.is_allocated(0));
+        assert!(!allocator.is_allocated(1));
+        assert!(allocator.is_allocated(2));
+        assert_eq!(allocator.allocate(), Some(3));
+
+        let migrated = DeviceIdAllocator::deserialize(&allocator.serialize()).unwrap();
+        assert!(migrated.is_allocated(3));
+    }
+
+    #[test]
+    fn grow_keeps_allocations() {
+        let mut allocator = DeviceIdAllocator::with_capacity(2);
+        assert_eq!(allocator.allocate(), Some(0));
+        assert_eq!(allocator.allocate(), Some(1));
+        assert_eq!(allocator.allocate(), None);
+
+        allocator.grow(4);
+        assert_eq!(allocator.capacity(), 4);
+        assert!(allocator.is_allocated(0));
+        assert_eq!(allocator.allocate(), Some(2));
+
+        // shrinking is not supported.
+        allocator.grow(1);
+        assert_eq!(allocator.capacity(), 4);
+    }
+
+    #[test]
+    fn restore_rebuilds_from_devices() {
+        let allocator = DeviceIdAllocator::restore("devid", None, &[0, 5]).unwrap();
+        assert_eq!(allocator.capacity(), DEFAULT_DEVICE_COUNT);
+        assert!(allocator.is_allocated(0));
+        assert!(allocator.is_allocated(5));
+
+        assert!(matches!(
+            DeviceIdAllocator::restore("devid", None, &[DEFAULT_DEVICE_COUNT]),
+            Err(Error::DeviceIdOutOfRange(_, id)) if id == DEFAULT_DEVICE_COUNT
+        ));
+    }
+
+    #[test]
+    fn restore_checks_state_against_devices() {
+        let mut allocator = DeviceIdAllocator::with_capacity(16);
+        allocator.reserve(1);
+        let state = allocator.serialize();
+
+        let restored = DeviceIdAllocator::restore("blkid", Some(&state), &[1]).unwrap();
+        assert_eq!(restored.capacity(), DEFAULT_DEVICE_COUNT);
+        assert!(restored.is_allocated(1));
+
+        assert!(matches!(
+            DeviceIdAllocator::restore("blkid", Some(&state), &[1, 2]),
+            Err(Error::DeviceIdAllocatorMismatch(_, 2))
+        ));
+    }
+}
@@ -129,3 +129,38 @@ impl DeviceStateWaiter {
         Ok(())
     }
 }
+
+pub async fn wait_for_state(
+    xsd: &XsdClient,
+    state_path: &str,
+    desired: u32,
+    deadline: Duration,
+) -> Result<()> {
+    let mut watch = xsd.create_watch(state_path).await?;
+    xsd.bind_watch(&watch).await?;
+    let result = timeout(deadline, async {
+        loop {
+            let state = xsd
+                .read_string(state_path)
+                .await?
+                .and_then(|state| state.parse::<u32>().ok());
+            if state == Some(desired) {
+                return Ok::<(), Error>(());
+            }
+
+            select! {
+                _update = watch.receiver.recv() => {},
+                _timeout = sleep(Duration::from_millis(250)) => {},
+            }
+        }
+    })
+    .await;
+
+    match result {
+        Ok(result) => result,
+        Err(_) => Err(Error::DevStateWaitError(format!(
+            "state path '{}' did not reach {} in time",
+            state_path, desired
+        ))),
+    }
+}
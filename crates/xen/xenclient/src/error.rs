@@ -40,16 +40,66 @@ pub enum Error {
     ParseIntError(#[from] std::num::ParseIntError),
     #[error("invalid pci bdf string")]
     InvalidPciBdfString,
+    #[error("invalid pci spec: {0}")]
+    InvalidPciSpec(String),
     #[error("pci device {0} is not assignable")]
     PciDeviceNotAssignable(PciBdf),
+    #[error("pci device {0} has an invalid config space: {1}")]
+    InvalidPciConfigSpace(PciBdf, String),
+    #[error("pci device {0} cannot be reset between domains")]
+    PciDeviceNotResettable(PciBdf),
+    #[error("sr-iov configuration failed: {0}")]
+    SriovConfigurationFailed(String),
+    #[error("invalid mac address: {0}")]
+    InvalidMacAddress(String),
+    #[error("pci device {0} is not attached")]
+    PciDeviceNotAttached(PciBdf),
+    #[error("pci device {0} is already attached")]
+    PciDeviceAlreadyAttached(PciBdf),
+    #[error("unsafe pci assignment: {0}")]
+    UnsafePciAssignment(String),
+    #[error("domain {0} does not have a pci root device")]
+    PciRootNotFound(u32),
     #[error("xen platform error: {0}")]
     XenPlatform(#[from] xenplatform::error::Error),
     #[error("invalid block index")]
     InvalidBlockIdx,
+    #[error("invalid virtual disk: {0}")]
+    InvalidVirtualDisk(String),
+    #[error("invalid virtual device number: {0:#x}")]
+    InvalidVirtualDevice(u32),
+    #[error("block index {0} is already in use")]
+    BlockIdxInUse(u32),
+    #[error("block index {0} is beyond the allocator capacity of {1}")]
+    BlockIdxOutOfRange(u32, u32),
+    #[error("virtual disk {0} is already in use")]
+    VirtualDiskInUse(String),
+    #[error("invalid vbd option: {0}")]
+    InvalidVbdOption(&'static str),
     #[error("device state wait error: {0}")]
     DevStateWaitError(String),
     #[error("device ids exhausted")]
     DevIdExhausted,
+    #[error("invalid device id allocator state: {0}")]
+    InvalidDeviceIdAllocatorState(String),
+    #[error("{0} allocator state does not reserve id {1}, which is in use")]
+    DeviceIdAllocatorMismatch(String, u32),
+    #[error("{0} {1} found in use is beyond the allocator capacity")]
+    DeviceIdOutOfRange(String, u32),
+    #[error("memory target of {target_kb}kb exceeds the static maximum of {static_max_kb}kb")]
+    InvalidMemoryTarget { target_kb: u64, static_max_kb: u64 },
+    #[error(
+        "domain {domid} did not reach its memory target of {target_kb}kb, holding {current_kb}kb"
+    )]
+    MemoryTargetTimeout {
+        domid: u32,
+        target_kb: u64,
+        current_kb: u64,
+    },
+    #[error("vcpu count {count} is outside of 1 to {max}")]
+    InvalidVcpuCount { count: u32, max: u32 },
+    #[error("domain {domid} did not bring {count} vcpus online, {online} are online")]
+    VcpuOnlineTimeout { domid: u32, count: u32, online: u32 },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
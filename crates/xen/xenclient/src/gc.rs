@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+
+use std::str::FromStr;
+
+use xencall::XenCall;
+use xenstore::{XsdInterface, XsdTransaction};
+
+use crate::error::Result;
+
+const DOMAIN_LIST_BATCH: u32 = 256;
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub live_domains: Vec<u32>,
+    pub stale_domains: Vec<u32>,
+    pub removed_paths: Vec<String>,
+}
+
+pub(crate) async fn list_live_domains(call: &XenCall) -> Result<HashSet<u32>> {
+    let mut domains = HashSet::new();
+    let mut first_domain = 0;
+    loop {
+        let infos = call
+            .get_domain_info_list(first_domain, DOMAIN_LIST_BATCH)
+            .await?;
+        let Some(last) = infos.last() else {
+            break;
+        };
+        first_domain = last.domid as u32 + 1;
+        let count = infos.len();
+        domains.extend(infos.into_iter().map(|info| info.domid as u32));
+        if count < DOMAIN_LIST_BATCH as usize {
+            break;
+        }
+    }
+    Ok(domains)
+}
+
+// the parts of the store that garbage collection looks at.
+#[derive(Debug, Default)]
+pub(crate) struct StoreSnapshot {
+    // each domain in /local/domain with its vm path.
+    pub domains: Vec<(u32, Option<String>)>,
+    // each backend type directory with the frontend domains listed in it.
+    pub backends: Vec<(u32, String, Vec<String>)>,
+    pub vm_paths: Vec<String>,
+}
+
+pub(crate) async fn read_store_snapshot(tx: &XsdTransaction) -> Result<StoreSnapshot> {
+    let mut snapshot = StoreSnapshot::default();
+    for domid in tx.list("/local/domain").await? {
+        let Ok(domid) = u32::from_str(&domid) else {
+            continue;
+        };
+        let dom_path = format!("/local/domain/{}", domid);
+        let vm_path = tx.read_string(format!("{}/vm", dom_path)).await?;
+        snapshot.domains.push((domid, vm_path));
+
+        let backend_path = format!("{}/backend", dom_path);
+        for backend_type in tx.list(&backend_path).await? {
+            let type_path = format!("{}/{}", backend_path, backend_type);
+            let frontends = tx.list(&type_path).await?;
+            snapshot.backends.push((domid, type_path, frontends));
+        }
+    }
+    for uuid in tx.list("/vm").await? {
+        snapshot.vm_paths.push(format!("/vm/{}", uuid));
+    }
+    Ok(snapshot)
+}
+
+// compares the store against the live domains. dom0 is never collected, even
+// when the hypervisor did not list it.
+pub(crate) fn collect_garbage(
+    snapshot: &StoreSnapshot,
+    live_domains: &HashSet<u32>,
+    dry_run: bool,
+) -> GcReport {
+    let is_live = |domid: u32| domid == 0 || live_domains.contains(&domid);
+    let mut stale_domains = Vec::new();
+    let mut stale_paths = Vec::new();
+    let mut referenced_vm_paths = HashSet::new();
+
+    for (domid, vm_path) in &snapshot.domains {
+        if let Some(vm_path) = vm_path {
+            referenced_vm_paths.insert(vm_path.clone());
+        }
+        if is_live(*domid) {
+            continue;
+        }
+        stale_domains.push(*domid);
+        if let Some(vm_path) = vm_path {
+            stale_paths.push(vm_path.clone());
+        }
+        stale_paths.push(format!("/local/domain/{}", domid));
+    }
+
+    for (backend_domid, type_path, frontends) in &snapshot.backends {
+        if !is_live(*backend_domid) {
+            continue;
+        }
+        for domid in frontends {
+            let Ok(domid) = u32::from_str(domid) else {
+                continue;
+            };
+            if is_live(domid) {
+                continue;
+            }
+            if !stale_domains.contains(&domid) {
+                stale_domains.push(domid);
+            }
+            stale_paths.push(format!("{}/{}", type_path, domid));
+        }
+    }
+
+    for vm_path in &snapshot.vm_paths {
+        if !referenced_vm_paths.contains(vm_path) {
+            stale_paths.push(vm_path.clone());
+        }
+    }
+
+    stale_domains.sort();
+    let mut live_domains = live_domains.iter().copied().collect::<Vec<_>>();
+    live_domains.sort();
+    GcReport {
+        dry_run,
+        live_domains,
+        stale_domains,
+        removed_paths: stale_paths,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{collect_garbage, StoreSnapshot};
+
+    fn snapshot() -> StoreSnapshot {
+        StoreSnapshot {
+            domains: vec![
+                (0, None),
+                (1, Some("/vm/live".to_string())),
+                (2, Some("/vm/stale".to_string())),
+            ],
+            backends: vec![
+                (
+                    0,
+                    "/local/domain/0/backend/vbd".to_string(),
+                    vec!["1".to_string(), "2".to_string(), "3".to_string()],
+                ),
+                (
+                    2,
+                    "/local/domain/2/backend/vif".to_string(),
+                    vec!["4".to_string()],
+                ),
+            ],
+            vm_paths: vec![
+                "/vm/live".to_string(),
+                "/vm/stale".to_string(),
+                "/vm/orphan".to_string(),
+            ],
+        }
+    }
+
+    #[test]
+    fn live_domains_are_skipped() {
+        let report = collect_garbage(&snapshot(), &HashSet::from([0, 1]), false);
+        assert!(!report.dry_run);
+        assert_eq!(report.live_domains, vec![0, 1]);
+        // domain 3 only remains as a backend entry, and the backends of a
+        // stale domain go with its own path.
+        assert_eq!(report.stale_domains, vec![2, 3]);
+        assert_eq!(
+            report.removed_paths,
+            vec![
+                "/vm/stale",
+                "/local/domain/2",
+                "/local/domain/0/backend/vbd/2",
+                "/local/domain/0/backend/vbd/3",
+                "/vm/orphan",
+            ]
+        );
+    }
+
+    #[test]
+    fn dom0_is_never_stale() {
+        let report = collect_garbage(&snapshot(), &HashSet::from([1]), false);
+        assert!(!report.stale_domains.contains(&0));
+        assert!(!report
+            .removed_paths
+            .iter()
+            .any(|path| path == "/local/domain/0"));
+        assert!(report
+            .removed_paths
+            .contains(&"/local/domain/0/backend/vbd/2".to_string()));
+    }
+
+    #[test]
+    fn dry_run_reports_the_same_paths() {
+        let live = HashSet::from([0, 1]);
+        let report = collect_garbage(&snapshot(), &live, true);
+        assert!(report.dry_run);
+        assert_eq!(
+            report.removed_paths,
+            collect_garbage(&snapshot(), &live, false).removed_paths
+        );
+    }
+}
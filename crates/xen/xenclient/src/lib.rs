@@ -1,31 +1,70 @@
 pub mod error;
 
 use config::{DomainConfig, DomainResult};
+use destroy::{close_backend, BackendPciDevice, DestroyReport, DEFAULT_BACKEND_CLOSE_TIMEOUT};
+use devstate::wait_for_state;
 use error::{Error, Result};
-use log::{debug, trace};
-use tokio::time::timeout;
+use gc::{collect_garbage, list_live_domains, read_store_snapshot, GcReport};
+use krataloopdev::LoopDevice;
+use log::{trace, warn};
+use memory::{current_memory_kb, wait_for_memory_target, MemoryTargetConfig};
+use pci::{sysfs_bdf, PciBdf, XenPciBackend};
+use pcigroup::PciGroupValidator;
+use sriov::SriovPhysicalFunction;
+use tokio::task::JoinSet;
+use tx::pci::{set_resource_permissions, PciDeviceConfig};
 use tx::{DeviceConfig, XenTransaction};
-use xenplatform::domain::{PlatformDomainInfo, PlatformDomainManager};
+use vcpu::{vcpu_counts, wait_for_online_vcpus, DEFAULT_VCPU_ONLINE_TIMEOUT};
+use xenplatform::domain::{PlatformDomainInfo, PlatformDomainManager, XEN_EXTRA_MEMORY_KB};
 
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use xencall::bitmap::{CpuMap, NodeMap};
 use xencall::XenCall;
 use xenstore::{XsdClient, XsdInterface};
 
 pub mod config;
+pub mod destroy;
 pub mod devalloc;
 pub mod devstate;
+pub mod gc;
+pub mod memory;
+mod netlink;
 pub mod pci;
+pub mod pcigroup;
+pub mod pciinfo;
+pub mod pcispec;
+pub mod sriov;
 pub mod tx;
 pub mod util;
+pub mod vcpu;
+pub mod vdisk;
+
+const PCI_RECONFIGURE_TIMEOUT: Duration = Duration::from_secs(10);
+const PCI_BACKEND_DEVICE_KEYS: &[&str] = &[
+    "key",
+    "dev",
+    "opts",
+    "vdefn",
+    "state",
+    "vdev",
+    "vdevfn",
+    "x-seized",
+    "x-driver",
+    "x-sriov-pf",
+    "x-sriov-vf",
+    "x-sriov-vf-mac",
+    "x-sriov-vf-vlan",
+];
 
 #[derive(Clone)]
 pub struct XenClient {
     pub store: XsdClient,
     pub call: XenCall,
     domain_manager: Arc<PlatformDomainManager>,
+    pci_backend: XenPciBackend,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -38,21 +77,29 @@ impl XenClient {
             store,
             call,
             domain_manager: Arc::new(domain_manager),
+            pci_backend: XenPciBackend::new(),
         })
     }
 
-    pub async fn create(&self, config: DomainConfig) -> Result<DomainResult> {
+    pub fn pci_backend(&mut self, pci_backend: XenPciBackend) -> &mut Self {
+        self.pci_backend = pci_backend;
+        self
+    }
+
+    pub async fn create(&self, mut config: DomainConfig) -> Result<DomainResult> {
         let platform = config
             .get_platform()
             .as_ref()
             .ok_or_else(|| Error::ParameterMissing("platform"))?
             .clone();
         let platform = self.domain_manager.create(platform).await?;
-        match self.init(platform.domid, config, &platform).await {
+        match self.init(platform.domid, &mut config, &platform).await {
             Ok(result) => Ok(result),
             Err(err) => {
                 // ignore since destroying a domain is best-effort when an error occurs
                 let _ = self.domain_manager.destroy(platform.domid).await;
+                // devices taken from the host go back to their drivers once the domain is gone.
+                config.release(&self.pci_backend).await;
                 Err(err)
             }
         }
@@ -65,7 +112,7 @@ impl XenClient {
     async fn init(
         &self,
         domid: u32,
-        mut config: DomainConfig,
+        config: &mut DomainConfig,
         created: &PlatformDomainInfo,
     ) -> Result<DomainResult> {
         trace!("xenclient init domid={} domain={:?}", domid, created);
@@ -89,7 +136,9 @@ impl XenClient {
         {
             return Err(Error::IntroduceDomainFailed);
         }
-        config.prepare(domid, &self.call, created).await?;
+        config
+            .prepare(domid, &self.call, &self.pci_backend, created)
+            .await?;
         let mut channels;
         let mut vifs;
         let mut vbds;
@@ -110,6 +159,14 @@ impl XenClient {
                 vifs.push(result);
             }
 
+            // requested disks are reserved up front, so that automatically
+            // assigned block indexes never take one.
+            for vbd in config.get_vbds() {
+                if let Some(disk) = vbd.get_disk() {
+                    transaction.reserve_vdisk(*disk).await?;
+                }
+            }
+
             vbds = Vec::new();
             for vbd in config.get_vbds() {
                 let result = vbd.add_to_transaction(&transaction).await?;
@@ -154,13 +211,76 @@ impl XenClient {
         })
     }
 
-    pub async fn destroy(&self, domid: u32) -> Result<()> {
-        let _ = self.destroy_store(domid).await;
-        self.domain_manager.destroy(domid).await?;
-        Ok(())
+    pub async fn destroy(&self, domid: u32) -> Result<DestroyReport> {
+        self.destroy_with_timeout(domid, DEFAULT_BACKEND_CLOSE_TIMEOUT)
+            .await
+    }
+
+    pub async fn destroy_with_timeout(
+        &self,
+        domid: u32,
+        backend_timeout: Duration,
+    ) -> Result<DestroyReport> {
+        let mut report = DestroyReport::new(domid);
+        let pci_devices = match self
+            .destroy_store(domid, backend_timeout, &mut report)
+            .await
+        {
+            Ok(pci_devices) => pci_devices,
+            Err(error) => {
+                report.failed(format!("domain {} store", domid), error);
+                Vec::new()
+            }
+        };
+        if let Err(error) = self.domain_manager.destroy(domid).await {
+            report.failed(format!("domain {}", domid), error.into());
+        }
+
+        // pci devices can only be reset once the domain no longer owns them.
+        let pci_backend = &self.pci_backend;
+        for device in pci_devices {
+            let mut result = pci_backend.reset(&device.bdf).await;
+            if result.is_ok() && device.seized {
+                result = pci_backend
+                    .release(&device.bdf, device.driver.as_deref())
+                    .await;
+            }
+            if result.is_ok() {
+                if let Some((physical_function, index)) = device.sriov_parent {
+                    result = SriovPhysicalFunction::with_sysfs_root(
+                        pci_backend.sysfs_root(),
+                        physical_function,
+                    )
+                    .reset_vf(index, device.sriov_mac, device.sriov_vlan)
+                    .await;
+                }
+            }
+            match result {
+                Ok(()) => report.released_pci_devices.push(device.bdf),
+                Err(error) => report.failed(device.bdf.to_string(), error),
+            }
+        }
+
+        match self.store.release_domain(domid).await {
+            Ok(_) => report.store_released = true,
+            Err(error) => report.failed(format!("domain {} release", domid), error.into()),
+        }
+
+        for failure in &report.failures {
+            warn!(
+                "failed to clean up {} for domain {}: {}",
+                failure.target, domid, failure.error
+            );
+        }
+        Ok(report)
     }
 
-    async fn destroy_store(&self, domid: u32) -> Result<()> {
+    async fn destroy_store(
+        &self,
+        domid: u32,
+        backend_timeout: Duration,
+        report: &mut DestroyReport,
+    ) -> Result<Vec<BackendPciDevice>> {
         let dom_path = self.store.get_domain_path(domid).await?;
         let vm_path = self.store.read_string(&format!("{}/vm", dom_path)).await?;
         if vm_path.is_none() {
@@ -168,6 +288,8 @@ impl XenClient {
         }
 
         let mut backend_paths: Vec<String> = Vec::new();
+        let mut loop_devices: Vec<String> = Vec::new();
+        let mut pci_devices: Vec<BackendPciDevice> = Vec::new();
         let console_frontend_path = format!("{}/console", dom_path);
         let console_backend_path = self
             .store
@@ -192,12 +314,68 @@ impl XenClient {
                 else {
                     continue;
                 };
+
+                match device_category.as_str() {
+                    "vbd" => match self.read_backend_loop_device(&backend_path).await {
+                        Ok(Some(path)) => loop_devices.push(path),
+                        Ok(None) => {}
+                        Err(error) => report.failed(&backend_path, error),
+                    },
+
+                    "pci" => {
+                        pci_devices.extend(
+                            self.collect_backend_pci_devices(&backend_path, report)
+                                .await,
+                        );
+                    }
+
+                    _ => {}
+                }
                 backend_paths.push(backend_path);
             }
         }
 
+        let mut backend_tasks = JoinSet::new();
         for backend in &backend_paths {
-            self.destroy_backend(backend).await?;
+            let store = self.store.clone();
+            let backend = backend.clone();
+            backend_tasks.spawn(async move {
+                let result = close_backend(&store, &backend, backend_timeout).await;
+                (backend, result)
+            });
+        }
+
+        while let Some(joined) = backend_tasks.join_next().await {
+            let (backend, result) = match joined {
+                Ok(joined) => joined,
+                Err(error) => {
+                    report.failed(
+                        format!("domain {} backend", domid),
+                        Error::GenericError(error.to_string()),
+                    );
+                    continue;
+                }
+            };
+            if let Ok(false) = result {
+                warn!(
+                    "backend did not close in time, forcing removal: {}",
+                    backend
+                );
+            }
+            report.backend_closed(backend, result);
+        }
+
+        for path in loop_devices {
+            let detach_path = path.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                LoopDevice::open(&detach_path).and_then(|device| device.detach())
+            })
+            .await;
+            match result {
+                Ok(Ok(())) => report.released_loop_devices.push(path),
+                Ok(Err(error)) => report.failed(path, error.into()),
+                Err(error) => report.failed(path, Error::GenericError(error.to_string())),
+            }
         }
 
         let tx = self.store.transaction().await?;
@@ -206,58 +384,443 @@ impl XenClient {
         if let Some(backend) = console_backend_path {
             backend_removals.push(backend);
         }
+        let mut removed_paths = Vec::new();
         for path in &backend_removals {
             let path = PathBuf::from(path);
             let parent = path.parent().ok_or(Error::PathParentNotFound)?;
-            tx.rm(parent.to_str().ok_or(Error::PathStringConversion)?)
-                .await?;
+            let parent = parent.to_str().ok_or(Error::PathStringConversion)?;
+            tx.rm(parent).await?;
+            removed_paths.push(parent.to_string());
         }
         if let Some(vm_path) = vm_path {
             tx.rm(&vm_path).await?;
+            removed_paths.push(vm_path);
         }
         tx.rm(&dom_path).await?;
+        removed_paths.push(dom_path);
         tx.commit().await?;
-        Ok(())
+        report.removed_paths.extend(removed_paths);
+        Ok(pci_devices)
+    }
+
+    // the loop device behind a vbd, when it was marked for release with the domain.
+    async fn read_backend_loop_device(&self, backend: &str) -> Result<Option<String>> {
+        if self
+            .store
+            .read_string(format!("{}/x-release-loop", backend))
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+        Ok(self
+            .store
+            .read_string(format!("{}/physical-device-path", backend))
+            .await?
+            .filter(|path| path.starts_with("/dev/loop")))
+    }
+
+    async fn read_backend_pci_devices(&self, backend: &str) -> Result<Vec<BackendPciDevice>> {
+        let mut devices = Vec::new();
+        for index in 0..self.read_backend_num_devs(backend).await? {
+            if let Some(device) = self.read_backend_pci_device(backend, index).await? {
+                devices.push(device);
+            }
+        }
+        Ok(devices)
+    }
+
+    // a malformed device is recorded in the report and skipped, so that it
+    // does not keep the rest of the domain from being torn down.
+    async fn collect_backend_pci_devices(
+        &self,
+        backend: &str,
+        report: &mut DestroyReport,
+    ) -> Vec<BackendPciDevice> {
+        let num_devs = match self.read_backend_num_devs(backend).await {
+            Ok(num_devs) => num_devs,
+            Err(error) => {
+                report.failed(backend, error);
+                return Vec::new();
+            }
+        };
+
+        let mut devices = Vec::new();
+        for index in 0..num_devs {
+            match self.read_backend_pci_device(backend, index).await {
+                Ok(Some(device)) => devices.push(device),
+                Ok(None) => {}
+                Err(error) => report.failed(format!("{}/dev-{}", backend, index), error),
+            }
+        }
+        devices
+    }
+
+    async fn read_backend_num_devs(&self, backend: &str) -> Result<u32> {
+        match self
+            .store
+            .read_string(format!("{}/num_devs", backend))
+            .await?
+        {
+            Some(num_devs) => Ok(u32::from_str(&num_devs)?),
+            None => Ok(0),
+        }
+    }
+
+    async fn read_backend_pci_device(
+        &self,
+        backend: &str,
+        index: u32,
+    ) -> Result<Option<BackendPciDevice>> {
+        let Some(dev) = self
+            .store
+            .read_string(format!("{}/dev-{}", backend, index))
+            .await?
+        else {
+            return Ok(None);
+        };
+        let bdf = PciBdf::from_str(&dev)?;
+        let seized = self
+            .store
+            .read_string(format!("{}/x-seized-{}", backend, index))
+            .await?
+            .is_some();
+        let driver = self
+            .store
+            .read_string(format!("{}/x-driver-{}", backend, index))
+            .await?;
+        let sriov_pf = self
+            .store
+            .read_string(format!("{}/x-sriov-pf-{}", backend, index))
+            .await?;
+        let sriov_vf = self
+            .store
+            .read_string(format!("{}/x-sriov-vf-{}", backend, index))
+            .await?;
+        let sriov_parent = match (sriov_pf, sriov_vf) {
+            (Some(pf), Some(vf)) => Some((PciBdf::from_str(&pf)?, u32::from_str(&vf)?)),
+            _ => None,
+        };
+        let sriov_mac = self
+            .store
+            .read_string(format!("{}/x-sriov-vf-mac-{}", backend, index))
+            .await?
+            .is_some();
+        let sriov_vlan = self
+            .store
+            .read_string(format!("{}/x-sriov-vf-vlan-{}", backend, index))
+            .await?
+            .is_some();
+        Ok(Some(BackendPciDevice {
+            bdf: PciBdf { vdefn: None, ..bdf },
+            seized,
+            driver,
+            sriov_parent,
+            sriov_mac,
+            sriov_vlan,
+        }))
     }
 
     async fn destroy_backend(&self, backend: &str) -> Result<()> {
-        let state_path = format!("{}/state", backend);
-        let mut watch = self.store.create_watch(&state_path).await?;
-        let online_path = format!("{}/online", backend);
-        let tx = self.store.transaction().await?;
-        let state = tx.read_string(&state_path).await?.unwrap_or(String::new());
-        if state.is_empty() {
-            return Ok(());
+        if !close_backend(&self.store, backend, DEFAULT_BACKEND_CLOSE_TIMEOUT).await? {
+            warn!("unable to safely destroy backend: {}", backend);
+        }
+        Ok(())
+    }
+
+    // asks the balloon driver of the zone to move to target_mb, returning the
+    // memory in kb the zone holds afterwards.
+    pub async fn set_memory(&self, domid: u32, target_mb: u64) -> Result<u64> {
+        self.set_memory_with_config(domid, target_mb, &MemoryTargetConfig::default())
+            .await
+    }
+
+    pub async fn set_memory_with_config(
+        &self,
+        domid: u32,
+        target_mb: u64,
+        config: &MemoryTargetConfig,
+    ) -> Result<u64> {
+        let dom_path = self.store.get_domain_path(domid).await?;
+        let Some(static_max_kb) = self
+            .store
+            .read_string(format!("{}/memory/static-max", dom_path))
+            .await?
+        else {
+            return Err(Error::DomainNonExistent);
+        };
+        let static_max_kb = u64::from_str(&static_max_kb)?;
+        let target_kb = target_mb * 1024;
+        if target_kb > static_max_kb {
+            return Err(Error::InvalidMemoryTarget {
+                target_kb,
+                static_max_kb,
+            });
         }
-        tx.write_string(&online_path, "0").await?;
-        if !state.is_empty() && u32::from_str(&state).unwrap_or(0) != 6 {
-            tx.write_string(&state_path, "5").await?;
+
+        if config.adjust_max {
+            self.call
+                .set_max_mem(domid, target_kb + XEN_EXTRA_MEMORY_KB)
+                .await?;
         }
-        self.store.bind_watch(&watch).await?;
-        tx.commit().await?;
+        self.store
+            .write_string(
+                format!("{}/memory/target", dom_path),
+                &target_kb.to_string(),
+            )
+            .await?;
+
+        match config.timeout {
+            Some(timeout) => wait_for_memory_target(&self.call, domid, target_kb, timeout).await,
+            None => current_memory_kb(&self.call, domid).await,
+        }
+    }
+
+    // asks the zone to bring vcpus online or offline until count of them are online.
+    pub async fn set_vcpus(&self, domid: u32, count: u32) -> Result<()> {
+        self.set_vcpus_with_timeout(domid, count, DEFAULT_VCPU_ONLINE_TIMEOUT)
+            .await
+    }
 
-        let mut count: u32 = 0;
+    pub async fn set_vcpus_with_timeout(
+        &self,
+        domid: u32,
+        count: u32,
+        timeout: Duration,
+    ) -> Result<()> {
+        let counts = vcpu_counts(&self.call, domid).await?;
+        counts.check(count)?;
+        let max = counts.max;
+        let dom_path = self.store.get_domain_path(domid).await?;
         loop {
-            if count >= 3 {
-                debug!("unable to safely destroy backend: {}", backend);
+            let tx = self.store.transaction().await?;
+            for i in 0..max {
+                let path = format!("{}/cpu/{}/availability", dom_path, i);
+                let availability = if i < count { "online" } else { "offline" };
+                if tx.read_string(&path).await?.as_deref() != Some(availability) {
+                    tx.write_string(&path, availability).await?;
+                }
+            }
+            if tx.maybe_commit().await? {
                 break;
             }
-            let _ = timeout(Duration::from_secs(1), watch.receiver.recv()).await;
-            let state = self
-                .store
-                .read_string(&state_path)
+        }
+        wait_for_online_vcpus(&self.call, domid, count, timeout).await
+    }
+
+    // re-pins every vcpu of a running zone, leaving whichever map is None as it is.
+    pub async fn set_cpu_affinity(
+        &self,
+        domid: u32,
+        hard: Option<&CpuMap>,
+        soft: Option<&CpuMap>,
+    ) -> Result<()> {
+        let max = vcpu_counts(&self.call, domid).await?.max;
+        for vcpu in 0..max {
+            self.call.set_vcpu_affinity(domid, vcpu, hard, soft).await?;
+        }
+        Ok(())
+    }
+
+    // only memory allocated from now on follows the new node affinity.
+    pub async fn set_node_affinity(&self, domid: u32, nodes: &NodeMap) -> Result<()> {
+        self.call.set_node_affinity(domid, nodes).await?;
+        Ok(())
+    }
+
+    pub async fn gc(&self, dry_run: bool) -> Result<GcReport> {
+        loop {
+            let live_domains = list_live_domains(&self.call).await?;
+            let tx = self.store.transaction().await?;
+            let report = collect_garbage(&read_store_snapshot(&tx).await?, &live_domains, dry_run);
+
+            // a domain created while the store was being scanned would look stale,
+            // so only act when the set of live domains did not change.
+            if list_live_domains(&self.call).await? != live_domains {
+                tx.abort().await?;
+                continue;
+            }
+
+            if report.dry_run || report.removed_paths.is_empty() {
+                tx.abort().await?;
+            } else {
+                for path in &report.removed_paths {
+                    tx.rm(path).await?;
+                }
+                if !tx.maybe_commit().await? {
+                    continue;
+                }
+            }
+            return Ok(report);
+        }
+    }
+
+    pub async fn attach_pci(&self, domid: u32, mut device: PciDeviceConfig) -> Result<()> {
+        let backend_path = self.find_pci_backend(domid).await?;
+        let mut bdfs = self
+            .read_backend_pci_devices(&backend_path)
+            .await?
+            .into_iter()
+            .map(|attached| attached.bdf)
+            .collect::<Vec<_>>();
+        if bdfs
+            .iter()
+            .any(|attached| sysfs_bdf(attached) == sysfs_bdf(device.get_bdf()))
+        {
+            return Err(Error::PciDeviceAlreadyAttached(*device.get_bdf()));
+        }
+        bdfs.push(*device.get_bdf());
+        let mut validator = PciGroupValidator::with_sysfs_root(self.pci_backend.sysfs_root());
+        validator
+            .hypervisor(self.call.clone())
+            .validate(domid, &bdfs)
+            .await?;
+
+        let result = match device.prepare(domid, &self.call, &self.pci_backend).await {
+            Ok(()) => self.add_backend_pci_device(&backend_path, &device).await,
+            Err(error) => Err(error),
+        };
+        let connected = match result {
+            Ok(connected) => connected,
+            Err(error) => {
+                device.revoke(domid, &self.call, &self.pci_backend).await;
+                return Err(error);
+            }
+        };
+
+        if connected {
+            let state_path = format!("{}/state", backend_path);
+            wait_for_state(&self.store, &state_path, 4, PCI_RECONFIGURE_TIMEOUT).await?;
+        }
+        Ok(())
+    }
+
+    // returns whether pcifront was connected and has been asked to reconfigure.
+    async fn add_backend_pci_device(
+        &self,
+        backend_path: &str,
+        device: &PciDeviceConfig,
+    ) -> Result<bool> {
+        let state_path = format!("{}/state", backend_path);
+        loop {
+            let tx = self.store.transaction().await?;
+            let num_devs = tx
+                .read_string(format!("{}/num_devs", backend_path))
                 .await?
-                .unwrap_or_else(|| "6".to_string());
-            let state = i64::from_str(&state).unwrap_or(-1);
-            if state == 6 {
+                .map(|num_devs| u32::from_str(&num_devs))
+                .transpose()?
+                .unwrap_or(0);
+            let connected = tx.read_string(&state_path).await?.as_deref() == Some("4");
+            for (key, value) in device.backend_items(num_devs as usize) {
+                tx.write_string(format!("{}/{}", backend_path, key), &value)
+                    .await?;
+            }
+            tx.write_string(format!("{}/state-{}", backend_path, num_devs), "1")
+                .await?;
+            tx.write_string(
+                format!("{}/num_devs", backend_path),
+                &(num_devs + 1).to_string(),
+            )
+            .await?;
+            if connected {
+                tx.write_string(&state_path, "7").await?;
+            }
+            if tx.maybe_commit().await? {
+                return Ok(connected);
+            }
+        }
+    }
+
+    pub async fn detach_pci(&self, domid: u32, bdf: PciBdf) -> Result<()> {
+        let backend_path = self.find_pci_backend(domid).await?;
+        let state_path = format!("{}/state", backend_path);
+        let devices = self.read_backend_pci_devices(&backend_path).await?;
+        let Some(index) = devices
+            .iter()
+            .position(|device| sysfs_bdf(&device.bdf) == sysfs_bdf(&bdf))
+        else {
+            return Err(Error::PciDeviceNotAttached(bdf));
+        };
+        let device = &devices[index];
+
+        // ask pcifront to let go of the device before it is taken away.
+        if self.store.read_string(&state_path).await?.as_deref() == Some("4") {
+            let tx = self.store.transaction().await?;
+            tx.write_string(format!("{}/state-{}", backend_path, index), "5")
+                .await?;
+            tx.write_string(&state_path, "7").await?;
+            tx.commit().await?;
+            wait_for_state(&self.store, &state_path, 4, PCI_RECONFIGURE_TIMEOUT).await?;
+        }
+
+        loop {
+            let tx = self.store.transaction().await?;
+            for key in PCI_BACKEND_DEVICE_KEYS {
+                for current in index..devices.len() - 1 {
+                    let next = tx
+                        .read_string(format!("{}/{}-{}", backend_path, key, current + 1))
+                        .await?;
+                    let path = format!("{}/{}-{}", backend_path, key, current);
+                    match next {
+                        Some(next) => {
+                            tx.write_string(&path, &next).await?;
+                        }
+                        None => {
+                            tx.rm(&path).await?;
+                        }
+                    }
+                }
+                tx.rm(format!("{}/{}-{}", backend_path, key, devices.len() - 1))
+                    .await?;
+            }
+            tx.write_string(
+                format!("{}/num_devs", backend_path),
+                &(devices.len() - 1).to_string(),
+            )
+            .await?;
+            if tx.maybe_commit().await? {
                 break;
             }
-            count += 1;
         }
-        self.store.rm(backend).await?;
+
+        let pci_backend = &self.pci_backend;
+        set_resource_permissions(pci_backend, &device.bdf, domid, &self.call, false).await?;
+        if let Some(irq) = pci_backend.read_irq(&device.bdf).await? {
+            self.call.irq_permission(domid, irq, false).await?;
+            self.call.unmap_pirq(domid, irq).await?;
+        }
+        self.call
+            .deassign_device(domid, device.bdf.encode())
+            .await?;
+        pci_backend.reset(&device.bdf).await?;
+        if device.seized {
+            pci_backend
+                .release(&device.bdf, device.driver.as_deref())
+                .await?;
+        }
+        if let Some((physical_function, index)) = device.sriov_parent {
+            SriovPhysicalFunction::with_sysfs_root(pci_backend.sysfs_root(), physical_function)
+                .reset_vf(index, device.sriov_mac, device.sriov_vlan)
+                .await?;
+        }
         Ok(())
     }
 
+    async fn find_pci_backend(&self, domid: u32) -> Result<String> {
+        let dom_path = self.store.get_domain_path(domid).await?;
+        let pci_path = format!("{}/device/pci", dom_path);
+        for device_id in self.store.list(&pci_path).await? {
+            if let Some(backend_path) = self
+                .store
+                .read_string(format!("{}/{}/backend", pci_path, device_id))
+                .await?
+            {
+                return Ok(backend_path);
+            }
+        }
+        Err(Error::PciRootNotFound(domid))
+    }
+
     pub async fn destroy_device(
         &self,
         category: &str,
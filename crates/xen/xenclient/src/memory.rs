@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use xencall::XenCall;
+use xenplatform::sys::XEN_PAGE_SIZE;
+
+use crate::{
+    error::{Error, Result},
+    util::{domain_info, poll_until},
+};
+
+pub const DEFAULT_MEMORY_TARGET_TIMEOUT: Duration = Duration::from_secs(30);
+const MEMORY_TARGET_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// balloon drivers may settle a few pages away from the target.
+const MEMORY_TARGET_SLACK_KB: u64 = 1024;
+
+#[derive(Clone, Debug)]
+pub struct MemoryTargetConfig {
+    // moves the hypervisor limit along with the target, so the zone can neither
+    // grow past it nor keep memory it was asked to give back.
+    pub adjust_max: bool,
+    // how long to wait for the balloon driver, or None to return once the target is written.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for MemoryTargetConfig {
+    fn default() -> Self {
+        MemoryTargetConfig {
+            adjust_max: true,
+            timeout: Some(DEFAULT_MEMORY_TARGET_TIMEOUT),
+        }
+    }
+}
+
+pub(crate) async fn current_memory_kb(call: &XenCall, domid: u32) -> Result<u64> {
+    Ok(domain_info(call, domid).await?.total_pages * (XEN_PAGE_SIZE / 1024))
+}
+
+pub(crate) async fn wait_for_memory_target(
+    call: &XenCall,
+    domid: u32,
+    target_kb: u64,
+    timeout: Duration,
+) -> Result<u64> {
+    poll_until(
+        timeout,
+        MEMORY_TARGET_POLL_INTERVAL,
+        || current_memory_kb(call, domid),
+        |current_kb| current_kb.abs_diff(target_kb) <= MEMORY_TARGET_SLACK_KB,
+    )
+    .await?
+    .map_err(|current_kb| Error::MemoryTargetTimeout {
+        domid,
+        target_kb,
+        current_kb,
+    })
+}
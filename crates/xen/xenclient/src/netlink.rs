@@ -0,0 +1,140 @@
+use std::{io, mem::size_of};
+
+const RTM_SETLINK: u16 = 19;
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLA_F_NESTED: u16 = 1 << 15;
+const IFLA_VFINFO_LIST: u16 = 22;
+const IFLA_VF_INFO: u16 = 1;
+const IFLA_VF_MAC: u16 = 1;
+const IFLA_VF_VLAN: u16 = 2;
+const NLMSG_HEADER_SIZE: usize = 16;
+const IFINFOMSG_SIZE: usize = 16;
+// struct ifla_vf_mac keeps room for 32 byte hardware addresses.
+const IFLA_VF_MAC_SIZE: usize = 32;
+
+pub(crate) enum VfOption {
+    Mac([u8; 6]),
+    Vlan(u16),
+}
+
+// sets an option of a virtual function through its physical function, as
+// 'ip link set dev <pf> vf <vf> ...' does.
+pub(crate) fn set_vf_option(ifindex: i32, vf: u32, option: &VfOption) -> io::Result<()> {
+    let setting = match option {
+        VfOption::Mac(mac) => {
+            let mut payload = vf.to_ne_bytes().to_vec();
+            let mut address = [0u8; IFLA_VF_MAC_SIZE];
+            address[..mac.len()].copy_from_slice(mac);
+            payload.extend_from_slice(&address);
+            attribute(IFLA_VF_MAC, &payload)
+        }
+        VfOption::Vlan(vlan) => {
+            let mut payload = vf.to_ne_bytes().to_vec();
+            payload.extend_from_slice(&(*vlan as u32).to_ne_bytes());
+            // qos
+            payload.extend_from_slice(&0u32.to_ne_bytes());
+            attribute(IFLA_VF_VLAN, &payload)
+        }
+    };
+    let info = attribute(IFLA_VF_INFO | NLA_F_NESTED, &setting);
+    let list = attribute(IFLA_VFINFO_LIST | NLA_F_NESTED, &info);
+
+    let mut message = Vec::with_capacity(NLMSG_HEADER_SIZE + IFINFOMSG_SIZE + list.len());
+    message.extend_from_slice(
+        &((NLMSG_HEADER_SIZE + IFINFOMSG_SIZE + list.len()) as u32).to_ne_bytes(),
+    );
+    message.extend_from_slice(&RTM_SETLINK.to_ne_bytes());
+    message.extend_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+    // sequence and port id, the kernel fills in the port id.
+    message.extend_from_slice(&1u32.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    // struct ifinfomsg, selecting the physical function by index.
+    message.extend_from_slice(&[libc::AF_UNSPEC as u8, 0]);
+    message.extend_from_slice(&0u16.to_ne_bytes());
+    message.extend_from_slice(&ifindex.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&list);
+    request(&message)
+}
+
+fn attribute(kind: u16, payload: &[u8]) -> Vec<u8> {
+    let length = 4 + payload.len();
+    let mut attribute = Vec::with_capacity(length.next_multiple_of(4));
+    attribute.extend_from_slice(&(length as u16).to_ne_bytes());
+    attribute.extend_from_slice(&kind.to_ne_bytes());
+    attribute.extend_from_slice(payload);
+    attribute.resize(length.next_multiple_of(4), 0);
+    attribute
+}
+
+fn request(message: &[u8]) -> io::Result<()> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = exchange(fd, message);
+    unsafe {
+        libc::close(fd);
+    }
+    result
+}
+
+fn exchange(fd: i32, message: &[u8]) -> io::Result<()> {
+    let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    let sent = unsafe {
+        libc::sendto(
+            fd,
+            message.as_ptr() as *const libc::c_void,
+            message.len(),
+            0,
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut buffer = [0u8; 4096];
+    let received = unsafe {
+        libc::recv(
+            fd,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            0,
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let reply = &buffer[..received as usize];
+    // the ack is an nlmsgerr, an errno followed by the request header.
+    if reply.len() < NLMSG_HEADER_SIZE + 4
+        || u16::from_ne_bytes([reply[4], reply[5]]) != NLMSG_ERROR
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected netlink reply",
+        ));
+    }
+    let error = i32::from_ne_bytes([
+        reply[NLMSG_HEADER_SIZE],
+        reply[NLMSG_HEADER_SIZE + 1],
+        reply[NLMSG_HEADER_SIZE + 2],
+        reply[NLMSG_HEADER_SIZE + 3],
+    ]);
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(-error));
+    }
+    Ok(())
+}
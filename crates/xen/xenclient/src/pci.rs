@@ -1,18 +1,37 @@
 use regex::Regex;
-use std::{fmt::Display, path::PathBuf, str::FromStr};
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
 use tokio::fs;
 
-use crate::error::{Error, Result};
+use crate::{
+    error::{Error, Result},
+    pciinfo::PciDeviceInfo,
+};
 
-const PCIBACK_SYSFS_PATH: &str = "/sys/bus/pci/drivers/pciback";
-const PCI_BDF_REGEX: &str = r"^([0-9a-f]{4}):([0-9a-f]{2}):([0-9a-f]{2}).([0-9a-f]{1})$";
-const PCI_BDF_SHORT_REGEX: &str = r"^([0-9a-f]{2}):([0-9a-f]{2}).([0-9a-f]{1})$";
-const PCI_BDF_VDEFN_REGEX: &str =
-    r"^([0-9a-f]{4}):([0-9a-f]{2}):([0-9a-f]{2}).([0-9a-f]{1})@([0-9a-f]{2})$";
+const SYSFS_ROOT: &str = "/sys";
+pub(crate) const PCIBACK_DRIVER_NAME: &str = "pciback";
 const FLAG_PCI_BAR_IO: u64 = 0x1;
 
+static PCI_BDF_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([0-9a-fA-F]{4}):([0-9a-fA-F]{2}):([0-9a-fA-F]{2})\.([0-7])$")
+        .expect("pci bdf regex is valid")
+});
+static PCI_BDF_SHORT_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([0-9a-fA-F]{2}):([0-9a-fA-F]{2})\.([0-7])$")
+        .expect("pci bdf short regex is valid")
+});
+static PCI_BDF_VDEFN_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([0-9a-fA-F]{4}):([0-9a-fA-F]{2}):([0-9a-fA-F]{2})\.([0-7])@([0-9a-fA-F]{2})$")
+        .expect("pci bdf vdefn regex is valid")
+});
+
 #[derive(Clone)]
 pub struct XenPciBackend {
+    sysfs: PathBuf,
     path: PathBuf,
 }
 
@@ -24,9 +43,19 @@ impl Default for XenPciBackend {
 
 impl XenPciBackend {
     pub fn new() -> Self {
-        Self {
-            path: PathBuf::from(PCIBACK_SYSFS_PATH),
-        }
+        Self::with_sysfs_root(SYSFS_ROOT)
+    }
+
+    pub fn with_sysfs_root(sysfs: impl AsRef<Path>) -> Self {
+        let sysfs = sysfs.as_ref().to_path_buf();
+        let mut path = sysfs.clone();
+        path.push("bus/pci/drivers");
+        path.push(PCIBACK_DRIVER_NAME);
+        Self { sysfs, path }
+    }
+
+    pub fn sysfs_root(&self) -> &Path {
+        &self.sysfs
     }
 
     pub async fn is_loaded(&self) -> Result<bool> {
@@ -133,6 +162,104 @@ impl XenPciBackend {
         Ok(false)
     }
 
+    pub async fn current_driver(&self, bdf: &PciBdf) -> Result<Option<String>> {
+        let mut path = self.sysfs.clone();
+        path.push("bus/pci/devices");
+        path.push(sysfs_bdf(bdf).to_string());
+        path.push("driver");
+        if !fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        let driver = fs::read_link(&path).await?;
+        Ok(driver
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string()))
+    }
+
+    pub async fn read_device_info(&self, bdf: &PciBdf) -> Result<PciDeviceInfo> {
+        let mut path = self.sysfs.clone();
+        path.push("bus/pci/devices");
+        path.push(sysfs_bdf(bdf).to_string());
+
+        let config = fs::read(path.join("config")).await?;
+        let mut info = PciDeviceInfo::parse(*bdf, &config)?;
+
+        let reset_method_path = path.join("reset_method");
+        if fs::try_exists(&reset_method_path).await? {
+            info.reset_methods = fs::read_to_string(&reset_method_path)
+                .await?
+                .split_whitespace()
+                .map(|method| method.to_string())
+                .collect();
+        }
+        info.has_reset = fs::try_exists(path.join("reset")).await?;
+        info.driver = self.current_driver(bdf).await?;
+        Ok(info)
+    }
+
+    // binds the device to pciback, returning the host driver it was taken from.
+    pub async fn make_assignable(&self, bdf: &PciBdf) -> Result<Option<String>> {
+        let bdf = &sysfs_bdf(bdf);
+        let name = bdf.to_string();
+        let driver = self.current_driver(bdf).await?;
+        if driver.as_deref() == Some(PCIBACK_DRIVER_NAME) {
+            return Ok(None);
+        }
+
+        if let Some(driver) = driver.as_ref() {
+            let mut path = self.sysfs.clone();
+            path.push("bus/pci/drivers");
+            path.push(driver);
+            path.push("unbind");
+            fs::write(path, &name).await?;
+        }
+
+        if !self.has_slot(bdf).await? {
+            let mut path = self.path.clone();
+            path.push("new_slot");
+            fs::write(path, &name).await?;
+        }
+
+        let mut path = self.path.clone();
+        path.push("bind");
+        fs::write(path, &name).await?;
+
+        if !self.is_assigned(bdf).await? {
+            return Err(Error::PciDeviceNotAssignable(*bdf));
+        }
+        Ok(driver)
+    }
+
+    // reverses make_assignable, handing the device back to the given driver
+    // or letting the kernel probe for one.
+    pub async fn release(&self, bdf: &PciBdf, driver: Option<&str>) -> Result<()> {
+        let bdf = &sysfs_bdf(bdf);
+        let name = bdf.to_string();
+        if self.is_assigned(bdf).await? {
+            let mut path = self.path.clone();
+            path.push("unbind");
+            fs::write(path, &name).await?;
+        }
+
+        if self.has_slot(bdf).await? {
+            let mut path = self.path.clone();
+            path.push("remove_slot");
+            fs::write(path, &name).await?;
+        }
+
+        let mut path = self.sysfs.clone();
+        path.push("bus/pci");
+        if let Some(driver) = driver {
+            path.push("drivers");
+            path.push(driver);
+            path.push("bind");
+        } else {
+            path.push("drivers_probe");
+        }
+        fs::write(path, &name).await?;
+        Ok(())
+    }
+
     pub async fn reset(&self, bdf: &PciBdf) -> Result<()> {
         let mut path: PathBuf = self.path.clone();
         path.push(bdf.to_string());
@@ -142,6 +269,14 @@ impl XenPciBackend {
     }
 }
 
+pub(crate) fn sysfs_bdf(bdf: &PciBdf) -> PciBdf {
+    PciBdf {
+        domain: Some(bdf.domain.unwrap_or(0)),
+        vdefn: None,
+        ..*bdf
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct PciBdf {
     pub domain: Option<u32>,
@@ -191,11 +326,7 @@ impl FromStr for PciBdf {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let pci_bdf_regex = Regex::from_str(PCI_BDF_REGEX)?;
-        let pci_bdf_vdefn_regex = Regex::from_str(PCI_BDF_VDEFN_REGEX)?;
-        let pci_bdf_short_regex = Regex::from_str(PCI_BDF_SHORT_REGEX)?;
-
-        if let Some(pci_bdf_captures) = pci_bdf_regex.captures(s) {
+        if let Some(pci_bdf_captures) = PCI_BDF_REGEX.captures(s) {
             let domain = pci_bdf_captures
                 .get(1)
                 .ok_or_else(|| Error::GenericError("capture group 1 did not exist".to_string()))?;
@@ -215,7 +346,7 @@ impl FromStr for PciBdf {
             let function = u16::from_str_radix(function.as_str(), 16)?;
 
             Ok(PciBdf::new(Some(domain), bus, device, function, None))
-        } else if let Some(pci_bdf_vdefn_captures) = pci_bdf_vdefn_regex.captures(s) {
+        } else if let Some(pci_bdf_vdefn_captures) = PCI_BDF_VDEFN_REGEX.captures(s) {
             let domain = pci_bdf_vdefn_captures
                 .get(1)
                 .ok_or_else(|| Error::GenericError("capture group 1 did not exist".to_string()))?;
@@ -244,7 +375,7 @@ impl FromStr for PciBdf {
                 function,
                 Some(vdefn),
             ))
-        } else if let Some(pci_bdf_short_captures) = pci_bdf_short_regex.captures(s) {
+        } else if let Some(pci_bdf_short_captures) = PCI_BDF_SHORT_REGEX.captures(s) {
             let bus = pci_bdf_short_captures
                 .get(1)
                 .ok_or_else(|| Error::GenericError("capture group 1 did not exist".to_string()))?;
@@ -311,3 +442,105 @@ impl PciMemoryResource {
         (self.end - self.start) + 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, path::Path, str::FromStr};
+
+    use super::{PciBdf, XenPciBackend};
+
+    const DEVICE: &str = "0000:01:00.0";
+
+    // lays out the parts of sysfs that binding a device touches, with the
+    // device bound to host_driver and pciback loaded with no slots.
+    fn fake_sysfs(root: &Path, host_driver: &str) {
+        let drivers = root.join("bus/pci/drivers");
+        std::fs::create_dir_all(drivers.join(host_driver)).unwrap();
+        std::fs::create_dir_all(drivers.join("pciback")).unwrap();
+        std::fs::write(drivers.join("pciback/slots"), "").unwrap();
+        let device = root.join("bus/pci/devices").join(DEVICE);
+        std::fs::create_dir_all(&device).unwrap();
+        symlink(drivers.join(host_driver), device.join("driver")).unwrap();
+    }
+
+    fn read(root: &Path, path: &str) -> String {
+        std::fs::read_to_string(root.join(path)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn make_assignable_moves_the_device_to_pciback() {
+        let root = tempfile::tempdir().unwrap();
+        fake_sysfs(root.path(), "e1000e");
+        // the kernel creates this once the bind below succeeds.
+        std::fs::create_dir_all(root.path().join("bus/pci/drivers/pciback").join(DEVICE)).unwrap();
+
+        let backend = XenPciBackend::with_sysfs_root(root.path());
+        let bdf = PciBdf::from_str("01:00.0").unwrap();
+        let driver = backend.make_assignable(&bdf).await.unwrap();
+        assert_eq!(driver.as_deref(), Some("e1000e"));
+        assert_eq!(read(root.path(), "bus/pci/drivers/e1000e/unbind"), DEVICE);
+        assert_eq!(
+            read(root.path(), "bus/pci/drivers/pciback/new_slot"),
+            DEVICE
+        );
+        assert_eq!(read(root.path(), "bus/pci/drivers/pciback/bind"), DEVICE);
+    }
+
+    #[tokio::test]
+    async fn make_assignable_fails_when_pciback_does_not_take_the_device() {
+        let root = tempfile::tempdir().unwrap();
+        fake_sysfs(root.path(), "e1000e");
+
+        let backend = XenPciBackend::with_sysfs_root(root.path());
+        let bdf = PciBdf::from_str(DEVICE).unwrap();
+        assert!(backend.make_assignable(&bdf).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn make_assignable_leaves_pciback_devices_alone() {
+        let root = tempfile::tempdir().unwrap();
+        fake_sysfs(root.path(), "pciback");
+
+        let backend = XenPciBackend::with_sysfs_root(root.path());
+        let bdf = PciBdf::from_str(DEVICE).unwrap();
+        assert_eq!(backend.make_assignable(&bdf).await.unwrap(), None);
+        assert!(!root.path().join("bus/pci/drivers/pciback/bind").exists());
+    }
+
+    #[tokio::test]
+    async fn release_hands_the_device_back() {
+        let root = tempfile::tempdir().unwrap();
+        fake_sysfs(root.path(), "e1000e");
+        let pciback = root.path().join("bus/pci/drivers/pciback");
+        std::fs::create_dir_all(pciback.join(DEVICE)).unwrap();
+        std::fs::write(pciback.join("slots"), format!("{}\n", DEVICE)).unwrap();
+
+        let backend = XenPciBackend::with_sysfs_root(root.path());
+        let bdf = PciBdf::from_str(DEVICE).unwrap();
+        backend.release(&bdf, Some("e1000e")).await.unwrap();
+        assert_eq!(read(root.path(), "bus/pci/drivers/pciback/unbind"), DEVICE);
+        assert_eq!(
+            read(root.path(), "bus/pci/drivers/pciback/remove_slot"),
+            DEVICE
+        );
+        assert_eq!(read(root.path(), "bus/pci/drivers/e1000e/bind"), DEVICE);
+
+        backend.release(&bdf, None).await.unwrap();
+        assert_eq!(read(root.path(), "bus/pci/drivers_probe"), DEVICE);
+    }
+
+    #[test]
+    fn parses_bdfs_in_either_case() {
+        let bdf = PciBdf::from_str("0000:0A:1f.7").unwrap();
+        assert_eq!(bdf, PciBdf::from_str("0000:0a:1F.7").unwrap());
+        assert_eq!((bdf.bus, bdf.device, bdf.function), (0x0a, 0x1f, 7));
+        assert_eq!(PciBdf::from_str("0A:00.1").unwrap().bus, 0x0a);
+        assert_eq!(
+            PciBdf::from_str("0000:0a:00.0@1F").unwrap().vdefn,
+            Some(0x1f)
+        );
+        for bdf in ["0000:0a:00.8", "0000:0a:00x0", "0a:00.f"] {
+            assert!(PciBdf::from_str(bdf).is_err(), "{}", bdf);
+        }
+    }
+}
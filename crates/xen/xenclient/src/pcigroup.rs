@@ -0,0 +1,486 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use log::debug;
+use tokio::fs;
+use xencall::XenCall;
+
+use crate::{
+    error::{Error, Result},
+    pci::{sysfs_bdf, PciBdf, XenPciBackend},
+    pciinfo::PciDeviceInfo,
+};
+
+const SYSFS_ROOT: &str = "/sys";
+const MAX_DEVICE_GROUP_SIZE: u32 = 256;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PciGroupIssue {
+    SharedIommuGroup {
+        device: PciBdf,
+        group: u32,
+        sibling: PciBdf,
+        driver: Option<String>,
+    },
+    SharedDeviceGroup {
+        device: PciBdf,
+        sibling: PciBdf,
+    },
+    MultifunctionSibling {
+        device: PciBdf,
+        sibling: PciBdf,
+        driver: Option<String>,
+    },
+}
+
+impl PciGroupIssue {
+    pub fn device(&self) -> &PciBdf {
+        match self {
+            PciGroupIssue::SharedIommuGroup { device, .. } => device,
+            PciGroupIssue::SharedDeviceGroup { device, .. } => device,
+            PciGroupIssue::MultifunctionSibling { device, .. } => device,
+        }
+    }
+
+    pub fn sibling(&self) -> &PciBdf {
+        match self {
+            PciGroupIssue::SharedIommuGroup { sibling, .. } => sibling,
+            PciGroupIssue::SharedDeviceGroup { sibling, .. } => sibling,
+            PciGroupIssue::MultifunctionSibling { sibling, .. } => sibling,
+        }
+    }
+}
+
+impl Display for PciGroupIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PciGroupIssue::SharedIommuGroup {
+                device,
+                group,
+                sibling,
+                driver,
+            } => write!(
+                f,
+                "{} shares iommu group {} with {} ({}), which is not being assigned",
+                device,
+                group,
+                sibling,
+                describe_driver(driver)
+            ),
+
+            PciGroupIssue::SharedDeviceGroup { device, sibling } => write!(
+                f,
+                "{} shares a hypervisor device group with {}, which is not being assigned",
+                device, sibling
+            ),
+
+            PciGroupIssue::MultifunctionSibling {
+                device,
+                sibling,
+                driver,
+            } => write!(
+                f,
+                "{} is a function of a multifunction device, but function {} ({}) is not being assigned",
+                device,
+                sibling,
+                describe_driver(driver)
+            ),
+        }
+    }
+}
+
+fn describe_driver(driver: &Option<String>) -> String {
+    match driver {
+        Some(driver) => format!("bound to {}", driver),
+        None => "not bound to a driver".to_string(),
+    }
+}
+
+#[derive(Clone)]
+pub struct PciGroupValidator {
+    sysfs: PathBuf,
+    backend: XenPciBackend,
+    call: Option<XenCall>,
+}
+
+impl Default for PciGroupValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PciGroupValidator {
+    pub fn new() -> Self {
+        Self::with_sysfs_root(SYSFS_ROOT)
+    }
+
+    pub fn with_sysfs_root(sysfs: impl AsRef<Path>) -> Self {
+        Self {
+            sysfs: sysfs.as_ref().to_path_buf(),
+            backend: XenPciBackend::with_sysfs_root(sysfs),
+            call: None,
+        }
+    }
+
+    pub fn hypervisor(&mut self, call: XenCall) -> &mut Self {
+        self.call = Some(call);
+        self
+    }
+
+    fn device_path(&self, bdf: &PciBdf) -> PathBuf {
+        let mut path = self.sysfs.clone();
+        path.push("bus/pci/devices");
+        path.push(sysfs_bdf(bdf).to_string());
+        path
+    }
+
+    pub async fn iommu_group(&self, bdf: &PciBdf) -> Result<Option<u32>> {
+        let mut path = self.device_path(bdf);
+        path.push("iommu_group");
+        if !fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        let group = fs::read_link(&path).await?;
+        let Some(group) = group.file_name() else {
+            return Ok(None);
+        };
+        Ok(u32::from_str(&group.to_string_lossy()).ok())
+    }
+
+    pub async fn iommu_group_devices(&self, group: u32) -> Result<Vec<PciBdf>> {
+        let mut path = self.sysfs.clone();
+        path.push("kernel/iommu_groups");
+        path.push(group.to_string());
+        path.push("devices");
+        list_bdfs(&path).await
+    }
+
+    // the functions of the device bdf belongs to, when function 0 flags it as
+    // multifunction. virtual functions share slots without being functions of
+    // one device, so they only ever stand for themselves. absent devices have
+    // no functions.
+    pub async fn functions(&self, bdf: &PciBdf) -> Result<Vec<PciBdf>> {
+        let bdf = sysfs_bdf(bdf);
+        if !fs::try_exists(self.device_path(&bdf)).await? {
+            return Ok(Vec::new());
+        }
+        if self.is_virtual_function(&bdf).await? || !self.is_multifunction(&bdf).await? {
+            return Ok(vec![bdf]);
+        }
+
+        let mut path = self.sysfs.clone();
+        path.push("bus/pci/devices");
+        let mut functions = Vec::new();
+        for other in list_bdfs(&path).await? {
+            if other.domain == bdf.domain
+                && other.bus == bdf.bus
+                && other.device == bdf.device
+                && !self.is_virtual_function(&other).await?
+            {
+                functions.push(other);
+            }
+        }
+        Ok(functions)
+    }
+
+    async fn is_virtual_function(&self, bdf: &PciBdf) -> Result<bool> {
+        let mut path = self.device_path(bdf);
+        path.push("physfn");
+        Ok(fs::try_exists(&path).await?)
+    }
+
+    // the parsed config space header, when the device has a readable one.
+    async fn device_info(&self, bdf: &PciBdf) -> Result<Option<PciDeviceInfo>> {
+        let mut path = self.device_path(bdf);
+        path.push("config");
+        if !fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        let config = fs::read(&path).await?;
+        Ok(PciDeviceInfo::parse(*bdf, &config).ok())
+    }
+
+    async fn is_multifunction(&self, bdf: &PciBdf) -> Result<bool> {
+        let function = PciBdf {
+            function: 0,
+            ..*bdf
+        };
+        Ok(self
+            .device_info(&function)
+            .await?
+            .is_some_and(|info| info.multifunction))
+    }
+
+    async fn is_bridge(&self, bdf: &PciBdf) -> Result<bool> {
+        Ok(self
+            .device_info(bdf)
+            .await?
+            .is_some_and(|info| info.is_bridge()))
+    }
+
+    async fn device_group(&self, domid: u32, bdf: &PciBdf) -> Option<Vec<PciBdf>> {
+        let call = self.call.as_ref()?;
+        let bdf = sysfs_bdf(bdf);
+        let sdevs = match call
+            .get_device_group(domid, bdf.encode(), MAX_DEVICE_GROUP_SIZE)
+            .await
+        {
+            Ok(sdevs) => sdevs,
+            Err(error) => {
+                debug!(
+                    "unable to read hypervisor device group of {}: {}",
+                    bdf, error
+                );
+                return None;
+            }
+        };
+        Some(
+            sdevs
+                .into_iter()
+                .map(|sdev| {
+                    let devfn = (sdev >> 8) & 0xff;
+                    PciBdf::new(
+                        bdf.domain,
+                        ((sdev >> 16) & 0xff) as u16,
+                        (devfn >> 3) as u16,
+                        (devfn & 0x7) as u16,
+                        None,
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    pub async fn check(&self, domid: u32, devices: &[PciBdf]) -> Result<Vec<PciGroupIssue>> {
+        let devices = devices.iter().map(sysfs_bdf).collect::<Vec<_>>();
+        let mut issues: Vec<PciGroupIssue> = Vec::new();
+        let mut push = |issue: PciGroupIssue| {
+            if !issues
+                .iter()
+                .any(|other| other.sibling() == issue.sibling())
+            {
+                issues.push(issue);
+            }
+        };
+
+        for device in &devices {
+            if let Some(group) = self.iommu_group(device).await? {
+                for sibling in self.iommu_group_devices(group).await? {
+                    if devices.contains(&sibling) || self.is_bridge(&sibling).await? {
+                        continue;
+                    }
+                    push(PciGroupIssue::SharedIommuGroup {
+                        device: *device,
+                        group,
+                        sibling,
+                        driver: self.backend.current_driver(&sibling).await?,
+                    });
+                }
+            }
+
+            for sibling in self.functions(device).await? {
+                if devices.contains(&sibling) {
+                    continue;
+                }
+                push(PciGroupIssue::MultifunctionSibling {
+                    device: *device,
+                    sibling,
+                    driver: self.backend.current_driver(&sibling).await?,
+                });
+            }
+
+            if let Some(group) = self.device_group(domid, device).await {
+                for sibling in group {
+                    if devices.contains(&sibling) || self.is_bridge(&sibling).await? {
+                        continue;
+                    }
+                    push(PciGroupIssue::SharedDeviceGroup {
+                        device: *device,
+                        sibling,
+                    });
+                }
+            }
+        }
+        Ok(issues)
+    }
+
+    pub async fn validate(&self, domid: u32, devices: &[PciBdf]) -> Result<()> {
+        let issues = self.check(domid, devices).await?;
+        if issues.is_empty() {
+            return Ok(());
+        }
+        Err(Error::UnsafePciAssignment(
+            issues
+                .iter()
+                .map(|issue| issue.to_string())
+                .collect::<Vec<_>>()
+                .join("; "),
+        ))
+    }
+
+    // returns the siblings that need to be assigned alongside the given devices.
+    pub async fn missing_siblings(&self, domid: u32, devices: &[PciBdf]) -> Result<Vec<PciBdf>> {
+        let mut missing = Vec::new();
+        let mut devices = devices.to_vec();
+        loop {
+            let issues = self.check(domid, &devices).await?;
+            if issues.is_empty() {
+                return Ok(missing);
+            }
+            for issue in issues {
+                missing.push(*issue.sibling());
+                devices.push(*issue.sibling());
+            }
+        }
+    }
+}
+
+async fn list_bdfs(path: &Path) -> Result<Vec<PciBdf>> {
+    let mut devices = Vec::new();
+    if !fs::try_exists(path).await? {
+        return Ok(devices);
+    }
+    let mut dir = fs::read_dir(path).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let Ok(bdf) = PciBdf::from_str(&entry.file_name().to_string_lossy()) else {
+            continue;
+        };
+        devices.push(bdf);
+    }
+    devices.sort_by_key(|bdf| bdf.encode());
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, path::Path, str::FromStr};
+
+    use super::{PciGroupIssue, PciGroupValidator};
+    use crate::pci::PciBdf;
+
+    fn add_device(root: &Path, bdf: &str, header_type: u8, physfn: Option<&str>) {
+        let devices = root.join("bus/pci/devices");
+        let path = devices.join(bdf);
+        std::fs::create_dir_all(&path).unwrap();
+        let mut config = vec![0u8; 64];
+        config[0] = 0x86;
+        config[1] = 0x80;
+        config[0x0e] = header_type;
+        std::fs::write(path.join("config"), config).unwrap();
+        if let Some(physfn) = physfn {
+            symlink(devices.join(physfn), path.join("physfn")).unwrap();
+        }
+    }
+
+    async fn functions(root: &Path, bdf: &str) -> Vec<String> {
+        PciGroupValidator::with_sysfs_root(root)
+            .functions(&PciBdf::from_str(bdf).unwrap())
+            .await
+            .unwrap()
+            .iter()
+            .map(|function| function.to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn multifunction_devices_list_their_functions() {
+        let root = tempfile::tempdir().unwrap();
+        add_device(root.path(), "0000:01:00.0", 0x80, None);
+        add_device(root.path(), "0000:01:00.1", 0x00, None);
+        add_device(root.path(), "0000:02:00.0", 0x00, None);
+        assert_eq!(
+            functions(root.path(), "0000:01:00.1").await,
+            ["0000:01:00.0", "0000:01:00.1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn single_function_devices_have_no_siblings() {
+        let root = tempfile::tempdir().unwrap();
+        add_device(root.path(), "0000:01:00.0", 0x00, None);
+        add_device(root.path(), "0000:01:00.1", 0x00, None);
+        assert_eq!(
+            functions(root.path(), "0000:01:00.0").await,
+            ["0000:01:00.0"]
+        );
+    }
+
+    #[tokio::test]
+    async fn virtual_functions_are_never_siblings() {
+        let root = tempfile::tempdir().unwrap();
+        add_device(root.path(), "0000:01:00.0", 0x80, None);
+        add_device(root.path(), "0000:01:00.1", 0x00, None);
+        add_device(root.path(), "0000:01:00.2", 0x00, Some("0000:01:00.0"));
+        add_device(root.path(), "0000:01:00.3", 0x00, Some("0000:01:00.0"));
+        assert_eq!(
+            functions(root.path(), "0000:01:00.2").await,
+            ["0000:01:00.2"]
+        );
+        assert_eq!(
+            functions(root.path(), "0000:01:00.0").await,
+            ["0000:01:00.0", "0000:01:00.1"]
+        );
+        assert!(PciGroupValidator::with_sysfs_root(root.path())
+            .check(0, &[PciBdf::from_str("0000:01:00.3").unwrap()])
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    fn add_iommu_group(root: &Path, group: u32, bdfs: &[&str]) {
+        let path = root.join(format!("kernel/iommu_groups/{}/devices", group));
+        std::fs::create_dir_all(&path).unwrap();
+        for bdf in bdfs {
+            let device = root.join("bus/pci/devices").join(bdf);
+            symlink(&device, path.join(bdf)).unwrap();
+            symlink(
+                root.join(format!("kernel/iommu_groups/{}", group)),
+                device.join("iommu_group"),
+            )
+            .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn iommu_group_siblings_skip_bridges() {
+        let root = tempfile::tempdir().unwrap();
+        add_device(root.path(), "0000:00:01.0", 0x01, None);
+        // class 0x0604, a pci to pci bridge.
+        let bridge = root.path().join("bus/pci/devices/0000:00:01.0/config");
+        let mut config = std::fs::read(&bridge).unwrap();
+        config[0x0a] = 0x04;
+        config[0x0b] = 0x06;
+        std::fs::write(&bridge, config).unwrap();
+        add_device(root.path(), "0000:01:00.0", 0x00, None);
+        add_device(root.path(), "0000:02:00.0", 0x00, None);
+        let driver = root.path().join("bus/pci/drivers/nvme");
+        std::fs::create_dir_all(&driver).unwrap();
+        symlink(
+            &driver,
+            root.path().join("bus/pci/devices/0000:02:00.0/driver"),
+        )
+        .unwrap();
+        add_iommu_group(
+            root.path(),
+            7,
+            &["0000:00:01.0", "0000:01:00.0", "0000:02:00.0"],
+        );
+
+        let issues = PciGroupValidator::with_sysfs_root(root.path())
+            .check(0, &[PciBdf::from_str("0000:01:00.0").unwrap()])
+            .await
+            .unwrap();
+        assert_eq!(
+            issues,
+            [PciGroupIssue::SharedIommuGroup {
+                device: PciBdf::from_str("0000:01:00.0").unwrap(),
+                group: 7,
+                sibling: PciBdf::from_str("0000:02:00.0").unwrap(),
+                driver: Some("nvme".to_string()),
+            }]
+        );
+    }
+}
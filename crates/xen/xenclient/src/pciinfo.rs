@@ -0,0 +1,468 @@
+use crate::{
+    error::{Error, Result},
+    pci::PciBdf,
+};
+
+const PCI_VENDOR_ID: usize = 0x00;
+const PCI_DEVICE_ID: usize = 0x02;
+const PCI_STATUS: usize = 0x06;
+const PCI_REVISION_ID: usize = 0x08;
+const PCI_CLASS_PROG: usize = 0x09;
+const PCI_HEADER_TYPE: usize = 0x0e;
+const PCI_SUBSYSTEM_VENDOR_ID: usize = 0x2c;
+const PCI_SUBSYSTEM_ID: usize = 0x2e;
+const PCI_CAPABILITY_LIST: usize = 0x34;
+const PCI_CB_CAPABILITY_LIST: usize = 0x14;
+const PCI_STATUS_CAP_LIST: u16 = 0x10;
+const PCI_HEADER_TYPE_MASK: u8 = 0x7f;
+const PCI_HEADER_TYPE_MULTIFUNCTION: u8 = 0x80;
+const PCI_HEADER_TYPE_CARDBUS: u8 = 2;
+const PCI_STD_HEADER_SIZE: usize = 0x40;
+const PCI_EXT_CAP_START: usize = 0x100;
+const PCI_CONFIG_SPACE_SIZE: usize = 0x1000;
+
+// bounds the number of capabilities walked, in case the list loops.
+const PCI_CAP_MAX: usize = 48;
+const PCI_EXT_CAP_MAX: usize = (PCI_CONFIG_SPACE_SIZE - PCI_EXT_CAP_START) / 8;
+
+pub const PCI_CAP_ID_PM: u8 = 0x01;
+pub const PCI_CAP_ID_MSI: u8 = 0x05;
+pub const PCI_CAP_ID_EXP: u8 = 0x10;
+pub const PCI_CAP_ID_MSIX: u8 = 0x11;
+pub const PCI_CAP_ID_AF: u8 = 0x13;
+pub const PCI_EXT_CAP_ID_SRIOV: u16 = 0x10;
+
+const PCI_MSI_FLAGS_64BIT: u16 = 0x80;
+const PCI_MSI_FLAGS_MASKBIT: u16 = 0x100;
+const PCI_MSIX_FLAGS_QSIZE: u16 = 0x7ff;
+const PCI_MSIX_TABLE_BIR: u32 = 0x7;
+const PCI_EXP_DEVCAP_FLR: u32 = 1 << 28;
+const PCI_PM_CTRL_NO_SOFT_RESET: u16 = 0x08;
+const PCI_AF_CAP_FLR: u8 = 0x02;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PciCapability {
+    pub id: u8,
+    pub offset: u16,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PciExtendedCapability {
+    pub id: u16,
+    pub version: u8,
+    pub offset: u16,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PciMsiCapability {
+    pub offset: u16,
+    pub vectors: u32,
+    pub is_64bit: bool,
+    pub per_vector_masking: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PciMsixCapability {
+    pub offset: u16,
+    pub table_size: u16,
+    pub table_bir: u8,
+    pub table_offset: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PciExpressCapability {
+    pub offset: u16,
+    pub device_type: u8,
+    pub flr: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PciPowerManagementCapability {
+    pub offset: u16,
+    pub version: u8,
+    pub no_soft_reset: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PciSriovCapability {
+    pub offset: u16,
+    pub initial_vfs: u16,
+    pub total_vfs: u16,
+}
+
+#[derive(Clone, Debug)]
+pub struct PciDeviceInfo {
+    pub bdf: PciBdf,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub subsystem_vendor_id: u16,
+    pub subsystem_id: u16,
+    pub revision: u8,
+    pub class: u32,
+    pub header_type: u8,
+    pub multifunction: bool,
+    pub capabilities: Vec<PciCapability>,
+    pub extended_capabilities: Vec<PciExtendedCapability>,
+    pub msi: Option<PciMsiCapability>,
+    pub msix: Option<PciMsixCapability>,
+    pub express: Option<PciExpressCapability>,
+    pub power_management: Option<PciPowerManagementCapability>,
+    pub sriov: Option<PciSriovCapability>,
+    pub af_flr: bool,
+    pub reset_methods: Vec<String>,
+    pub has_reset: bool,
+    pub driver: Option<String>,
+}
+
+impl PciDeviceInfo {
+    pub fn parse(bdf: PciBdf, config: &[u8]) -> Result<PciDeviceInfo> {
+        if config.len() < PCI_STD_HEADER_SIZE {
+            return Err(Error::InvalidPciConfigSpace(
+                bdf,
+                format!("config space is only {} bytes", config.len()),
+            ));
+        }
+
+        let vendor_id = read_u16(config, PCI_VENDOR_ID);
+        if vendor_id == 0xffff {
+            return Err(Error::InvalidPciConfigSpace(
+                bdf,
+                "device is not present".to_string(),
+            ));
+        }
+
+        let header_type = config[PCI_HEADER_TYPE];
+        let class = (config[PCI_CLASS_PROG] as u32)
+            | ((config[PCI_CLASS_PROG + 1] as u32) << 8)
+            | ((config[PCI_CLASS_PROG + 2] as u32) << 16);
+
+        let mut info = PciDeviceInfo {
+            bdf,
+            vendor_id,
+            device_id: read_u16(config, PCI_DEVICE_ID),
+            subsystem_vendor_id: read_u16(config, PCI_SUBSYSTEM_VENDOR_ID),
+            subsystem_id: read_u16(config, PCI_SUBSYSTEM_ID),
+            revision: config[PCI_REVISION_ID],
+            class,
+            header_type: header_type & PCI_HEADER_TYPE_MASK,
+            multifunction: header_type & PCI_HEADER_TYPE_MULTIFUNCTION != 0,
+            capabilities: Vec::new(),
+            extended_capabilities: Vec::new(),
+            msi: None,
+            msix: None,
+            express: None,
+            power_management: None,
+            sriov: None,
+            af_flr: false,
+            reset_methods: Vec::new(),
+            has_reset: false,
+            driver: None,
+        };
+
+        if read_u16(config, PCI_STATUS) & PCI_STATUS_CAP_LIST != 0 {
+            info.parse_capabilities(config);
+        }
+
+        if info.express.is_some() && config.len() > PCI_EXT_CAP_START {
+            info.parse_extended_capabilities(config);
+        }
+        Ok(info)
+    }
+
+    fn parse_capabilities(&mut self, config: &[u8]) {
+        let list = if self.header_type == PCI_HEADER_TYPE_CARDBUS {
+            PCI_CB_CAPABILITY_LIST
+        } else {
+            PCI_CAPABILITY_LIST
+        };
+        let mut offset = (config[list] & !0x3) as usize;
+        for _ in 0..PCI_CAP_MAX {
+            if offset < PCI_STD_HEADER_SIZE || offset + 8 > config.len() {
+                break;
+            }
+
+            let id = config[offset];
+            let next = (config[offset + 1] & !0x3) as usize;
+            self.capabilities.push(PciCapability {
+                id,
+                offset: offset as u16,
+            });
+
+            match id {
+                PCI_CAP_ID_PM => {
+                    self.power_management = Some(PciPowerManagementCapability {
+                        offset: offset as u16,
+                        version: (read_u16(config, offset + 2) & 0x7) as u8,
+                        no_soft_reset: read_u16(config, offset + 4) & PCI_PM_CTRL_NO_SOFT_RESET
+                            != 0,
+                    });
+                }
+
+                PCI_CAP_ID_MSI => {
+                    let flags = read_u16(config, offset + 2);
+                    self.msi = Some(PciMsiCapability {
+                        offset: offset as u16,
+                        vectors: 1 << ((flags >> 1) & 0x7).min(5),
+                        is_64bit: flags & PCI_MSI_FLAGS_64BIT != 0,
+                        per_vector_masking: flags & PCI_MSI_FLAGS_MASKBIT != 0,
+                    });
+                }
+
+                PCI_CAP_ID_EXP => {
+                    self.express = Some(PciExpressCapability {
+                        offset: offset as u16,
+                        device_type: ((read_u16(config, offset + 2) >> 4) & 0xf) as u8,
+                        flr: read_u32(config, offset + 4) & PCI_EXP_DEVCAP_FLR != 0,
+                    });
+                }
+
+                PCI_CAP_ID_MSIX => {
+                    let table = read_u32(config, offset + 4);
+                    self.msix = Some(PciMsixCapability {
+                        offset: offset as u16,
+                        table_size: (read_u16(config, offset + 2) & PCI_MSIX_FLAGS_QSIZE) + 1,
+                        table_bir: (table & PCI_MSIX_TABLE_BIR) as u8,
+                        table_offset: table & !PCI_MSIX_TABLE_BIR,
+                    });
+                }
+
+                PCI_CAP_ID_AF => {
+                    self.af_flr = config[offset + 3] & PCI_AF_CAP_FLR != 0;
+                }
+
+                _ => {}
+            }
+
+            if next == 0 {
+                break;
+            }
+            offset = next;
+        }
+    }
+
+    fn parse_extended_capabilities(&mut self, config: &[u8]) {
+        let mut offset = PCI_EXT_CAP_START;
+        for _ in 0..PCI_EXT_CAP_MAX {
+            if offset < PCI_EXT_CAP_START || offset + 16 > config.len() {
+                break;
+            }
+
+            let header = read_u32(config, offset);
+            if header == 0 || header == 0xffffffff {
+                break;
+            }
+
+            let id = (header & 0xffff) as u16;
+            let next = ((header >> 20) & 0xffc) as usize;
+            self.extended_capabilities.push(PciExtendedCapability {
+                id,
+                version: ((header >> 16) & 0xf) as u8,
+                offset: offset as u16,
+            });
+
+            if id == PCI_EXT_CAP_ID_SRIOV {
+                self.sriov = Some(PciSriovCapability {
+                    offset: offset as u16,
+                    initial_vfs: read_u16(config, offset + 0x0c),
+                    total_vfs: read_u16(config, offset + 0x0e),
+                });
+            }
+
+            if next == 0 {
+                break;
+            }
+            offset = next;
+        }
+    }
+
+    pub fn capability(&self, id: u8) -> Option<&PciCapability> {
+        self.capabilities.iter().find(|cap| cap.id == id)
+    }
+
+    pub fn extended_capability(&self, id: u16) -> Option<&PciExtendedCapability> {
+        self.extended_capabilities.iter().find(|cap| cap.id == id)
+    }
+
+    pub fn supports_flr(&self) -> bool {
+        self.express.map(|express| express.flr).unwrap_or(false)
+            || self.af_flr
+            || self
+                .reset_methods
+                .iter()
+                .any(|method| method == "flr" || method == "af_flr")
+    }
+
+    pub fn supports_pm_reset(&self) -> bool {
+        self.power_management
+            .map(|pm| !pm.no_soft_reset)
+            .unwrap_or(false)
+    }
+
+    // whether the kernel has a way to reset the device between zones.
+    pub fn can_reset(&self) -> bool {
+        !self.reset_methods.is_empty()
+            || self.has_reset
+            || self.supports_flr()
+            || self.supports_pm_reset()
+    }
+
+    pub fn is_bridge(&self) -> bool {
+        (self.class >> 16) == 0x06
+    }
+}
+
+fn read_u16(config: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([config[offset], config[offset + 1]])
+}
+
+fn read_u32(config: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        config[offset],
+        config[offset + 1],
+        config[offset + 2],
+        config[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{
+        PciDeviceInfo, PCI_CAP_ID_EXP, PCI_CAP_ID_MSI, PCI_CAP_ID_PM, PCI_EXT_CAP_ID_SRIOV,
+    };
+    use crate::pci::PciBdf;
+
+    fn bdf() -> PciBdf {
+        PciBdf::from_str("0000:01:00.0").unwrap()
+    }
+
+    // a type 0 header for an ethernet controller, with the capability list
+    // starting at first_cap when it is non-zero.
+    fn header(size: usize, first_cap: u8) -> Vec<u8> {
+        let mut config = vec![0u8; size];
+        config[0x00..0x02].copy_from_slice(&0x8086u16.to_le_bytes());
+        config[0x02..0x04].copy_from_slice(&0x10d3u16.to_le_bytes());
+        config[0x08] = 0x02;
+        config[0x0b] = 0x02;
+        config[0x2c..0x2e].copy_from_slice(&0x8086u16.to_le_bytes());
+        config[0x2e..0x30].copy_from_slice(&0xa01fu16.to_le_bytes());
+        if first_cap != 0 {
+            config[0x06] = 0x10;
+            config[0x34] = first_cap;
+        }
+        config
+    }
+
+    fn capability(config: &mut [u8], offset: usize, id: u8, next: u8) {
+        config[offset] = id;
+        config[offset + 1] = next;
+    }
+
+    #[test]
+    fn parses_the_header() {
+        let mut config = header(64, 0);
+        config[0x0e] = 0x80;
+        let info = PciDeviceInfo::parse(bdf(), &config).unwrap();
+        assert_eq!(info.vendor_id, 0x8086);
+        assert_eq!(info.device_id, 0x10d3);
+        assert_eq!(info.subsystem_id, 0xa01f);
+        assert_eq!(info.revision, 0x02);
+        assert_eq!(info.class, 0x020000);
+        assert_eq!(info.header_type, 0);
+        assert!(info.multifunction);
+        assert!(info.capabilities.is_empty());
+        assert!(!info.can_reset());
+    }
+
+    #[test]
+    fn rejects_truncated_config_space() {
+        assert!(PciDeviceInfo::parse(bdf(), &header(64, 0)[..63]).is_err());
+        assert!(PciDeviceInfo::parse(bdf(), &[]).is_err());
+        let mut absent = header(64, 0);
+        absent[0x00..0x02].copy_from_slice(&0xffffu16.to_le_bytes());
+        assert!(PciDeviceInfo::parse(bdf(), &absent).is_err());
+    }
+
+    #[test]
+    fn stops_at_capabilities_past_the_end() {
+        // the list points beyond the 64 bytes an unprivileged read returns.
+        let info = PciDeviceInfo::parse(bdf(), &header(64, 0x40)).unwrap();
+        assert!(info.capabilities.is_empty());
+
+        let mut config = header(256, 0x40);
+        capability(&mut config, 0x40, PCI_CAP_ID_PM, 0xfc);
+        let info = PciDeviceInfo::parse(bdf(), &config).unwrap();
+        assert_eq!(info.capabilities.len(), 1);
+    }
+
+    #[test]
+    fn stops_walking_a_looping_capability_list() {
+        let mut config = header(256, 0x40);
+        capability(&mut config, 0x40, PCI_CAP_ID_PM, 0x50);
+        capability(&mut config, 0x50, PCI_CAP_ID_MSI, 0x40);
+        let info = PciDeviceInfo::parse(bdf(), &config).unwrap();
+        assert_eq!(info.capabilities.len(), 48);
+        assert!(info.power_management.is_some());
+        assert!(info.msi.is_some());
+    }
+
+    #[test]
+    fn parses_msi_and_power_management() {
+        let mut config = header(256, 0x40);
+        capability(&mut config, 0x40, PCI_CAP_ID_PM, 0x50);
+        config[0x42..0x44].copy_from_slice(&0x0003u16.to_le_bytes());
+        capability(&mut config, 0x50, PCI_CAP_ID_MSI, 0);
+        // 8 vectors requested, 64-bit addresses and per-vector masking.
+        config[0x52..0x54].copy_from_slice(&(0x0100u16 | 0x0080 | (3 << 1)).to_le_bytes());
+
+        let info = PciDeviceInfo::parse(bdf(), &config).unwrap();
+        assert_eq!(info.capability(PCI_CAP_ID_PM).unwrap().offset, 0x40);
+        let pm = info.power_management.unwrap();
+        assert_eq!(pm.version, 3);
+        assert!(!pm.no_soft_reset);
+        assert!(info.supports_pm_reset());
+        assert!(info.can_reset());
+
+        let msi = info.msi.unwrap();
+        assert_eq!(msi.offset, 0x50);
+        assert_eq!(msi.vectors, 8);
+        assert!(msi.is_64bit);
+        assert!(msi.per_vector_masking);
+        assert!(info.msix.is_none());
+    }
+
+    #[test]
+    fn no_soft_reset_rules_out_pm_reset() {
+        let mut config = header(256, 0x40);
+        capability(&mut config, 0x40, PCI_CAP_ID_PM, 0);
+        config[0x44] = 0x08;
+        let info = PciDeviceInfo::parse(bdf(), &config).unwrap();
+        assert!(!info.supports_pm_reset());
+        assert!(!info.can_reset());
+    }
+
+    #[test]
+    fn parses_express_flr_and_sriov() {
+        let mut config = header(4096, 0x40);
+        capability(&mut config, 0x40, PCI_CAP_ID_EXP, 0);
+        config[0x44..0x48].copy_from_slice(&(1u32 << 28).to_le_bytes());
+        config[0x100..0x104]
+            .copy_from_slice(&(PCI_EXT_CAP_ID_SRIOV as u32 | 1 << 16).to_le_bytes());
+        config[0x10c..0x10e].copy_from_slice(&8u16.to_le_bytes());
+        config[0x10e..0x110].copy_from_slice(&64u16.to_le_bytes());
+
+        let info = PciDeviceInfo::parse(bdf(), &config).unwrap();
+        assert!(info.supports_flr());
+        let sriov = info.sriov.unwrap();
+        assert_eq!(sriov.offset, 0x100);
+        assert_eq!(sriov.initial_vfs, 8);
+        assert_eq!(sriov.total_vfs, 64);
+        assert_eq!(
+            info.extended_capability(PCI_EXT_CAP_ID_SRIOV)
+                .unwrap()
+                .version,
+            1
+        );
+    }
+}
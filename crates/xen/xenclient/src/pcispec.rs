@@ -0,0 +1,192 @@
+use std::{str::FromStr, sync::LazyLock};
+
+use regex::Regex;
+
+use crate::{
+    error::{Error, Result},
+    pci::{PciBdf, XenPciBackend},
+    pcigroup::PciGroupValidator,
+    tx::pci::{PciDeviceConfig, PciRdmReservePolicy},
+};
+
+static PCI_SPEC_BDF_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:([0-9a-fA-F]{4}):)?([0-9a-fA-F]{2}):([0-9a-fA-F]{2})\.([0-7]|\*)(?:@([0-9a-fA-F]{1,2}))?$")
+        .expect("pci spec regex is valid")
+});
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PciSpecFunction {
+    Single(u16),
+    All,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PciSpecOptions {
+    pub permissive: Option<bool>,
+    pub msi_translate: Option<bool>,
+    pub power_management: Option<bool>,
+    pub rdm_reserve_policy: Option<PciRdmReservePolicy>,
+    pub seize: Option<bool>,
+}
+
+// a pci device in the syntax used by xl:
+// [DDDD:]BB:DD.F[@VSLOT][,option=value]...
+// where F may be '*' to select every function present on the device.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PciDeviceSpec {
+    pub domain: u32,
+    pub bus: u16,
+    pub device: u16,
+    pub function: PciSpecFunction,
+    pub vslot: Option<u16>,
+    pub options: PciSpecOptions,
+}
+
+impl PciDeviceSpec {
+    fn bdf(&self, function: u16) -> PciBdf {
+        // xl treats the value after '@' as a virtual slot, so the function
+        // number is carried over into the virtual devfn.
+        let vdefn = self.vslot.map(|vslot| (vslot << 3) | function);
+        PciBdf::new(Some(self.domain), self.bus, self.device, function, vdefn)
+    }
+
+    pub async fn expand(&self, backend: &XenPciBackend) -> Result<Vec<PciBdf>> {
+        match self.function {
+            PciSpecFunction::Single(function) => Ok(vec![self.bdf(function)]),
+            PciSpecFunction::All => {
+                let functions = PciGroupValidator::with_sysfs_root(backend.sysfs_root())
+                    .functions(&PciBdf::new(
+                        Some(self.domain),
+                        self.bus,
+                        self.device,
+                        0,
+                        None,
+                    ))
+                    .await?;
+                if functions.is_empty() {
+                    return Err(Error::InvalidPciSpec(format!(
+                        "no functions are present on {:04x}:{:02x}:{:02x}",
+                        self.domain, self.bus, self.device
+                    )));
+                }
+                Ok(functions
+                    .into_iter()
+                    .map(|function| self.bdf(function.function))
+                    .collect())
+            }
+        }
+    }
+
+    pub async fn device_configs(&self, backend: &XenPciBackend) -> Result<Vec<PciDeviceConfig>> {
+        let mut configs = Vec::new();
+        for bdf in self.expand(backend).await? {
+            let mut config = PciDeviceConfig::new(bdf);
+            if let Some(permissive) = self.options.permissive {
+                config.permissive(permissive);
+            }
+            if let Some(msi_translate) = self.options.msi_translate {
+                config.msi_translate(msi_translate);
+            }
+            if let Some(power_management) = self.options.power_management {
+                config.power_management(power_management);
+            }
+            if let Some(rdm_reserve_policy) = self.options.rdm_reserve_policy.clone() {
+                config.rdm_reserve_policy(rdm_reserve_policy);
+            }
+            if let Some(seize) = self.options.seize {
+                config.seize(seize);
+            }
+            configs.push(config);
+        }
+        Ok(configs)
+    }
+}
+
+impl FromStr for PciDeviceSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(',');
+        let bdf = parts.next().unwrap_or_default().trim();
+        let captures = PCI_SPEC_BDF_REGEX
+            .captures(bdf)
+            .ok_or_else(|| Error::InvalidPciSpec(format!("invalid bdf '{}'", bdf)))?;
+
+        let domain = match captures.get(1) {
+            Some(domain) => u32::from_str_radix(domain.as_str(), 16)?,
+            None => 0,
+        };
+        let bus = u16::from_str_radix(&captures[2], 16)?;
+        let device = u16::from_str_radix(&captures[3], 16)?;
+        if device > 0x1f {
+            return Err(Error::InvalidPciSpec(format!(
+                "device {:#x} is out of range",
+                device
+            )));
+        }
+        let function = match &captures[4] {
+            "*" => PciSpecFunction::All,
+            function => PciSpecFunction::Single(u16::from_str(function)?),
+        };
+        let vslot = match captures.get(5) {
+            Some(vslot) => Some(u16::from_str_radix(vslot.as_str(), 16)?),
+            None => None,
+        };
+        if vslot.is_some_and(|vslot| vslot > 0x1f) {
+            return Err(Error::InvalidPciSpec(format!(
+                "virtual slot in '{}' is out of range",
+                bdf
+            )));
+        }
+
+        let mut options = PciSpecOptions::default();
+        for option in parts {
+            let option = option.trim();
+            if option.is_empty() {
+                continue;
+            }
+            let (key, value) = option.split_once('=').ok_or_else(|| {
+                Error::InvalidPciSpec(format!("option '{}' has no value", option))
+            })?;
+            match key {
+                "permissive" => options.permissive = Some(parse_spec_bool(key, value)?),
+                "msitranslate" => options.msi_translate = Some(parse_spec_bool(key, value)?),
+                "power_mgmt" => options.power_management = Some(parse_spec_bool(key, value)?),
+                "seize" => options.seize = Some(parse_spec_bool(key, value)?),
+                "rdm_policy" => {
+                    options.rdm_reserve_policy = Some(match value {
+                        "strict" => PciRdmReservePolicy::Strict,
+                        "relaxed" => PciRdmReservePolicy::Relaxed,
+                        _ => {
+                            return Err(Error::InvalidPciSpec(format!(
+                                "unknown rdm_policy '{}'",
+                                value
+                            )))
+                        }
+                    })
+                }
+                _ => return Err(Error::InvalidPciSpec(format!("unknown option '{}'", key))),
+            }
+        }
+
+        Ok(PciDeviceSpec {
+            domain,
+            bus,
+            device,
+            function,
+            vslot,
+            options,
+        })
+    }
+}
+
+fn parse_spec_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "1" => Ok(true),
+        "0" => Ok(false),
+        _ => Err(Error::InvalidPciSpec(format!(
+            "option '{}' expects 0 or 1, got '{}'",
+            key, value
+        ))),
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use tokio::fs;
+
+use crate::{
+    error::{Error, Result},
+    netlink::{self, VfOption},
+    pci::{sysfs_bdf, PciBdf, PCIBACK_DRIVER_NAME},
+    tx::pci::PciDeviceConfig,
+};
+
+const SYSFS_ROOT: &str = "/sys";
+const VF_MAC_CLEARED: [u8; 6] = [0; 6];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SriovVirtualFunction {
+    pub physical_function: PciBdf,
+    pub index: u32,
+    pub bdf: PciBdf,
+}
+
+impl SriovVirtualFunction {
+    pub fn device_config(&self) -> PciDeviceConfig {
+        let mut config = PciDeviceConfig::new(self.bdf);
+        config
+            .seize(true)
+            .sriov_parent(self.physical_function, self.index);
+        config
+    }
+}
+
+#[derive(Clone)]
+pub struct SriovPhysicalFunction {
+    bdf: PciBdf,
+    path: PathBuf,
+}
+
+impl SriovPhysicalFunction {
+    pub fn new(bdf: PciBdf) -> Self {
+        Self::with_sysfs_root(SYSFS_ROOT, bdf)
+    }
+
+    pub fn with_sysfs_root(sysfs: impl AsRef<Path>, bdf: PciBdf) -> Self {
+        let bdf = sysfs_bdf(&bdf);
+        let mut path = sysfs.as_ref().to_path_buf();
+        path.push("bus/pci/devices");
+        path.push(bdf.to_string());
+        Self { bdf, path }
+    }
+
+    pub fn bdf(&self) -> &PciBdf {
+        &self.bdf
+    }
+
+    pub async fn is_supported(&self) -> Result<bool> {
+        Ok(fs::try_exists(self.path.join("sriov_totalvfs")).await?)
+    }
+
+    pub async fn total_vfs(&self) -> Result<u32> {
+        self.read_u32("sriov_totalvfs").await
+    }
+
+    pub async fn num_vfs(&self) -> Result<u32> {
+        self.read_u32("sriov_numvfs").await
+    }
+
+    pub async fn set_num_vfs(&self, count: u32) -> Result<()> {
+        let total = self.total_vfs().await?;
+        if count > total {
+            return Err(Error::SriovConfigurationFailed(format!(
+                "{} supports at most {} virtual functions, {} requested",
+                self.bdf, total, count
+            )));
+        }
+
+        let current = self.num_vfs().await?;
+        if current == count {
+            return Ok(());
+        }
+
+        // changing the count destroys every existing vf, including ones handed to domains.
+        for function in self.virtual_functions().await? {
+            if self.vf_driver(function.index).await?.as_deref() == Some(PCIBACK_DRIVER_NAME) {
+                return Err(Error::SriovConfigurationFailed(format!(
+                    "vf {} of {} is bound to pciback",
+                    function.bdf, self.bdf
+                )));
+            }
+        }
+
+        // the kernel refuses to change a non-zero vf count without resetting it first.
+        let path = self.path.join("sriov_numvfs");
+        if current != 0 {
+            fs::write(&path, "0").await?;
+        }
+        if count != 0 {
+            fs::write(&path, count.to_string()).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn virtual_functions(&self) -> Result<Vec<SriovVirtualFunction>> {
+        let mut functions = Vec::new();
+        let mut dir = fs::read_dir(&self.path).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(index) = name.strip_prefix("virtfn") else {
+                continue;
+            };
+            let Ok(index) = u32::from_str(index) else {
+                continue;
+            };
+            let target = fs::read_link(entry.path()).await?;
+            let Some(target) = target.file_name() else {
+                continue;
+            };
+            functions.push(SriovVirtualFunction {
+                physical_function: self.bdf,
+                index,
+                bdf: PciBdf::from_str(&target.to_string_lossy())?,
+            });
+        }
+        functions.sort_by_key(|function| function.index);
+        Ok(functions)
+    }
+
+    pub async fn net_interface(&self) -> Result<Option<String>> {
+        let path = self.path.join("net");
+        if !fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        let mut dir = fs::read_dir(&path).await?;
+        if let Some(entry) = dir.next_entry().await? {
+            return Ok(Some(entry.file_name().to_string_lossy().to_string()));
+        }
+        Ok(None)
+    }
+
+    pub async fn set_vf_mac(&self, index: u32, mac: &str) -> Result<()> {
+        let mac = parse_mac(mac)?;
+        self.set_vf_option(index, VfOption::Mac(mac)).await
+    }
+
+    pub async fn clear_vf_mac(&self, index: u32) -> Result<()> {
+        self.set_vf_option(index, VfOption::Mac(VF_MAC_CLEARED))
+            .await
+    }
+
+    pub async fn set_vf_vlan(&self, index: u32, vlan: u16) -> Result<()> {
+        self.set_vf_option(index, VfOption::Vlan(vlan)).await
+    }
+
+    // undoes the settings a domain's vf was given, leaving anything else as it was.
+    pub async fn reset_vf(&self, index: u32, mac: bool, vlan: bool) -> Result<()> {
+        if mac {
+            self.clear_vf_mac(index).await?;
+        }
+        if vlan {
+            self.set_vf_vlan(index, 0).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_vf_option(&self, index: u32, option: VfOption) -> Result<()> {
+        let Some(interface) = self.net_interface().await? else {
+            return Err(Error::SriovConfigurationFailed(format!(
+                "{} does not have a network interface",
+                self.bdf
+            )));
+        };
+
+        let mut path = self.path.join("net");
+        path.push(&interface);
+        path.push("ifindex");
+        let ifindex = i32::from_str(fs::read_to_string(path).await?.trim())?;
+        tokio::task::spawn_blocking(move || netlink::set_vf_option(ifindex, index, &option))
+            .await
+            .map_err(|error| Error::SriovConfigurationFailed(error.to_string()))?
+            .map_err(|error| {
+                Error::SriovConfigurationFailed(format!(
+                    "unable to configure vf {} of {}: {}",
+                    index, interface, error
+                ))
+            })
+    }
+
+    async fn vf_driver(&self, index: u32) -> Result<Option<String>> {
+        let path = self.path.join(format!("virtfn{}", index)).join("driver");
+        if !fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        let driver = fs::read_link(&path).await?;
+        Ok(driver
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string()))
+    }
+
+    async fn read_u32(&self, name: &str) -> Result<u32> {
+        let content = fs::read_to_string(self.path.join(name)).await?;
+        Ok(u32::from_str(content.trim())?)
+    }
+}
+
+fn parse_mac(mac: &str) -> Result<[u8; 6]> {
+    let mut address = [0u8; 6];
+    let mut octets = mac.split(':');
+    for octet in address.iter_mut() {
+        let Some(part) = octets.next().filter(|part| part.len() == 2) else {
+            return Err(Error::InvalidMacAddress(mac.to_string()));
+        };
+        *octet =
+            u8::from_str_radix(part, 16).map_err(|_| Error::InvalidMacAddress(mac.to_string()))?;
+    }
+    if octets.next().is_some() {
+        return Err(Error::InvalidMacAddress(mac.to_string()));
+    }
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, path::Path, str::FromStr};
+
+    use super::{parse_mac, SriovPhysicalFunction};
+    use crate::pci::PciBdf;
+
+    const PF: &str = "0000:01:00.0";
+    const VF: &str = "0000:01:10.0";
+
+    // a physical function with one vf enabled, bound to the given driver.
+    fn fake_sysfs(root: &Path, vf_driver: &str) {
+        let devices = root.join("bus/pci/devices");
+        let drivers = root.join("bus/pci/drivers");
+        std::fs::create_dir_all(drivers.join(vf_driver)).unwrap();
+        std::fs::create_dir_all(devices.join(PF)).unwrap();
+        std::fs::create_dir_all(devices.join(VF)).unwrap();
+        std::fs::write(devices.join(PF).join("sriov_totalvfs"), "8\n").unwrap();
+        std::fs::write(devices.join(PF).join("sriov_numvfs"), "1\n").unwrap();
+        symlink(devices.join(VF), devices.join(PF).join("virtfn0")).unwrap();
+        symlink(drivers.join(vf_driver), devices.join(VF).join("driver")).unwrap();
+    }
+
+    fn physical_function(root: &Path) -> SriovPhysicalFunction {
+        SriovPhysicalFunction::with_sysfs_root(root, PciBdf::from_str(PF).unwrap())
+    }
+
+    fn num_vfs(root: &Path) -> String {
+        std::fs::read_to_string(root.join("bus/pci/devices").join(PF).join("sriov_numvfs")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn set_num_vfs_refuses_when_a_vf_is_bound_to_pciback() {
+        let root = tempfile::tempdir().unwrap();
+        fake_sysfs(root.path(), "pciback");
+        assert!(physical_function(root.path()).set_num_vfs(4).await.is_err());
+        assert_eq!(num_vfs(root.path()), "1\n");
+    }
+
+    #[tokio::test]
+    async fn set_num_vfs_resets_the_count_first() {
+        let root = tempfile::tempdir().unwrap();
+        fake_sysfs(root.path(), "ixgbevf");
+        let physical_function = physical_function(root.path());
+        assert_eq!(
+            physical_function.virtual_functions().await.unwrap().len(),
+            1
+        );
+        physical_function.set_num_vfs(4).await.unwrap();
+        assert_eq!(num_vfs(root.path()), "4");
+        assert!(physical_function.set_num_vfs(9).await.is_err());
+    }
+
+    #[test]
+    fn parses_mac_addresses() {
+        assert_eq!(
+            parse_mac("02:00:5e:10:AB:ff").unwrap(),
+            [0x02, 0x00, 0x5e, 0x10, 0xab, 0xff]
+        );
+        for mac in [
+            "",
+            "02:00:5e:10:ab",
+            "02:00:5e:10:ab:ff:00",
+            "2:00:5e:10:ab:ff",
+            "zz:00:5e:10:ab:ff",
+        ] {
+            assert!(parse_mac(mac).is_err(), "{}", mac);
+        }
+    }
+}
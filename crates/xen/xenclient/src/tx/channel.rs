@@ -3,8 +3,39 @@ use xenplatform::domain::PlatformDomainInfo;
 use super::{DeviceConfig, DeviceDescription, DeviceResult, XenTransaction};
 use crate::error::{Error, Result};
 
+// where the backend sends what the zone writes to a channel.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChannelConnection {
+    #[default]
+    Pty,
+    Socket(String),
+    File(String),
+    // handled by whatever the backend registered under the channel name.
+    Callback,
+}
+
+impl ChannelConnection {
+    pub fn kind(&self) -> &str {
+        match self {
+            ChannelConnection::Pty => "pty",
+            ChannelConnection::Socket(_) => "socket",
+            ChannelConnection::File(_) => "file",
+            ChannelConnection::Callback => "callback",
+        }
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            ChannelConnection::Socket(path) | ChannelConnection::File(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
 pub struct ChannelDeviceConfig {
     backend_type: String,
+    name: Option<String>,
+    connection: ChannelConnection,
     default_console: bool,
     default_console_options: Option<(u32, u64)>,
     backend_initialized: bool,
@@ -20,6 +51,8 @@ impl ChannelDeviceConfig {
     pub fn new() -> Self {
         Self {
             backend_type: "console".to_string(),
+            name: None,
+            connection: ChannelConnection::Pty,
             default_console: false,
             default_console_options: None,
             backend_initialized: false,
@@ -31,6 +64,16 @@ impl ChannelDeviceConfig {
         self
     }
 
+    pub fn name(&mut self, name: impl AsRef<str>) -> &mut Self {
+        self.name = Some(name.as_ref().to_string());
+        self
+    }
+
+    pub fn connection(&mut self, connection: ChannelConnection) -> &mut Self {
+        self.connection = connection;
+        self
+    }
+
     pub fn default_console(&mut self) -> &mut Self {
         self.default_console = true;
         self
@@ -64,7 +107,16 @@ impl DeviceConfig for ChannelDeviceConfig {
             .add_backend_bool("online", true)
             .add_backend_item("protocol", "vt100")
             .add_backend_item("type", &self.backend_type)
-            .add_backend_item("state", if self.backend_initialized { 4 } else { 1 });
+            .add_backend_item("state", if self.backend_initialized { 4 } else { 1 })
+            .add_backend_item("connection", self.connection.kind());
+        if let Some(path) = self.connection.path() {
+            device.add_backend_item("path", path);
+        }
+        if let Some(name) = &self.name {
+            device
+                .add_backend_item("name", name)
+                .add_frontend_item("name", name);
+        }
 
         if self.default_console {
             device.special_frontend_path("console");
@@ -79,6 +131,7 @@ impl DeviceConfig for ChannelDeviceConfig {
 
         device
             .add_frontend_item("limit", 1048576)
+            // the frontend only knows pty, the backend connection key carries the rest.
             .add_frontend_item("output", "pty")
             .add_frontend_item("tty", "")
             .add_frontend_item("type", &self.backend_type)
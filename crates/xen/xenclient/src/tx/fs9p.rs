@@ -6,6 +6,7 @@ pub struct Fs9pDeviceConfig {
     security_model: String,
     path: Option<String>,
     tag: Option<String>,
+    read_only: bool,
 }
 
 impl Default for Fs9pDeviceConfig {
@@ -21,6 +22,7 @@ impl Fs9pDeviceConfig {
             security_model: "none".to_string(),
             path: None,
             tag: None,
+            read_only: false,
         }
     }
 
@@ -44,6 +46,11 @@ impl Fs9pDeviceConfig {
         self
     }
 
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.read_only = read_only;
+        self
+    }
+
     pub fn done(self) -> Self {
         self
     }
@@ -68,7 +75,8 @@ impl DeviceConfig for Fs9pDeviceConfig {
             .add_backend_bool("online", true)
             .add_backend_item("state", 1)
             .add_backend_item("path", path)
-            .add_backend_item("security_model", &self.security_model);
+            .add_backend_item("security_model", &self.security_model)
+            .add_backend_item("mode", if self.read_only { "r" } else { "w" });
         device
             .add_frontend_item("state", 1)
             .add_frontend_item("tag", tag);
@@ -7,6 +7,7 @@ pub mod vif;
 use crate::{
     devalloc::DeviceIdAllocator,
     error::{Error, Result},
+    vdisk::{VirtualDisk, VirtualDiskKind},
 };
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
@@ -23,6 +24,8 @@ pub struct XenTransaction {
     backend_dom_path: String,
     blkalloc: Arc<Mutex<DeviceIdAllocator>>,
     devalloc: Arc<Mutex<DeviceIdAllocator>>,
+    // disks named in this transaction, and whether their device has claimed them yet.
+    vdisks: Arc<Mutex<HashMap<VirtualDisk, bool>>>,
     tx: XsdTransaction,
     abort: bool,
 }
@@ -44,6 +47,7 @@ impl XenTransaction {
             tx,
             devalloc: Arc::new(Mutex::new(devalloc)),
             blkalloc: Arc::new(Mutex::new(blkalloc)),
+            vdisks: Arc::new(Mutex::new(HashMap::new())),
             abort: true,
         })
     }
@@ -59,10 +63,54 @@ impl XenTransaction {
                 frontend_dom_path, allocator_type
             ))
             .await?;
-        let allocator = state
-            .and_then(|state| DeviceIdAllocator::deserialize(&state))
-            .unwrap_or_else(DeviceIdAllocator::new);
-        Ok(allocator)
+        let in_use = XenTransaction::list_ids_in_use(tx, allocator_type, frontend_dom_path).await?;
+
+        DeviceIdAllocator::restore(allocator_type, state.as_deref(), &in_use)
+    }
+
+    async fn list_ids_in_use(
+        tx: &XsdTransaction,
+        allocator_type: &str,
+        frontend_dom_path: &str,
+    ) -> Result<Vec<u32>> {
+        let device_path = format!("{}/device", frontend_dom_path);
+        let mut ids = Vec::new();
+        if allocator_type == "blkid" {
+            for id in tx.list(format!("{}/vbd", device_path)).await? {
+                // hd and sd disks have their own index space, outside the allocator.
+                if let Some(vdev) = tx
+                    .read_string(format!("{}/vbd/{}/virtual-device", device_path, id))
+                    .await?
+                {
+                    let kind = vdev
+                        .parse::<u32>()
+                        .ok()
+                        .and_then(|vdev| VirtualDisk::decode(vdev).ok())
+                        .map(|disk| disk.kind);
+                    if kind.is_some_and(|kind| kind != VirtualDiskKind::Xvd) {
+                        continue;
+                    }
+                }
+                let Some(index) = tx
+                    .read_string(format!("{}/vbd/{}/x-index", device_path, id))
+                    .await?
+                else {
+                    continue;
+                };
+                if let Ok(index) = index.parse::<u32>() {
+                    ids.push(index);
+                }
+            }
+        } else {
+            for category in tx.list(&device_path).await? {
+                for id in tx.list(format!("{}/{}", device_path, category)).await? {
+                    if let Ok(id) = id.parse::<u32>() {
+                        ids.push(id);
+                    }
+                }
+            }
+        }
+        Ok(ids)
     }
 
     pub async fn assign_next_devid(&self) -> Result<u64> {
@@ -82,6 +130,50 @@ impl XenTransaction {
             .ok_or(Error::DevIdExhausted)
     }
 
+    pub async fn reserve_blkidx(&self, idx: u32) -> Result<()> {
+        let mut blkalloc = self.blkalloc.lock().await;
+        if idx >= blkalloc.capacity() {
+            return Err(Error::BlockIdxOutOfRange(idx, blkalloc.capacity()));
+        }
+        if !blkalloc.reserve(idx) {
+            return Err(Error::BlockIdxInUse(idx));
+        }
+        Ok(())
+    }
+
+    // holds a disk back from automatic assignment until a device added later
+    // in the transaction claims it.
+    pub async fn reserve_vdisk(&self, disk: VirtualDisk) -> Result<()> {
+        let mut vdisks = self.vdisks.lock().await;
+        if vdisks.contains_key(&disk) {
+            return Err(Error::VirtualDiskInUse(disk.name()));
+        }
+        // a disk and its partitions share its block index, which only the first takes.
+        // hd and sd names live outside the xvd indexes the allocator hands out.
+        let shared = vdisks
+            .keys()
+            .any(|other| other.kind == disk.kind && other.disk == disk.disk);
+        if disk.kind == VirtualDiskKind::Xvd && !shared {
+            self.reserve_blkidx(disk.block_index()).await?;
+        }
+        vdisks.insert(disk, false);
+        Ok(())
+    }
+
+    pub async fn claim_vdisk(&self, disk: VirtualDisk) -> Result<()> {
+        match self.vdisks.lock().await.get_mut(&disk) {
+            Some(claimed) if !*claimed => {
+                *claimed = true;
+                return Ok(());
+            }
+            Some(_) => return Err(Error::VirtualDiskInUse(disk.name())),
+            None => {}
+        }
+        self.reserve_vdisk(disk).await?;
+        self.vdisks.lock().await.insert(disk, true);
+        Ok(())
+    }
+
     pub async fn release_devid(&self, devid: u64) -> Result<()> {
         self.devalloc.lock().await.release(devid as u32);
         Ok(())
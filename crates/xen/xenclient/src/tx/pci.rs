@@ -2,8 +2,11 @@ use super::{DeviceConfig, DeviceDescription, DeviceResult, XenTransaction};
 use crate::{
     error::{Error, Result},
     pci::{PciBdf, XenPciBackend},
+    pcigroup::PciGroupValidator,
+    sriov::SriovPhysicalFunction,
 };
 use indexmap::IndexMap;
+use log::warn;
 use xencall::{sys::DOMCTL_DEV_RDM_RELAXED, XenCall};
 use xenplatform::sys::XEN_PAGE_SHIFT;
 
@@ -25,16 +28,31 @@ impl PciRdmReservePolicy {
     }
 }
 
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum PciGroupPolicy {
+    Ignore,
+    #[default]
+    Reject,
+    IncludeSiblings,
+}
+
 pub struct PciDeviceConfig {
     bdf: PciBdf,
     rdm_reserve_policy: PciRdmReservePolicy,
     permissive: bool,
-    msi_translate: bool,
-    power_management: bool,
+    msi_translate: Option<bool>,
+    power_management: Option<bool>,
+    require_reset: bool,
+    seize: bool,
+    seized_driver: Option<Option<String>>,
+    sriov_parent: Option<(PciBdf, u32)>,
+    sriov_mac: Option<String>,
+    sriov_vlan: Option<u16>,
 }
 
 pub struct PciRootDeviceConfig {
     backend_type: String,
+    group_policy: PciGroupPolicy,
     devices: Vec<PciDeviceConfig>,
 }
 
@@ -44,8 +62,14 @@ impl PciDeviceConfig {
             bdf,
             rdm_reserve_policy: PciRdmReservePolicy::Strict,
             permissive: false,
-            msi_translate: false,
-            power_management: false,
+            msi_translate: None,
+            power_management: None,
+            require_reset: false,
+            seize: false,
+            seized_driver: None,
+            sriov_parent: None,
+            sriov_mac: None,
+            sriov_vlan: None,
         }
     }
 
@@ -60,18 +84,204 @@ impl PciDeviceConfig {
     }
 
     pub fn msi_translate(&mut self, msi_translate: bool) -> &mut Self {
-        self.msi_translate = msi_translate;
+        self.msi_translate = Some(msi_translate);
         self
     }
 
     pub fn power_management(&mut self, power_management: bool) -> &mut Self {
-        self.power_management = power_management;
+        self.power_management = Some(power_management);
+        self
+    }
+
+    // refuses devices the kernel has no way to reset between zones.
+    pub fn require_reset(&mut self, require_reset: bool) -> &mut Self {
+        self.require_reset = require_reset;
+        self
+    }
+
+    pub fn seize(&mut self, seize: bool) -> &mut Self {
+        self.seize = seize;
         self
     }
 
+    pub fn sriov_parent(&mut self, physical_function: PciBdf, index: u32) -> &mut Self {
+        self.sriov_parent = Some((physical_function, index));
+        self
+    }
+
+    // only applies to virtual functions, see sriov_parent.
+    pub fn sriov_mac(&mut self, mac: impl AsRef<str>) -> &mut Self {
+        self.sriov_mac = Some(mac.as_ref().to_string());
+        self
+    }
+
+    pub fn sriov_vlan(&mut self, vlan: u16) -> &mut Self {
+        self.sriov_vlan = Some(vlan);
+        self
+    }
+
+    pub fn get_bdf(&self) -> &PciBdf {
+        &self.bdf
+    }
+
     pub fn done(self) -> Self {
         self
     }
+
+    pub(crate) async fn prepare(
+        &mut self,
+        domid: u32,
+        call: &XenCall,
+        backend: &XenPciBackend,
+    ) -> Result<()> {
+        if !backend.is_assigned(&self.bdf).await? {
+            if !self.seize {
+                return Err(Error::PciDeviceNotAssignable(self.bdf));
+            }
+            self.seized_driver = Some(backend.make_assignable(&self.bdf).await?);
+        }
+
+        let info = backend.read_device_info(&self.bdf).await?;
+        if self.require_reset && !info.can_reset() {
+            return Err(Error::PciDeviceNotResettable(self.bdf));
+        }
+        if self.msi_translate.is_none() {
+            self.msi_translate = Some(info.msi.is_some() || info.msix.is_some());
+        }
+        if self.power_management.is_none() {
+            self.power_management = Some(info.power_management.is_some());
+        }
+
+        set_resource_permissions(backend, &self.bdf, domid, call, true).await?;
+
+        if let Some(irq) = backend.read_irq(&self.bdf).await? {
+            let irq = call.map_pirq(domid, irq as isize, None).await?;
+            call.irq_permission(domid, irq, true).await?;
+        }
+
+        backend.reset(&self.bdf).await?;
+
+        if let Some((physical_function, index)) = self.sriov_parent {
+            let physical_function =
+                SriovPhysicalFunction::with_sysfs_root(backend.sysfs_root(), physical_function);
+            if let Some(mac) = self.sriov_mac.as_ref() {
+                physical_function.set_vf_mac(index, mac).await?;
+            }
+            if let Some(vlan) = self.sriov_vlan {
+                physical_function.set_vf_vlan(index, vlan).await?;
+            }
+        }
+
+        call.assign_device(
+            domid,
+            self.bdf.encode(),
+            if self.rdm_reserve_policy == PciRdmReservePolicy::Relaxed {
+                DOMCTL_DEV_RDM_RELAXED
+            } else {
+                0
+            },
+        )
+        .await?;
+
+        if self.permissive {
+            backend.enable_permissive(&self.bdf).await?;
+        }
+        Ok(())
+    }
+
+    // undoes what prepare granted the domain, for a device that never made it
+    // into the backend. every step is attempted, as prepare may have stopped early.
+    pub(crate) async fn revoke(&self, domid: u32, call: &XenCall, backend: &XenPciBackend) {
+        if let Err(error) = set_resource_permissions(backend, &self.bdf, domid, call, false).await {
+            warn!("failed to revoke pci resources of {}: {}", self.bdf, error);
+        }
+        if let Ok(Some(irq)) = backend.read_irq(&self.bdf).await {
+            let _ = call.irq_permission(domid, irq, false).await;
+            let _ = call.unmap_pirq(domid, irq).await;
+        }
+        let _ = call.deassign_device(domid, self.bdf.encode()).await;
+        if let Some((physical_function, index)) = self.sriov_parent {
+            if let Err(error) =
+                SriovPhysicalFunction::with_sysfs_root(backend.sysfs_root(), physical_function)
+                    .reset_vf(index, self.sriov_mac.is_some(), self.sriov_vlan.is_some())
+                    .await
+            {
+                warn!("failed to reset sr-iov vf {}: {}", self.bdf, error);
+            }
+        }
+        self.release(backend).await;
+    }
+
+    // hands a device taken from the host by prepare back to its driver.
+    pub(crate) async fn release(&self, backend: &XenPciBackend) {
+        let Some(driver) = self.seized_driver.as_ref() else {
+            return;
+        };
+        if let Err(error) = backend.release(&self.bdf, driver.as_deref()).await {
+            warn!("failed to release pci device {}: {}", self.bdf, error);
+        }
+    }
+
+    pub(crate) fn backend_items(&self, index: usize) -> Vec<(String, String)> {
+        let mut options = IndexMap::new();
+        options.insert("permissive", if self.permissive { "1" } else { "0" });
+        options.insert("rdm_policy", self.rdm_reserve_policy.to_option_str());
+        options.insert(
+            "msitranslate",
+            if self.msi_translate.unwrap_or(false) {
+                "1"
+            } else {
+                "0"
+            },
+        );
+        options.insert(
+            "power_mgmt",
+            if self.power_management.unwrap_or(false) {
+                "1"
+            } else {
+                "0"
+            },
+        );
+        let options = options
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut items = vec![
+            (format!("key-{}", index), self.bdf.to_string()),
+            (format!("dev-{}", index), self.bdf.to_string()),
+            (format!("opts-{}", index), options),
+        ];
+
+        if let Some(vdefn) = self.bdf.vdefn {
+            items.push((format!("vdefn-{}", index), format!("{:#x}", vdefn)));
+        }
+
+        // remember devices taken from the host so they can be handed back on destroy.
+        if let Some(driver) = self.seized_driver.as_ref() {
+            items.push((format!("x-seized-{}", index), "1".to_string()));
+            if let Some(driver) = driver {
+                items.push((format!("x-driver-{}", index), driver.clone()));
+            }
+        }
+
+        if let Some((physical_function, vf_index)) = self.sriov_parent {
+            items.push((
+                format!("x-sriov-pf-{}", index),
+                physical_function.to_string(),
+            ));
+            items.push((format!("x-sriov-vf-{}", index), vf_index.to_string()));
+            // remember what was configured so only that is reset on destroy.
+            if let Some(mac) = self.sriov_mac.as_ref() {
+                items.push((format!("x-sriov-vf-mac-{}", index), mac.clone()));
+            }
+            if let Some(vlan) = self.sriov_vlan {
+                items.push((format!("x-sriov-vf-vlan-{}", index), vlan.to_string()));
+            }
+        }
+        items
+    }
 }
 
 impl Default for PciRootDeviceConfig {
@@ -84,6 +294,7 @@ impl PciRootDeviceConfig {
     pub fn new() -> Self {
         Self {
             backend_type: "pci".to_string(),
+            group_policy: PciGroupPolicy::Reject,
             devices: Vec::new(),
         }
     }
@@ -93,58 +304,60 @@ impl PciRootDeviceConfig {
         self
     }
 
+    pub fn group_policy(&mut self, group_policy: PciGroupPolicy) -> &mut Self {
+        self.group_policy = group_policy;
+        self
+    }
+
     pub fn add_device(&mut self, device: PciDeviceConfig) -> &mut Self {
         self.devices.push(device);
         self
     }
 
-    pub async fn prepare(&self, domid: u32, call: &XenCall) -> Result<()> {
-        for device in &self.devices {
-            let backend = XenPciBackend::new();
-            if !backend.is_assigned(&device.bdf).await? {
-                return Err(Error::PciDeviceNotAssignable(device.bdf));
-            }
-            let resources = backend.read_resources(&device.bdf).await?;
-            for resource in resources {
-                if resource.is_bar_io() {
-                    call.ioport_permission(
-                        domid,
-                        resource.start as u32,
-                        resource.size() as u32,
-                        true,
-                    )
-                    .await?;
-                } else {
-                    call.iomem_permission(
-                        domid,
-                        resource.start >> XEN_PAGE_SHIFT,
-                        (resource.size() + (XEN_PAGE_SHIFT - 1)) >> XEN_PAGE_SHIFT,
-                        true,
-                    )
-                    .await?;
-                }
-            }
-
-            if let Some(irq) = backend.read_irq(&device.bdf).await? {
-                let irq = call.map_pirq(domid, irq as isize, None).await?;
-                call.irq_permission(domid, irq, true).await?;
-            }
-
-            backend.reset(&device.bdf).await?;
+    pub async fn prepare(
+        &mut self,
+        domid: u32,
+        call: &XenCall,
+        backend: &XenPciBackend,
+    ) -> Result<()> {
+        self.check_groups(domid, call, backend).await?;
+        for device in &mut self.devices {
+            device.prepare(domid, call, backend).await?;
+        }
+        Ok(())
+    }
 
-            call.assign_device(
-                domid,
-                device.bdf.encode(),
-                if device.rdm_reserve_policy == PciRdmReservePolicy::Relaxed {
-                    DOMCTL_DEV_RDM_RELAXED
-                } else {
-                    0
-                },
-            )
-            .await?;
+    pub(crate) async fn release(&self, backend: &XenPciBackend) {
+        for device in &self.devices {
+            device.release(backend).await;
+        }
+    }
 
-            if device.permissive {
-                backend.enable_permissive(&device.bdf).await?;
+    async fn check_groups(
+        &mut self,
+        domid: u32,
+        call: &XenCall,
+        backend: &XenPciBackend,
+    ) -> Result<()> {
+        let mut validator = PciGroupValidator::with_sysfs_root(backend.sysfs_root());
+        validator.hypervisor(call.clone());
+        let bdfs = self
+            .devices
+            .iter()
+            .map(|device| device.bdf)
+            .collect::<Vec<_>>();
+        match self.group_policy {
+            PciGroupPolicy::Ignore => {}
+            PciGroupPolicy::Reject => validator.validate(domid, &bdfs).await?,
+            PciGroupPolicy::IncludeSiblings => {
+                // siblings are seized when any requested device is, but otherwise
+                // take the default options.
+                let seize = self.devices.iter().any(|device| device.seize);
+                for sibling in validator.missing_siblings(domid, &bdfs).await? {
+                    let mut device = PciDeviceConfig::new(sibling);
+                    device.seize(seize);
+                    self.devices.push(device);
+                }
             }
         }
         Ok(())
@@ -168,22 +381,8 @@ impl DeviceConfig for PciRootDeviceConfig {
             .add_backend_item("num_devs", self.devices.len());
 
         for (index, pci) in self.devices.iter().enumerate() {
-            let mut options = IndexMap::new();
-            options.insert("permissive", if pci.permissive { "1" } else { "0" });
-            options.insert("rdm_policy", pci.rdm_reserve_policy.to_option_str());
-            options.insert("msitranslate", if pci.msi_translate { "1" } else { "0" });
-            let options = options
-                .into_iter()
-                .map(|(key, value)| format!("{}={}", key, value))
-                .collect::<Vec<_>>()
-                .join(",");
-            device
-                .add_backend_item(format!("key-{}", index), pci.bdf.to_string())
-                .add_backend_item(format!("dev-{}", index), pci.bdf.to_string())
-                .add_backend_item(format!("opts-{}", index), options);
-
-            if let Some(vdefn) = pci.bdf.vdefn {
-                device.add_backend_item(format!("vdefn-{}", index), format!("{:#x}", vdefn));
+            for (key, value) in pci.backend_items(index) {
+                device.add_backend_item(key, value);
             }
         }
 
@@ -192,3 +391,28 @@ impl DeviceConfig for PciRootDeviceConfig {
         Ok(DeviceResult { id })
     }
 }
+
+pub(crate) async fn set_resource_permissions(
+    backend: &XenPciBackend,
+    bdf: &PciBdf,
+    domid: u32,
+    call: &XenCall,
+    allow: bool,
+) -> Result<()> {
+    let resources = backend.read_resources(bdf).await?;
+    for resource in resources {
+        if resource.is_bar_io() {
+            call.ioport_permission(domid, resource.start as u32, resource.size() as u32, allow)
+                .await?;
+        } else {
+            call.iomem_permission(
+                domid,
+                resource.start >> XEN_PAGE_SHIFT,
+                (resource.size() + (XEN_PAGE_SHIFT - 1)) >> XEN_PAGE_SHIFT,
+                allow,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
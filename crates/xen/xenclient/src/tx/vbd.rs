@@ -1,9 +1,14 @@
+use xenplatform::RuntimePlatformType;
+
 use super::{BlockDeviceRef, BlockDeviceResult, DeviceConfig, DeviceDescription, XenTransaction};
 use crate::{
     error::{Error, Result},
-    util::vbd_blkidx_to_disk_name,
+    vdisk::VirtualDisk,
 };
 
+// matches XENBUS_MAX_RING_GRANT_ORDER in the linux blkback driver.
+const VBD_MAX_RING_PAGE_ORDER: u32 = 4;
+
 pub struct VbdDeviceConfig {
     backend_type: String,
     removable: bool,
@@ -12,6 +17,16 @@ pub struct VbdDeviceConfig {
     discard: bool,
     trusted: bool,
     block_device: Option<BlockDeviceRef>,
+    release_loop: bool,
+    disk: Option<VirtualDisk>,
+    protocol: Option<String>,
+    persistent_grants: Option<bool>,
+    max_ring_page_order: Option<u32>,
+    multi_queue_max_queues: Option<u32>,
+    flush_cache: Option<bool>,
+    barrier: Option<bool>,
+    discard_secure: Option<bool>,
+    sector_size: Option<u32>,
 }
 
 impl Default for VbdDeviceConfig {
@@ -30,6 +45,16 @@ impl VbdDeviceConfig {
             discard: false,
             trusted: true,
             block_device: None,
+            release_loop: false,
+            disk: None,
+            protocol: None,
+            persistent_grants: None,
+            max_ring_page_order: None,
+            multi_queue_max_queues: None,
+            flush_cache: None,
+            barrier: None,
+            discard_secure: None,
+            sector_size: None,
         }
     }
 
@@ -68,9 +93,131 @@ impl VbdDeviceConfig {
         self
     }
 
+    // detaches the loop device behind block_device when the domain is destroyed,
+    // for loop devices that were set up only for this domain.
+    pub fn release_loop(&mut self, release_loop: bool) -> &mut Self {
+        self.release_loop = release_loop;
+        self
+    }
+
+    pub fn disk(&mut self, disk: VirtualDisk) -> &mut Self {
+        self.disk = Some(disk);
+        self
+    }
+
+    pub fn get_disk(&self) -> &Option<VirtualDisk> {
+        &self.disk
+    }
+
+    pub fn disk_name(&mut self, name: impl AsRef<str>) -> Result<&mut Self> {
+        self.disk = Some(name.as_ref().parse::<VirtualDisk>()?);
+        Ok(self)
+    }
+
+    pub fn protocol(&mut self, protocol: impl AsRef<str>) -> &mut Self {
+        self.protocol = Some(protocol.as_ref().to_string());
+        self
+    }
+
+    pub fn persistent_grants(&mut self, persistent_grants: bool) -> &mut Self {
+        self.persistent_grants = Some(persistent_grants);
+        self
+    }
+
+    pub fn max_ring_page_order(&mut self, max_ring_page_order: u32) -> &mut Self {
+        self.max_ring_page_order = Some(max_ring_page_order);
+        self
+    }
+
+    pub fn multi_queue_max_queues(&mut self, multi_queue_max_queues: u32) -> &mut Self {
+        self.multi_queue_max_queues = Some(multi_queue_max_queues);
+        self
+    }
+
+    pub fn flush_cache(&mut self, flush_cache: bool) -> &mut Self {
+        self.flush_cache = Some(flush_cache);
+        self
+    }
+
+    pub fn barrier(&mut self, barrier: bool) -> &mut Self {
+        self.barrier = Some(barrier);
+        self
+    }
+
+    pub fn discard_secure(&mut self, discard_secure: bool) -> &mut Self {
+        self.discard_secure = Some(discard_secure);
+        self
+    }
+
+    pub fn sector_size(&mut self, sector_size: u32) -> &mut Self {
+        self.sector_size = Some(sector_size);
+        self
+    }
+
     pub fn done(self) -> Self {
         self
     }
+
+    pub fn prepare(&mut self, platform: &RuntimePlatformType) -> Result<()> {
+        if self.protocol.is_none() {
+            self.protocol = Some(platform.io_protocol().to_string());
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(order) = self.max_ring_page_order {
+            if order > VBD_MAX_RING_PAGE_ORDER {
+                return Err(Error::InvalidVbdOption("max ring page order is too large"));
+            }
+        }
+
+        if self.multi_queue_max_queues == Some(0) {
+            return Err(Error::InvalidVbdOption(
+                "multi-queue max queues must be at least 1",
+            ));
+        }
+
+        if let Some(sector_size) = self.sector_size {
+            if sector_size < 512 || !sector_size.is_power_of_two() {
+                return Err(Error::InvalidVbdOption(
+                    "sector size must be a power of two and at least 512",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // negotiation options are only written when set, leaving blkback its defaults.
+    fn add_backend_options(&self, device: &mut DeviceDescription) {
+        if let Some(persistent_grants) = self.persistent_grants {
+            device.add_backend_bool("feature-persistent", persistent_grants);
+        }
+
+        if let Some(max_ring_page_order) = self.max_ring_page_order {
+            device.add_backend_item("max-ring-page-order", max_ring_page_order);
+        }
+
+        if let Some(multi_queue_max_queues) = self.multi_queue_max_queues {
+            device.add_backend_item("multi-queue-max-queues", multi_queue_max_queues);
+        }
+
+        if let Some(flush_cache) = self.flush_cache {
+            device.add_backend_bool("feature-flush-cache", flush_cache);
+        }
+
+        if let Some(barrier) = self.barrier {
+            device.add_backend_bool("feature-barrier", barrier);
+        }
+
+        if let Some(discard_secure) = self.discard_secure {
+            device.add_backend_bool("discard-secure", discard_secure);
+        }
+
+        if let Some(sector_size) = self.sector_size {
+            device.add_backend_item("sector-size", sector_size);
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -78,9 +225,17 @@ impl DeviceConfig for VbdDeviceConfig {
     type Result = BlockDeviceResult;
 
     async fn add_to_transaction(&self, tx: &XenTransaction) -> Result<BlockDeviceResult> {
+        self.validate()?;
         let id = tx.assign_next_devid().await?;
-        let idx = tx.assign_next_blkidx().await?;
-        let vdev = vbd_blkidx_to_disk_name(idx)?;
+        let disk = match self.disk {
+            Some(disk) => {
+                tx.claim_vdisk(disk).await?;
+                disk
+            }
+            None => VirtualDisk::from_block_index(tx.assign_next_blkidx().await?),
+        };
+        let idx = disk.block_index();
+        let vdev = disk.name();
         let block_device = self
             .block_device
             .as_ref()
@@ -104,28 +259,99 @@ impl DeviceConfig for VbdDeviceConfig {
             .add_backend_item("dev", &vdev)
             .add_backend_item("state", 1);
 
-        // we should use standard virtual-device support for first few block devices.
-        // the kernel warns when you use ext for indexes 5 or less, due to
-        // potential id overlapping.
-        let (vdev, vd_key) = if idx <= 5 {
-            // shift by 4 as partition count is 16
-            ((202 << 8) | (idx as u64 * 16u64), "virtual-device")
-        } else {
-            // this is silly but 256 is the number of partitions
-            // multiply the index by that to get the actual id
-            ((1u64 << 28u64) + (idx as u64) * 256, "virtual-device-ext")
-        };
+        if self.release_loop {
+            device.add_backend_bool("x-release-loop", true);
+        }
+
+        self.add_backend_options(&mut device);
+
+        let (vd_key, vdev) = disk.frontend_entry()?;
 
         device
             .add_frontend_item(vd_key, vdev)
             .add_frontend_item("state", 1)
             .add_frontend_item("device-type", "disk")
             .add_frontend_bool("trusted", self.trusted)
-            .add_frontend_item("protocol", "x86_64-abi")
             .add_frontend_item("x-index", idx);
 
+        // filled in by prepare from the platform the domain was created for.
+        if let Some(protocol) = &self.protocol {
+            device.add_frontend_item("protocol", protocol);
+        }
+
         tx.add_device(id, device).await?;
 
         Ok(BlockDeviceResult { id, idx })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use xenplatform::{sys::XEN_IO_PROTO_ABI_NATIVE, RuntimePlatformType};
+
+    use super::VbdDeviceConfig;
+    use crate::tx::DeviceDescription;
+
+    fn backend_options(config: &VbdDeviceConfig) -> DeviceDescription {
+        let mut device = DeviceDescription::new("vbd", "vbd");
+        config.add_backend_options(&mut device);
+        device
+    }
+
+    #[test]
+    fn options_are_only_written_when_set() {
+        let device = backend_options(&VbdDeviceConfig::new());
+        assert!(device.backend_items.is_empty());
+
+        let mut config = VbdDeviceConfig::new();
+        config
+            .persistent_grants(false)
+            .max_ring_page_order(2)
+            .multi_queue_max_queues(4)
+            .sector_size(4096);
+        let device = backend_options(&config);
+        let item = |key: &str| device.backend_items.get(key).map(String::as_str);
+        assert_eq!(item("feature-persistent"), Some("0"));
+        assert_eq!(item("max-ring-page-order"), Some("2"));
+        assert_eq!(item("multi-queue-max-queues"), Some("4"));
+        assert_eq!(item("sector-size"), Some("4096"));
+        assert_eq!(device.backend_items.len(), 4);
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_options() {
+        let mut config = VbdDeviceConfig::new();
+        config
+            .max_ring_page_order(4)
+            .multi_queue_max_queues(1)
+            .sector_size(512);
+        assert!(config.validate().is_ok());
+
+        assert!(VbdDeviceConfig::new()
+            .max_ring_page_order(5)
+            .validate()
+            .is_err());
+        assert!(VbdDeviceConfig::new()
+            .multi_queue_max_queues(0)
+            .validate()
+            .is_err());
+        assert!(VbdDeviceConfig::new().sector_size(256).validate().is_err());
+        assert!(VbdDeviceConfig::new().sector_size(1000).validate().is_err());
+    }
+
+    #[test]
+    fn protocol_falls_back_to_platform() {
+        let mut config = VbdDeviceConfig::new();
+        config.prepare(&RuntimePlatformType::Unsupported).unwrap();
+        assert_eq!(config.protocol.as_deref(), Some(XEN_IO_PROTO_ABI_NATIVE));
+
+        let platform = RuntimePlatformType::supported();
+        let mut config = VbdDeviceConfig::new();
+        config.prepare(&platform).unwrap();
+        assert_eq!(config.protocol.as_deref(), Some(platform.io_protocol()));
+
+        let mut config = VbdDeviceConfig::new();
+        config.protocol("arm-abi").prepare(&platform).unwrap();
+        assert_eq!(config.protocol.as_deref(), Some("arm-abi"));
+    }
+}
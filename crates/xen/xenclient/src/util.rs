@@ -1,21 +1,103 @@
-use crate::error::{Error, Result};
+use std::{future::Future, time::Duration};
+
+use tokio::time::{sleep, Instant};
+use xencall::{sys::GetDomainInfo, XenCall};
+
+use crate::{
+    error::{Error, Result},
+    vdisk::VirtualDisk,
+};
 
 pub fn vbd_blkidx_to_disk_name(blkid: u32) -> Result<String> {
-    let mut name = "xvd".to_string();
-    let mut suffix = String::new();
-    let mut n = blkid;
+    Ok(VirtualDisk::from_block_index(blkid).name())
+}
+
+pub(crate) async fn domain_info(call: &XenCall, domid: u32) -> Result<GetDomainInfo> {
+    let info = call.get_domain_info(domid).await?;
+    // getdomaininfo answers with the next domain when this one is gone.
+    if info.domid as u32 != domid {
+        return Err(Error::DomainNonExistent);
+    }
+    Ok(info)
+}
+
+// samples a value until done accepts it, handing back the last sample as the
+// error once the timeout has passed.
+pub(crate) async fn poll_until<T, F, Fut>(
+    timeout: Duration,
+    interval: Duration,
+    mut sample: F,
+    done: impl Fn(&T) -> bool,
+) -> Result<std::result::Result<T, T>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let deadline = Instant::now() + timeout;
     loop {
-        let c = (n % 26) as u8;
-        let c = b'a' + c;
-        let c = char::from_u32(c as u32).ok_or(Error::InvalidBlockIdx)?;
-        suffix.push(c);
-        if n >= 26 {
-            n = (n / 26) - 1;
-            continue;
-        } else {
-            break;
+        let value = sample().await?;
+        if done(&value) {
+            return Ok(Ok(value));
         }
+        if Instant::now() >= deadline {
+            return Ok(Err(value));
+        }
+        sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Duration};
+
+    use super::poll_until;
+    use crate::error::Error;
+
+    #[tokio::test]
+    async fn poll_until_returns_the_accepted_sample() {
+        let count = Cell::new(0);
+        let result = poll_until(
+            Duration::from_secs(5),
+            Duration::from_millis(1),
+            || async {
+                count.set(count.get() + 1);
+                Ok(count.get())
+            },
+            |value| *value == 3,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, Ok(3));
+        assert_eq!(count.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn poll_until_returns_the_last_sample_on_timeout() {
+        let count = Cell::new(0);
+        let result = poll_until(
+            Duration::from_millis(20),
+            Duration::from_millis(5),
+            || async {
+                count.set(count.get() + 1);
+                Ok(count.get())
+            },
+            |_| false,
+        )
+        .await
+        .unwrap();
+        assert!(count.get() > 1);
+        assert_eq!(result, Err(count.get()));
+    }
+
+    #[tokio::test]
+    async fn poll_until_stops_on_sample_errors() {
+        let result = poll_until(
+            Duration::from_secs(5),
+            Duration::from_millis(1),
+            || async { Err::<u32, _>(Error::DomainNonExistent) },
+            |_| true,
+        )
+        .await;
+        assert!(matches!(result, Err(Error::DomainNonExistent)));
     }
-    name.push_str(&suffix.chars().rev().collect::<String>());
-    Ok(name)
 }
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use xencall::{sys::GetDomainInfo, XenCall};
+
+use crate::{
+    error::{Error, Result},
+    util::{domain_info, poll_until},
+};
+
+pub const DEFAULT_VCPU_ONLINE_TIMEOUT: Duration = Duration::from_secs(10);
+const VCPU_ONLINE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) struct VcpuCounts {
+    pub online: u32,
+    pub max: u32,
+}
+
+impl VcpuCounts {
+    fn from_info(info: &GetDomainInfo) -> VcpuCounts {
+        VcpuCounts {
+            online: info.number_online_vcpus,
+            max: info.max_vcpu_id + 1,
+        }
+    }
+
+    // hotplug can only move between one vcpu and those the domain was created with.
+    pub fn check(&self, count: u32) -> Result<()> {
+        if count == 0 || count > self.max {
+            return Err(Error::InvalidVcpuCount {
+                count,
+                max: self.max,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub(crate) async fn vcpu_counts(call: &XenCall, domid: u32) -> Result<VcpuCounts> {
+    Ok(VcpuCounts::from_info(&domain_info(call, domid).await?))
+}
+
+pub(crate) async fn wait_for_online_vcpus(
+    call: &XenCall,
+    domid: u32,
+    count: u32,
+    timeout: Duration,
+) -> Result<()> {
+    poll_until(
+        timeout,
+        VCPU_ONLINE_POLL_INTERVAL,
+        || async { Ok(vcpu_counts(call, domid).await?.online) },
+        |online| *online == count,
+    )
+    .await?
+    .map(|_| ())
+    .map_err(|online| Error::VcpuOnlineTimeout {
+        domid,
+        count,
+        online,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use xencall::sys::GetDomainInfo;
+
+    use super::VcpuCounts;
+    use crate::error::Error;
+
+    #[test]
+    fn counts_follow_the_domain_info() {
+        let info = GetDomainInfo {
+            number_online_vcpus: 2,
+            max_vcpu_id: 3,
+            ..Default::default()
+        };
+        let counts = VcpuCounts::from_info(&info);
+        assert_eq!(counts.online, 2);
+        assert_eq!(counts.max, 4);
+    }
+
+    #[test]
+    fn count_is_bounded_by_max_vcpus() {
+        let counts = VcpuCounts { online: 1, max: 4 };
+        assert!(counts.check(1).is_ok());
+        assert!(counts.check(4).is_ok());
+        assert!(matches!(
+            counts.check(5),
+            Err(Error::InvalidVcpuCount { count: 5, max: 4 })
+        ));
+        assert!(matches!(
+            counts.check(0),
+            Err(Error::InvalidVcpuCount { count: 0, max: 4 })
+        ));
+    }
+}
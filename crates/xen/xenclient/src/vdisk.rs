@@ -0,0 +1,296 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::error::{Error, Result};
+
+const XVD_MAJOR: u32 = 202;
+const HD_MAJOR_PRIMARY: u32 = 3;
+const HD_MAJOR_SECONDARY: u32 = 22;
+const SD_MAJOR: u32 = 8;
+const VDEV_EXT_FLAG: u32 = 1 << 28;
+const VDEV_EXT_MAX_DISKS: u32 = 1 << 20;
+const VDEV_EXT_MAX_PARTITIONS: u32 = 256;
+
+// the linux kernel warns when the ext encoding is used for low disk indexes,
+// as they may overlap with the standard encoding.
+const VDEV_EXT_MIN_DISK: u32 = 6;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum VirtualDiskKind {
+    Xvd,
+    Hd,
+    Sd,
+}
+
+impl VirtualDiskKind {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            VirtualDiskKind::Xvd => "xvd",
+            VirtualDiskKind::Hd => "hd",
+            VirtualDiskKind::Sd => "sd",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct VirtualDisk {
+    pub kind: VirtualDiskKind,
+    pub disk: u32,
+    pub partition: u32,
+}
+
+impl VirtualDisk {
+    pub fn new(kind: VirtualDiskKind, disk: u32, partition: u32) -> Self {
+        Self {
+            kind,
+            disk,
+            partition,
+        }
+    }
+
+    pub fn from_block_index(idx: u32) -> Self {
+        Self::new(VirtualDiskKind::Xvd, idx, 0)
+    }
+
+    pub fn block_index(&self) -> u32 {
+        self.disk
+    }
+
+    pub fn is_partition(&self) -> bool {
+        self.partition != 0
+    }
+
+    pub fn disk_name(&self) -> String {
+        let mut suffix = Vec::new();
+        let mut n = self.disk;
+        loop {
+            suffix.push(b'a' + (n % 26) as u8);
+            if n < 26 {
+                break;
+            }
+            n = (n / 26) - 1;
+        }
+        suffix.reverse();
+        format!("{}{}", self.kind.prefix(), String::from_utf8_lossy(&suffix))
+    }
+
+    pub fn name(&self) -> String {
+        if self.partition == 0 {
+            self.disk_name()
+        } else {
+            format!("{}{}", self.disk_name(), self.partition)
+        }
+    }
+
+    pub fn encode(&self) -> Result<u32> {
+        match self.kind {
+            VirtualDiskKind::Xvd => {
+                if self.disk < 16 && self.partition < 16 {
+                    Ok((XVD_MAJOR << 8) | (self.disk << 4) | self.partition)
+                } else {
+                    self.encode_ext()
+                }
+            }
+
+            VirtualDiskKind::Hd => {
+                if self.disk >= 4 || self.partition >= 64 {
+                    return Err(Error::InvalidVirtualDisk(self.name()));
+                }
+                let major = if self.disk < 2 {
+                    HD_MAJOR_PRIMARY
+                } else {
+                    HD_MAJOR_SECONDARY
+                };
+                Ok((major << 8) | ((self.disk & 1) << 6) | self.partition)
+            }
+
+            VirtualDiskKind::Sd => {
+                if self.disk >= 16 || self.partition >= 16 {
+                    return Err(Error::InvalidVirtualDisk(self.name()));
+                }
+                Ok((SD_MAJOR << 8) | (self.disk << 4) | self.partition)
+            }
+        }
+    }
+
+    pub fn encode_ext(&self) -> Result<u32> {
+        if self.kind != VirtualDiskKind::Xvd
+            || self.disk >= VDEV_EXT_MAX_DISKS
+            || self.partition >= VDEV_EXT_MAX_PARTITIONS
+        {
+            return Err(Error::InvalidVirtualDisk(self.name()));
+        }
+        Ok(VDEV_EXT_FLAG | (self.disk << 8) | self.partition)
+    }
+
+    pub fn decode(value: u32) -> Result<VirtualDisk> {
+        if value & VDEV_EXT_FLAG != 0 {
+            let disk = (value >> 8) & (VDEV_EXT_MAX_DISKS - 1);
+            let partition = value & (VDEV_EXT_MAX_PARTITIONS - 1);
+            return Ok(VirtualDisk::new(VirtualDiskKind::Xvd, disk, partition));
+        }
+
+        let minor = value & 0xff;
+        match value >> 8 {
+            XVD_MAJOR => Ok(VirtualDisk::new(
+                VirtualDiskKind::Xvd,
+                minor >> 4,
+                minor & 0xf,
+            )),
+            HD_MAJOR_PRIMARY => Ok(VirtualDisk::new(
+                VirtualDiskKind::Hd,
+                minor >> 6,
+                minor & 0x3f,
+            )),
+            HD_MAJOR_SECONDARY => Ok(VirtualDisk::new(
+                VirtualDiskKind::Hd,
+                2 + (minor >> 6),
+                minor & 0x3f,
+            )),
+            SD_MAJOR => Ok(VirtualDisk::new(
+                VirtualDiskKind::Sd,
+                minor >> 4,
+                minor & 0xf,
+            )),
+            _ => Err(Error::InvalidVirtualDevice(value)),
+        }
+    }
+
+    pub fn frontend_entry(&self) -> Result<(&'static str, u32)> {
+        if self.kind == VirtualDiskKind::Xvd && self.disk >= VDEV_EXT_MIN_DISK {
+            Ok(("virtual-device-ext", self.encode_ext()?))
+        } else {
+            Ok(("virtual-device", self.encode()?))
+        }
+    }
+}
+
+impl FromStr for VirtualDisk {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, rest) = if let Some(rest) = s.strip_prefix("xvd") {
+            (VirtualDiskKind::Xvd, rest)
+        } else if let Some(rest) = s.strip_prefix("hd") {
+            (VirtualDiskKind::Hd, rest)
+        } else if let Some(rest) = s.strip_prefix("sd") {
+            (VirtualDiskKind::Sd, rest)
+        } else {
+            return Err(Error::InvalidVirtualDisk(s.to_string()));
+        };
+
+        let letters = rest.bytes().take_while(|c| c.is_ascii_lowercase()).count();
+        let (letters, digits) = rest.split_at(letters);
+        if letters.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(Error::InvalidVirtualDisk(s.to_string()));
+        }
+
+        let mut disk: u32 = 0;
+        for c in letters.bytes() {
+            disk = disk
+                .checked_mul(26)
+                .and_then(|x| x.checked_add((c - b'a') as u32 + 1))
+                .ok_or_else(|| Error::InvalidVirtualDisk(s.to_string()))?;
+        }
+        disk -= 1;
+
+        let partition = if digits.is_empty() {
+            0
+        } else if digits.starts_with('0') {
+            return Err(Error::InvalidVirtualDisk(s.to_string()));
+        } else {
+            digits.parse::<u32>()?
+        };
+
+        let disk = VirtualDisk::new(kind, disk, partition);
+        disk.encode()?;
+        Ok(disk)
+    }
+}
+
+impl Display for VirtualDisk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VirtualDisk, VirtualDiskKind};
+
+    fn parse(name: &str) -> VirtualDisk {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for name in [
+            "xvda", "xvdz", "xvdaa", "xvdzz", "xvdaaa", "xvdb1", "xvdq15", "hda", "hdd63", "sda",
+            "sdp15",
+        ] {
+            assert_eq!(parse(name).name(), name);
+        }
+    }
+
+    #[test]
+    fn names_map_to_disk_indexes() {
+        assert_eq!(parse("xvda").disk, 0);
+        assert_eq!(parse("xvdz").disk, 25);
+        assert_eq!(parse("xvdaa").disk, 26);
+        assert_eq!(parse("xvdzz").disk, 701);
+        assert_eq!(parse("xvdaaa").disk, 702);
+        assert_eq!(VirtualDisk::from_block_index(27).name(), "xvdab");
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for name in [
+            "", "xvd", "vda", "xvdA", "xvda0", "xvda01", "xvda1b", "hde", "hda64", "sdq", "sda16",
+        ] {
+            assert!(name.parse::<VirtualDisk>().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn encodes_standard_numbers() {
+        assert_eq!(parse("xvda").encode().unwrap(), 202 << 8);
+        assert_eq!(parse("xvdb1").encode().unwrap(), (202 << 8) | (1 << 4) | 1);
+        assert_eq!(parse("hda").encode().unwrap(), 3 << 8);
+        assert_eq!(parse("hdb2").encode().unwrap(), (3 << 8) | (1 << 6) | 2);
+        assert_eq!(parse("hdc").encode().unwrap(), 22 << 8);
+        assert_eq!(parse("sdb3").encode().unwrap(), (8 << 8) | (1 << 4) | 3);
+    }
+
+    #[test]
+    fn encodes_extended_numbers() {
+        assert_eq!(parse("xvdq").encode().unwrap(), (1 << 28) | (16 << 8));
+        assert_eq!(parse("xvdb16").encode().unwrap(), (1 << 28) | (1 << 8) | 16);
+        assert!(parse("hda").encode_ext().is_err());
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for name in [
+            "xvda", "xvdp15", "xvdq", "xvdaa3", "hda", "hdd63", "sda", "sdp15",
+        ] {
+            let disk = parse(name);
+            assert_eq!(VirtualDisk::decode(disk.encode().unwrap()).unwrap(), disk);
+        }
+        let disk = parse("xvdb");
+        assert_eq!(
+            VirtualDisk::decode(disk.encode_ext().unwrap()).unwrap(),
+            disk
+        );
+        assert!(VirtualDisk::decode(1 << 8).is_err());
+    }
+
+    #[test]
+    fn frontend_entry_uses_ext_for_high_disks() {
+        assert_eq!(parse("xvdf").frontend_entry().unwrap().0, "virtual-device");
+        assert_eq!(
+            parse("xvdg").frontend_entry().unwrap().0,
+            "virtual-device-ext"
+        );
+        assert_eq!(parse("sdg").frontend_entry().unwrap().0, "virtual-device");
+        assert_eq!(parse("xvdg").kind, VirtualDiskKind::Xvd);
+    }
+}
@@ -0,0 +1,10 @@
+use xenconsole::backend::ConsoleBackend;
+use xenconsole::error::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let mut backend = ConsoleBackend::new().await?;
+    backend.run().await?;
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use std::env::args;
+
+use xenconsole::daemon::ConsoleDaemon;
+use xenconsole::error::Result;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let directory = args()
+        .nth(1)
+        .unwrap_or_else(|| "/var/run/krata/console".to_string());
+    let mut daemon = ConsoleDaemon::new().await?;
+    daemon
+        .log_dir(format!("{}/logs", directory))
+        .socket_dir(&directory)
+        .pty(true);
+    daemon.run().await?;
+    Ok(())
+}
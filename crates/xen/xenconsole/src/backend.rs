@@ -0,0 +1,292 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use log::{debug, error, info, warn};
+use tokio::task::JoinHandle;
+use xenevtchn::EventChannelService;
+use xengnt::{sys::GrantRef, GrantTab};
+use xenstore::{XsdClient, XsdInterface, XsdMultiWatchHandle};
+
+use crate::{
+    error::{Error, Result},
+    output::ChannelOutput,
+    ring::ConsoleRing,
+};
+
+const XENBUS_STATE_UNKNOWN: u32 = 0;
+const XENBUS_STATE_INITIALISING: u32 = 1;
+const XENBUS_STATE_INIT_WAIT: u32 = 2;
+const XENBUS_STATE_INITIALISED: u32 = 3;
+const XENBUS_STATE_CONNECTED: u32 = 4;
+const XENBUS_STATE_CLOSING: u32 = 5;
+const XENBUS_STATE_CLOSED: u32 = 6;
+
+struct ConsoleDevice {
+    task: JoinHandle<()>,
+}
+
+impl Drop for ConsoleDevice {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// serves the named console channels whose backend lives in this domain. the
+// default console of each zone is left to the console daemon.
+pub struct ConsoleBackend {
+    store: XsdClient,
+    gnttab: GrantTab,
+    evtchn: EventChannelService,
+    backend_domid: u32,
+    backend_type: String,
+    outputs: HashMap<String, ChannelOutput>,
+    devices: HashMap<(u32, u32), ConsoleDevice>,
+    watched_frontends: HashSet<String>,
+}
+
+impl ConsoleBackend {
+    pub async fn new() -> Result<ConsoleBackend> {
+        Ok(ConsoleBackend {
+            store: XsdClient::open().await?,
+            gnttab: GrantTab::open()?,
+            evtchn: EventChannelService::open().await?,
+            backend_domid: 0,
+            backend_type: "console".to_string(),
+            outputs: HashMap::new(),
+            devices: HashMap::new(),
+            watched_frontends: HashSet::new(),
+        })
+    }
+
+    pub fn backend_domid(&mut self, backend_domid: u32) -> &mut Self {
+        self.backend_domid = backend_domid;
+        self
+    }
+
+    pub fn backend_type(&mut self, backend_type: impl AsRef<str>) -> &mut Self {
+        self.backend_type = backend_type.as_ref().to_string();
+        self
+    }
+
+    // sends every channel with this name to the output, whatever its backend keys say.
+    pub fn output(&mut self, name: impl AsRef<str>, output: ChannelOutput) -> &mut Self {
+        self.outputs.insert(name.as_ref().to_string(), output);
+        self
+    }
+
+    fn backend_path(&self) -> String {
+        format!(
+            "/local/domain/{}/backend/{}",
+            self.backend_domid, self.backend_type
+        )
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        let backend_path = self.backend_path();
+        let mut watch = self.store.create_multi_watch().await?;
+        self.store.bind_watch_id(watch.id, &backend_path).await?;
+        watch.add_path(&backend_path);
+        info!("serving console channels from {}", backend_path);
+
+        while watch.receiver.recv().await.is_some() {
+            if let Err(error) = self.scan(&mut watch).await {
+                error!("failed to process console channels: {}", error);
+            }
+        }
+        Ok(())
+    }
+
+    async fn scan(&mut self, watch: &mut XsdMultiWatchHandle) -> Result<()> {
+        let backend_path = self.backend_path();
+        let mut present = HashSet::new();
+        for domid in self.store.list(&backend_path).await? {
+            let Ok(domid) = u32::from_str(&domid) else {
+                continue;
+            };
+            for devid in self
+                .store
+                .list(format!("{}/{}", backend_path, domid))
+                .await?
+            {
+                let Ok(devid) = u32::from_str(&devid) else {
+                    continue;
+                };
+                present.insert((domid, devid));
+                if let Err(error) = self.update(watch, domid, devid).await {
+                    warn!(
+                        "failed to update console channel {} of domain {}: {}",
+                        devid, domid, error
+                    );
+                }
+            }
+        }
+
+        self.devices.retain(|key, _| {
+            let keep = present.contains(key);
+            if !keep {
+                debug!("console channel {} of domain {} was removed", key.1, key.0);
+            }
+            keep
+        });
+        Ok(())
+    }
+
+    async fn update(
+        &mut self,
+        watch: &mut XsdMultiWatchHandle,
+        domid: u32,
+        devid: u32,
+    ) -> Result<()> {
+        let path = format!("{}/{}/{}", self.backend_path(), domid, devid);
+        let Some(state) = self.read_u32(format!("{}/state", path)).await? else {
+            return Ok(());
+        };
+        let Some(frontend) = self.store.read_string(format!("{}/frontend", path)).await? else {
+            return Ok(());
+        };
+        // the default console lives outside of device/ and has no state machine.
+        if !frontend.contains("/device/") {
+            return Ok(());
+        }
+        let name = self.store.read_string(format!("{}/name", path)).await?;
+        let Some(output) = self.resolve_output(&path, name.as_deref()).await? else {
+            return Ok(());
+        };
+
+        if !self.watched_frontends.contains(&frontend) {
+            let frontend_state = format!("{}/state", frontend);
+            self.store.bind_watch_id(watch.id, &frontend_state).await?;
+            watch.add_path(&frontend_state);
+            self.watched_frontends.insert(frontend.clone());
+        }
+        let frontend_state = self
+            .read_u32(format!("{}/state", frontend))
+            .await?
+            .unwrap_or(XENBUS_STATE_UNKNOWN);
+
+        match (state, frontend_state) {
+            (XENBUS_STATE_INITIALISING, _) | (XENBUS_STATE_CLOSED, XENBUS_STATE_INITIALISING) => {
+                self.set_state(&path, XENBUS_STATE_INIT_WAIT).await?;
+            }
+
+            (XENBUS_STATE_INIT_WAIT, XENBUS_STATE_INITIALISED | XENBUS_STATE_CONNECTED) => {
+                let name = name.unwrap_or_else(|| devid.to_string());
+                match self.connect(domid, &frontend, &name, &output).await {
+                    Ok(device) => {
+                        self.devices.insert((domid, devid), device);
+                        self.set_state(&path, XENBUS_STATE_CONNECTED).await?;
+                        info!(
+                            "connected console channel {} of domain {} to {:?}",
+                            name, domid, output
+                        );
+                    }
+                    Err(error) => {
+                        self.set_state(&path, XENBUS_STATE_CLOSED).await?;
+                        return Err(error);
+                    }
+                }
+            }
+
+            (
+                XENBUS_STATE_INIT_WAIT | XENBUS_STATE_CONNECTED,
+                XENBUS_STATE_CLOSING | XENBUS_STATE_CLOSED,
+            ) => {
+                self.devices.remove(&(domid, devid));
+                self.set_state(&path, XENBUS_STATE_CLOSED).await?;
+                info!("disconnected console channel {} of domain {}", devid, domid);
+            }
+
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // channels left as a pty are for xenconsoled, unless an output was registered for them.
+    async fn resolve_output(
+        &self,
+        path: &str,
+        name: Option<&str>,
+    ) -> Result<Option<ChannelOutput>> {
+        if let Some(output) = name.and_then(|name| self.outputs.get(name)) {
+            return Ok(Some(output.clone()));
+        }
+        let connection = self
+            .store
+            .read_string(format!("{}/connection", path))
+            .await?
+            .unwrap_or_else(|| "pty".to_string());
+        match connection.as_str() {
+            "pty" => Ok(None),
+            "callback" => Err(Error::UnsupportedOutput(format!(
+                "no callback registered for channel {}",
+                name.unwrap_or_default()
+            ))),
+            connection => {
+                let output_path = self.store.read_string(format!("{}/path", path)).await?;
+                Ok(Some(ChannelOutput::from_backend(
+                    connection,
+                    output_path.as_deref(),
+                )?))
+            }
+        }
+    }
+
+    async fn connect(
+        &self,
+        domid: u32,
+        frontend: &str,
+        name: &str,
+        output: &ChannelOutput,
+    ) -> Result<ConsoleDevice> {
+        let ring_ref = self
+            .read_u32(format!("{}/ring-ref", frontend))
+            .await?
+            .ok_or_else(|| Error::FrontendParameterMissing("ring-ref".to_string()))?;
+        let port = self
+            .read_u32(format!("{}/port", frontend))
+            .await?
+            .ok_or_else(|| Error::FrontendParameterMissing("port".to_string()))?;
+
+        let memory = self
+            .gnttab
+            .map_grant_refs(
+                vec![GrantRef {
+                    domid,
+                    reference: ring_ref,
+                }],
+                true,
+                true,
+            )
+            .await?;
+        let ring = ConsoleRing::new(memory)?;
+        let channel = self.evtchn.bind(domid, port).await?;
+        let (sink, input) = output.open().await?;
+
+        let name = name.to_string();
+        let task = tokio::task::spawn(async move {
+            if let Err(error) = ring.shuttle(channel, sink, input).await {
+                error!(
+                    "console channel {} of domain {} failed: {}",
+                    name, domid, error
+                );
+            }
+        });
+        Ok(ConsoleDevice { task })
+    }
+
+    async fn set_state(&self, path: &str, state: u32) -> Result<()> {
+        self.store
+            .write_string(format!("{}/state", path), &state.to_string())
+            .await?;
+        Ok(())
+    }
+
+    async fn read_u32(&self, path: String) -> Result<Option<u32>> {
+        match self.store.read_string(path).await? {
+            Some(value) => Ok(Some(u32::from_str(value.trim())?)),
+            None => Ok(None),
+        }
+    }
+}
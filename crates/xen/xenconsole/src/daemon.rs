@@ -0,0 +1,332 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use log::{debug, error, info, warn};
+use tokio::{
+    net::UnixListener,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use xencall::XenCall;
+use xenevtchn::EventChannelService;
+use xenstore::{XsdClient, XsdInterface};
+
+use crate::{
+    error::{Error, Result},
+    foreign::ForeignMemory,
+    logfile::ConsoleLog,
+    output::{accept_clients, INPUT_BACKLOG, SOCKET_BACKLOG},
+    pty::ConsolePty,
+    ring::{ConsoleRing, ConsoleSink},
+};
+
+const DEFAULT_MAX_LOG_SIZE: u64 = 4 * 1024 * 1024;
+const DEFAULT_MAX_LOG_FILES: u32 = 4;
+
+struct ZoneConsole {
+    tasks: Vec<JoinHandle<()>>,
+    socket: Option<PathBuf>,
+}
+
+impl Drop for ZoneConsole {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Some(socket) = &self.socket {
+            let _ = std::fs::remove_file(socket);
+        }
+    }
+}
+
+// output of a zone goes to its log and to everyone attached.
+struct ZoneSink {
+    domid: u32,
+    clients: broadcast::Sender<Vec<u8>>,
+    log: Option<ConsoleLog>,
+}
+
+impl ConsoleSink for ZoneSink {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(log) = self.log.as_mut() {
+            if let Err(error) = log.write(data).await {
+                warn!(
+                    "failed to write console log of domain {}, logging stopped: {}",
+                    self.domid, error
+                );
+                self.log = None;
+            }
+        }
+        let _ = self.clients.send(data.to_vec());
+        Ok(())
+    }
+}
+
+// serves the default console of every zone, in place of xenconsoled. the
+// console page and event channel are taken from the frontend keys written
+// by the domain builder.
+pub struct ConsoleDaemon {
+    store: XsdClient,
+    call: XenCall,
+    evtchn: EventChannelService,
+    backend_domid: u32,
+    backend_type: String,
+    log_dir: Option<PathBuf>,
+    socket_dir: Option<PathBuf>,
+    pty: bool,
+    max_log_size: u64,
+    max_log_files: u32,
+    zones: HashMap<u32, ZoneConsole>,
+}
+
+impl ConsoleDaemon {
+    pub async fn new() -> Result<ConsoleDaemon> {
+        Ok(ConsoleDaemon {
+            store: XsdClient::open().await?,
+            call: XenCall::open(0)?,
+            evtchn: EventChannelService::open().await?,
+            backend_domid: 0,
+            backend_type: "console".to_string(),
+            log_dir: None,
+            socket_dir: None,
+            pty: false,
+            max_log_size: DEFAULT_MAX_LOG_SIZE,
+            max_log_files: DEFAULT_MAX_LOG_FILES,
+            zones: HashMap::new(),
+        })
+    }
+
+    pub fn backend_domid(&mut self, backend_domid: u32) -> &mut Self {
+        self.backend_domid = backend_domid;
+        self
+    }
+
+    pub fn backend_type(&mut self, backend_type: impl AsRef<str>) -> &mut Self {
+        self.backend_type = backend_type.as_ref().to_string();
+        self
+    }
+
+    // writes the output of each zone to <log_dir>/<domid>.log.
+    pub fn log_dir(&mut self, log_dir: impl Into<PathBuf>) -> &mut Self {
+        self.log_dir = Some(log_dir.into());
+        self
+    }
+
+    // lets any number of clients attach to <socket_dir>/<domid>.sock.
+    pub fn socket_dir(&mut self, socket_dir: impl Into<PathBuf>) -> &mut Self {
+        self.socket_dir = Some(socket_dir.into());
+        self
+    }
+
+    // opens a pty for each zone and publishes it as the tty of its console.
+    pub fn pty(&mut self, pty: bool) -> &mut Self {
+        self.pty = pty;
+        self
+    }
+
+    pub fn max_log_size(&mut self, max_log_size: u64) -> &mut Self {
+        self.max_log_size = max_log_size;
+        self
+    }
+
+    pub fn max_log_files(&mut self, max_log_files: u32) -> &mut Self {
+        self.max_log_files = max_log_files;
+        self
+    }
+
+    fn backend_path(&self) -> String {
+        format!(
+            "/local/domain/{}/backend/{}",
+            self.backend_domid, self.backend_type
+        )
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        if let Some(log_dir) = &self.log_dir {
+            tokio::fs::create_dir_all(log_dir).await?;
+        }
+        if let Some(socket_dir) = &self.socket_dir {
+            tokio::fs::create_dir_all(socket_dir).await?;
+        }
+
+        let backend_path = self.backend_path();
+        let mut watch = self.store.create_multi_watch().await?;
+        for path in [backend_path.as_str(), "@releaseDomain"] {
+            self.store.bind_watch_id(watch.id, path).await?;
+            watch.add_path(path);
+        }
+        info!("serving zone consoles from {}", backend_path);
+
+        while watch.receiver.recv().await.is_some() {
+            if let Err(error) = self.scan().await {
+                error!("failed to process zone consoles: {}", error);
+            }
+        }
+        Ok(())
+    }
+
+    async fn scan(&mut self) -> Result<()> {
+        let backend_path = self.backend_path();
+        let mut present = HashSet::new();
+        for domid in self.store.list(&backend_path).await? {
+            let Ok(domid) = u32::from_str(&domid) else {
+                continue;
+            };
+            for devid in self
+                .store
+                .list(format!("{}/{}", backend_path, domid))
+                .await?
+            {
+                let path = format!("{}/{}/{}", backend_path, domid, devid);
+                let Some(frontend) = self.store.read_string(format!("{}/frontend", path)).await?
+                else {
+                    continue;
+                };
+                // named channels are served by the console backend.
+                if frontend.contains("/device/") {
+                    continue;
+                }
+                // the frontend goes away with the zone, before the backend does.
+                let Some(ring_ref) = self
+                    .store
+                    .read_string(format!("{}/ring-ref", frontend))
+                    .await?
+                else {
+                    continue;
+                };
+                present.insert(domid);
+                if self.zones.contains_key(&domid) {
+                    continue;
+                }
+                match self.attach(domid, &frontend, &ring_ref).await {
+                    Ok(zone) => {
+                        self.zones.insert(domid, zone);
+                        info!("attached to console of domain {}", domid);
+                    }
+                    Err(error) => {
+                        warn!("failed to attach to console of domain {}: {}", domid, error)
+                    }
+                }
+            }
+        }
+
+        self.zones.retain(|domid, _| {
+            let keep = present.contains(domid);
+            if !keep {
+                debug!("console of domain {} was removed", domid);
+            }
+            keep
+        });
+        Ok(())
+    }
+
+    async fn attach(&self, domid: u32, frontend: &str, ring_ref: &str) -> Result<ZoneConsole> {
+        let mfn = u64::from_str(ring_ref.trim())?;
+        let port = self
+            .store
+            .read_string(format!("{}/port", frontend))
+            .await?
+            .ok_or_else(|| Error::FrontendParameterMissing("port".to_string()))?;
+        let port = u32::from_str(port.trim())?;
+
+        let memory = ForeignMemory::map(&self.call, domid, &[mfn]).await?;
+        let ring = ConsoleRing::new(memory)?;
+        let channel = self.evtchn.bind(domid, port).await?;
+
+        let (clients, _) = broadcast::channel(SOCKET_BACKLOG);
+        let (input, receiver) = mpsc::channel(INPUT_BACKLOG);
+        let mut zone = ZoneConsole {
+            tasks: Vec::new(),
+            socket: None,
+        };
+
+        if let Some(socket_dir) = &self.socket_dir {
+            let path = socket_dir.join(format!("{}.sock", domid));
+            let _ = tokio::fs::remove_file(&path).await;
+            let listener = UnixListener::bind(&path)?;
+            zone.socket = Some(path);
+            zone.tasks.push(tokio::task::spawn(accept_clients(
+                listener,
+                clients.clone(),
+                input.clone(),
+            )));
+        }
+
+        if self.pty {
+            let pty = ConsolePty::open()?;
+            self.store
+                .write_string(format!("{}/tty", frontend), pty.path())
+                .await?;
+            debug!("console of domain {} is on {}", domid, pty.path());
+            zone.tasks.push(tokio::task::spawn(serve_pty(
+                pty,
+                clients.subscribe(),
+                input.clone(),
+            )));
+        }
+
+        let log = match &self.log_dir {
+            Some(log_dir) => Some(
+                ConsoleLog::open(
+                    log_dir.join(format!("{}.log", domid)),
+                    self.max_log_size,
+                    self.max_log_files,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+        let sink = ZoneSink {
+            domid,
+            clients,
+            log,
+        };
+        drop(input);
+
+        zone.tasks.push(tokio::task::spawn(async move {
+            if let Err(error) = ring.shuttle(channel, sink, receiver).await {
+                error!("console of domain {} failed: {}", domid, error);
+            }
+        }));
+        Ok(zone)
+    }
+}
+
+async fn serve_pty(
+    pty: ConsolePty,
+    mut output: broadcast::Receiver<Vec<u8>>,
+    input: mpsc::Sender<Vec<u8>>,
+) {
+    let mut buffer = vec![0u8; 1024];
+    loop {
+        tokio::select! {
+            data = output.recv() => match data {
+                // output is dropped while the terminal is not being read.
+                Ok(data) => {
+                    if let Err(error) = pty.try_write(&data) {
+                        warn!("failed to write console pty {}: {}", pty.path(), error);
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+
+            size = pty.read(&mut buffer) => match size {
+                Ok(0) => break,
+                Ok(size) => {
+                    if input.send(buffer[..size].to_vec()).await.is_err() {
+                        break;
+                    }
+                }
+                Err(error) => {
+                    warn!("failed to read console pty {}: {}", pty.path(), error);
+                    break;
+                }
+            },
+        }
+    }
+}
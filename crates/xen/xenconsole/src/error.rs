@@ -0,0 +1,28 @@
+use std::io;
+use std::num::ParseIntError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io issue encountered: {0}")]
+    Io(#[from] io::Error),
+    #[error("xenstore error: {0}")]
+    XenStore(#[from] xenstore::error::Error),
+    #[error("grant table error: {0}")]
+    GrantTable(#[from] xengnt::error::Error),
+    #[error("xen call error: {0}")]
+    XenCall(#[from] xencall::error::Error),
+    #[error("event channel error: {0}")]
+    EventChannel(#[from] xenevtchn::error::Error),
+    #[error("unable to parse integer: {0}")]
+    ParseInt(#[from] ParseIntError),
+    #[error("invalid console ring: {0}")]
+    InvalidRing(String),
+    #[error("frontend parameter missing: {0}")]
+    FrontendParameterMissing(String),
+    #[error("unsupported channel output: {0}")]
+    UnsupportedOutput(String),
+    #[error("failed to map console page of domain {0}")]
+    ForeignMapFailed(u32),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
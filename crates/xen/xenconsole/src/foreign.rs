@@ -0,0 +1,65 @@
+use std::ffi::c_void;
+
+use log::{debug, warn};
+use xencall::XenCall;
+use xengnt::{memory::SharedMemory, PAGE_SIZE};
+
+use crate::error::{Error, Result};
+
+// pages of another domain mapped through privcmd, such as the console page
+// the domain builder set aside for a zone.
+pub struct ForeignMemory {
+    addr: u64,
+    length: usize,
+}
+
+impl ForeignMemory {
+    pub async fn map(call: &XenCall, domid: u32, frames: &[u64]) -> Result<ForeignMemory> {
+        let length = frames.len() * PAGE_SIZE;
+        let addr = call
+            .mmap(0, length as u64)
+            .await
+            .ok_or(Error::ForeignMapFailed(domid))?;
+        // the mapping is unmapped on drop if the batch fails.
+        let memory = ForeignMemory { addr, length };
+        let result = call
+            .mmap_batch(domid, frames.len() as u64, addr, frames.to_vec())
+            .await?;
+        if result != 0 {
+            return Err(Error::ForeignMapFailed(domid));
+        }
+        debug!(
+            "mapped {} foreign pages of domain {} at {:#x}",
+            frames.len(),
+            domid,
+            addr
+        );
+        Ok(memory)
+    }
+}
+
+unsafe impl SharedMemory for ForeignMemory {
+    fn ptr(&self) -> *mut u8 {
+        self.addr as *mut u8
+    }
+
+    fn len(&self) -> usize {
+        self.length
+    }
+}
+
+unsafe impl Send for ForeignMemory {}
+unsafe impl Sync for ForeignMemory {}
+
+impl Drop for ForeignMemory {
+    fn drop(&mut self) {
+        let result = unsafe { libc::munmap(self.addr as *mut c_void, self.length) };
+        if result != 0 {
+            warn!(
+                "failed to unmap foreign memory at {:#x}: {}",
+                self.addr,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
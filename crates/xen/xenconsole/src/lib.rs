@@ -0,0 +1,8 @@
+pub mod backend;
+pub mod daemon;
+pub mod error;
+pub mod foreign;
+pub mod logfile;
+pub mod output;
+pub mod pty;
+pub mod ring;
@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::error::Result;
+
+// a log that moves to <path>.1 once it reaches max_size, shifting older logs
+// up to <path>.<max_files>. a max_size of zero never rotates.
+pub struct ConsoleLog {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl ConsoleLog {
+    pub async fn open(path: impl AsRef<Path>, max_size: u64, max_files: u32) -> Result<ConsoleLog> {
+        let path = path.as_ref().to_path_buf();
+        let file = Self::open_file(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(ConsoleLog {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.max_size > 0 && self.size > 0 && self.size + data.len() as u64 > self.max_size {
+            self.rotate().await?;
+        }
+        self.file.write_all(data).await?;
+        // tokio hands writes to a blocking task, so flush before the log can be dropped.
+        self.file.flush().await?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        self.file.flush().await?;
+        if self.max_files == 0 {
+            self.file.set_len(0).await?;
+            self.size = 0;
+            return Ok(());
+        }
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if tokio::fs::try_exists(&from).await? {
+                tokio::fs::rename(&from, self.rotated_path(index + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&self.path, self.rotated_path(1)).await?;
+        self.file = Self::open_file(&self.path).await?;
+        self.size = 0;
+        debug!("rotated console log {:?}", self.path);
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    async fn open_file(path: &Path) -> Result<File> {
+        Ok(OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::ConsoleLog;
+
+    fn read(path: impl AsRef<Path>) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn rotates_at_max_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.log");
+        let mut log = ConsoleLog::open(&path, 8, 2).await.unwrap();
+        log.write(b"abcd").await.unwrap();
+        log.write(b"efgh").await.unwrap();
+        assert_eq!(read(&path), "abcdefgh");
+        assert!(!dir.path().join("console.log.1").exists());
+
+        log.write(b"i").await.unwrap();
+        assert_eq!(read(&path), "i");
+        assert_eq!(read(dir.path().join("console.log.1")), "abcdefgh");
+    }
+
+    #[tokio::test]
+    async fn shifts_rotated_logs_up_to_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.log");
+        let mut log = ConsoleLog::open(&path, 4, 2).await.unwrap();
+        for chunk in ["aaaa", "bbbb", "cccc", "dddd"] {
+            log.write(chunk.as_bytes()).await.unwrap();
+        }
+        assert_eq!(read(&path), "dddd");
+        assert_eq!(read(dir.path().join("console.log.1")), "cccc");
+        assert_eq!(read(dir.path().join("console.log.2")), "bbbb");
+        assert!(!dir.path().join("console.log.3").exists());
+    }
+
+    #[tokio::test]
+    async fn zero_max_size_never_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.log");
+        let mut log = ConsoleLog::open(&path, 0, 2).await.unwrap();
+        for _ in 0..16 {
+            log.write(b"abcdefgh").await.unwrap();
+        }
+        assert_eq!(read(&path).len(), 128);
+        assert!(!dir.path().join("console.log.1").exists());
+    }
+
+    #[tokio::test]
+    async fn zero_max_files_truncates_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("console.log");
+        std::fs::write(&path, "old").unwrap();
+        let mut log = ConsoleLog::open(&path, 4, 0).await.unwrap();
+        log.write(b"new").await.unwrap();
+        assert_eq!(read(&path), "new");
+        assert!(!dir.path().join("console.log.1").exists());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use log::{debug, warn};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+
+use crate::{
+    error::{Error, Result},
+    ring::ConsoleSink,
+};
+
+pub type ChannelCallback = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+pub(crate) const SOCKET_BACKLOG: usize = 64;
+pub(crate) const INPUT_BACKLOG: usize = 64;
+
+// where the output of a console channel goes.
+#[derive(Clone)]
+pub enum ChannelOutput {
+    // every client connected to the socket sees the output and may write input.
+    Socket(PathBuf),
+    File(PathBuf),
+    Callback(ChannelCallback),
+}
+
+impl fmt::Debug for ChannelOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelOutput::Socket(path) => f.debug_tuple("Socket").field(path).finish(),
+            ChannelOutput::File(path) => f.debug_tuple("File").field(path).finish(),
+            ChannelOutput::Callback(_) => f.write_str("Callback"),
+        }
+    }
+}
+
+impl ChannelOutput {
+    // the output described by the connection and path backend keys.
+    pub fn from_backend(connection: &str, path: Option<&str>) -> Result<ChannelOutput> {
+        let path = || {
+            path.map(PathBuf::from)
+                .ok_or_else(|| Error::UnsupportedOutput(format!("{} without a path", connection)))
+        };
+        match connection {
+            "socket" => Ok(ChannelOutput::Socket(path()?)),
+            "file" => Ok(ChannelOutput::File(path()?)),
+            other => Err(Error::UnsupportedOutput(other.to_string())),
+        }
+    }
+
+    // starts delivering output, returning the sink and where input from it arrives.
+    pub(crate) async fn open(&self) -> Result<(ChannelSink, mpsc::Receiver<Vec<u8>>)> {
+        let (input, receiver) = mpsc::channel(INPUT_BACKLOG);
+        let sink = match self {
+            ChannelOutput::Socket(path) => {
+                let _ = tokio::fs::remove_file(path).await;
+                let listener = UnixListener::bind(path)?;
+                let (clients, _) = broadcast::channel(SOCKET_BACKLOG);
+                let accept = tokio::task::spawn(accept_clients(listener, clients.clone(), input));
+                ChannelSink::Socket {
+                    path: path.clone(),
+                    clients,
+                    accept,
+                }
+            }
+            ChannelOutput::File(path) => ChannelSink::File(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            ),
+            ChannelOutput::Callback(callback) => ChannelSink::Callback(callback.clone()),
+        };
+        Ok((sink, receiver))
+    }
+}
+
+pub(crate) enum ChannelSink {
+    Socket {
+        path: PathBuf,
+        clients: broadcast::Sender<Vec<u8>>,
+        accept: JoinHandle<()>,
+    },
+    File(File),
+    Callback(ChannelCallback),
+}
+
+impl ConsoleSink for ChannelSink {
+    async fn write(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            // output is dropped while nobody is attached.
+            ChannelSink::Socket { clients, .. } => {
+                let _ = clients.send(data.to_vec());
+            }
+            ChannelSink::File(file) => file.write_all(data).await?,
+            ChannelSink::Callback(callback) => callback(data),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ChannelSink {
+    fn drop(&mut self) {
+        if let ChannelSink::Socket { path, accept, .. } = self {
+            accept.abort();
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+pub(crate) async fn accept_clients(
+    listener: UnixListener,
+    clients: broadcast::Sender<Vec<u8>>,
+    input: mpsc::Sender<Vec<u8>>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                warn!("failed to accept console client: {}", error);
+                continue;
+            }
+        };
+        tokio::task::spawn(serve_client(stream, clients.subscribe(), input.clone()));
+    }
+}
+
+async fn serve_client(
+    stream: UnixStream,
+    mut output: broadcast::Receiver<Vec<u8>>,
+    input: mpsc::Sender<Vec<u8>>,
+) {
+    let (mut reader, mut writer) = stream.into_split();
+    let mut buffer = vec![0u8; 1024];
+    loop {
+        tokio::select! {
+            data = output.recv() => match data {
+                Ok(data) => {
+                    if writer.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("console client fell behind by {} writes", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+
+            size = reader.read(&mut buffer) => match size {
+                Ok(0) | Err(_) => break,
+                Ok(size) => {
+                    if input.send(buffer[..size].to_vec()).await.is_err() {
+                        break;
+                    }
+                }
+            },
+        }
+    }
+}
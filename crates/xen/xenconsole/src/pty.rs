@@ -0,0 +1,120 @@
+use std::{
+    ffi::CStr,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    ptr,
+};
+
+use tokio::io::unix::AsyncFd;
+
+use crate::error::Result;
+
+// a pseudo terminal whose slave end can be opened to attach to a console.
+pub struct ConsolePty {
+    master: AsyncFd<OwnedFd>,
+    // holding the slave open keeps the master readable while nobody is attached.
+    _slave: OwnedFd,
+    path: String,
+}
+
+impl ConsolePty {
+    pub fn open() -> Result<ConsolePty> {
+        let mut master = -1;
+        let mut slave = -1;
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let master = unsafe { OwnedFd::from_raw_fd(master) };
+        let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+
+        let mut name = [0 as libc::c_char; 128];
+        let result = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if result != 0 {
+            return Err(io::Error::from_raw_os_error(result).into());
+        }
+        let path = unsafe { CStr::from_ptr(name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+
+        unsafe {
+            let flags = libc::fcntl(master.as_raw_fd(), libc::F_GETFL);
+            if flags < 0
+                || libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0
+            {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+
+        Ok(ConsolePty {
+            master: AsyncFd::new(master)?,
+            _slave: slave,
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub async fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.master.readable().await?;
+            match guard.try_io(|master| {
+                let result = unsafe {
+                    libc::read(
+                        master.as_raw_fd(),
+                        buffer.as_mut_ptr() as *mut libc::c_void,
+                        buffer.len(),
+                    )
+                };
+                if result < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(result as usize)
+                }
+            }) {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    // writes what fits without blocking, so a stalled terminal never holds up the zone.
+    pub fn try_write(&self, data: &[u8]) -> io::Result<usize> {
+        let result = unsafe {
+            libc::write(
+                self.master.get_ref().as_raw_fd(),
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+            )
+        };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::WouldBlock {
+                return Ok(0);
+            }
+            return Err(error);
+        }
+        Ok(result as usize)
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    future::Future,
+    ptr::copy_nonoverlapping,
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+use tokio::sync::mpsc;
+use xenevtchn::BoundEventChannel;
+use xengnt::{memory::SharedMemory, PAGE_SIZE};
+
+use crate::error::{Error, Result};
+
+// layout of struct xencons_interface from xen/include/public/io/console.h.
+const INTF_IN: usize = 0;
+const INTF_IN_SIZE: usize = 1024;
+const INTF_OUT: usize = 1024;
+const INTF_OUT_SIZE: usize = 2048;
+const INTF_IN_CONS: usize = 3072;
+const INTF_IN_PROD: usize = 3076;
+const INTF_OUT_CONS: usize = 3080;
+const INTF_OUT_PROD: usize = 3084;
+
+// where a console ring delivers what the zone writes.
+pub(crate) trait ConsoleSink: Send {
+    fn write(&mut self, data: &[u8]) -> impl Future<Output = Result<()>> + Send;
+}
+
+// the backend end of a console ring: the zone writes to 'out' and reads from 'in'.
+pub struct ConsoleRing<M: SharedMemory> {
+    memory: M,
+}
+
+impl<M: SharedMemory> ConsoleRing<M> {
+    pub fn new(memory: M) -> Result<Self> {
+        if memory.len() < PAGE_SIZE {
+            return Err(Error::InvalidRing(format!(
+                "{} bytes cannot hold a console interface",
+                memory.len()
+            )));
+        }
+        Ok(ConsoleRing { memory })
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    // takes as much as the zone has written, returning how many bytes were read.
+    pub fn read_output(&self, buffer: &mut [u8]) -> usize {
+        let cons = self.index(INTF_OUT_CONS).load(Ordering::Relaxed);
+        let prod = self.index(INTF_OUT_PROD).load(Ordering::Acquire);
+        // never trust the zone to produce more than the ring holds.
+        let available = (prod.wrapping_sub(cons) as usize).min(INTF_OUT_SIZE);
+        let length = available.min(buffer.len());
+        if length == 0 {
+            return 0;
+        }
+        let start = cons as usize & (INTF_OUT_SIZE - 1);
+        let first = length.min(INTF_OUT_SIZE - start);
+        unsafe {
+            let base = self.memory.ptr().add(INTF_OUT);
+            copy_nonoverlapping(base.add(start), buffer.as_mut_ptr(), first);
+            copy_nonoverlapping(base, buffer[first..].as_mut_ptr(), length - first);
+        }
+        fence(Ordering::SeqCst);
+        self.index(INTF_OUT_CONS)
+            .store(cons.wrapping_add(length as u32), Ordering::Release);
+        length
+    }
+
+    // gives the zone as much input as fits, returning how many bytes were written.
+    pub fn write_input(&self, buffer: &[u8]) -> usize {
+        let prod = self.index(INTF_IN_PROD).load(Ordering::Relaxed);
+        let cons = self.index(INTF_IN_CONS).load(Ordering::Acquire);
+        let used = (prod.wrapping_sub(cons) as usize).min(INTF_IN_SIZE);
+        let length = (INTF_IN_SIZE - used).min(buffer.len());
+        if length == 0 {
+            return 0;
+        }
+        let start = prod as usize & (INTF_IN_SIZE - 1);
+        let first = length.min(INTF_IN_SIZE - start);
+        unsafe {
+            let base = self.memory.ptr().add(INTF_IN);
+            copy_nonoverlapping(buffer.as_ptr(), base.add(start), first);
+            copy_nonoverlapping(buffer[first..].as_ptr(), base, length - first);
+        }
+        fence(Ordering::SeqCst);
+        self.index(INTF_IN_PROD)
+            .store(prod.wrapping_add(length as u32), Ordering::Release);
+        length
+    }
+
+    fn index(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*(self.memory.ptr().add(offset) as *const AtomicU32) }
+    }
+
+    // moves output from the zone to the sink and input from the sink to the zone.
+    pub(crate) async fn shuttle(
+        self,
+        channel: BoundEventChannel,
+        mut sink: impl ConsoleSink,
+        input: mpsc::Receiver<Vec<u8>>,
+    ) -> Result<()> {
+        let mut input = Some(input);
+        let mut pending = Vec::new();
+        let mut buffer = vec![0u8; INTF_OUT_SIZE];
+        loop {
+            let mut progress = false;
+            loop {
+                let size = self.read_output(&mut buffer);
+                if size == 0 {
+                    break;
+                }
+                sink.write(&buffer[..size]).await?;
+                progress = true;
+            }
+            if !pending.is_empty() {
+                let size = self.write_input(&pending);
+                pending.drain(..size);
+                progress |= size > 0;
+            }
+            if progress {
+                channel.service.notify(channel.local_port).await?;
+            }
+
+            tokio::select! {
+                _ = channel.receiver.notified() => {
+                    channel.unmask().await?;
+                }
+
+                data = async {
+                    match input.as_mut() {
+                        Some(input) => input.recv().await,
+                        None => std::future::pending().await,
+                    }
+                }, if pending.is_empty() => match data {
+                    Some(data) => pending.extend_from_slice(&data),
+                    None => input = None,
+                },
+            }
+        }
+    }
+}
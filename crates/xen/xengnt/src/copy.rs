@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    error::{Error, Result},
+    GrantDevice, PAGE_SIZE,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ForeignGrant {
+    pub domid: u16,
+    pub reference: u32,
+    pub offset: u16,
+}
+
+pub enum GrantCopySource<'a> {
+    Grant(ForeignGrant),
+    Local(&'a [u8]),
+}
+
+pub enum GrantCopyDest<'a> {
+    Grant(ForeignGrant),
+    Local(&'a mut [u8]),
+}
+
+pub struct GrantCopySegment<'a> {
+    pub source: GrantCopySource<'a>,
+    pub dest: GrantCopyDest<'a>,
+    pub len: u16,
+}
+
+impl GrantCopySegment<'_> {
+    // a grant side of a copy has to stay within a single page.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let len = self.len as usize;
+        let check_grant = |grant: &ForeignGrant| {
+            if grant.offset as usize + len > PAGE_SIZE {
+                return Err(Error::InvalidGrantCopy(format!(
+                    "{} bytes at offset {} of grant {} cross a page boundary",
+                    len, grant.offset, grant.reference
+                )));
+            }
+            Ok(())
+        };
+        let check_local = |local: usize| {
+            if local < len {
+                return Err(Error::InvalidGrantCopy(format!(
+                    "local buffer of {} bytes is smaller than the {} byte segment",
+                    local, len
+                )));
+            }
+            Ok(())
+        };
+
+        match &self.source {
+            GrantCopySource::Grant(grant) => check_grant(grant)?,
+            GrantCopySource::Local(buffer) => check_local(buffer.len())?,
+        }
+        match &self.dest {
+            GrantCopyDest::Grant(grant) => check_grant(grant)?,
+            GrantCopyDest::Local(buffer) => check_local(buffer.len())?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GrantCopyStatus {
+    Okay,
+    GeneralError,
+    BadDomain,
+    BadGrantRef,
+    BadHandle,
+    BadVirtAddr,
+    BadDevAddr,
+    NoDeviceSpace,
+    PermissionDenied,
+    BadPage,
+    BadCopyArg,
+    AddressTooBig,
+    Again,
+    Unknown(i16),
+}
+
+impl GrantCopyStatus {
+    pub fn is_ok(&self) -> bool {
+        *self == GrantCopyStatus::Okay
+    }
+}
+
+impl From<i16> for GrantCopyStatus {
+    fn from(value: i16) -> Self {
+        match value {
+            0 => GrantCopyStatus::Okay,
+            -1 => GrantCopyStatus::GeneralError,
+            -2 => GrantCopyStatus::BadDomain,
+            -3 => GrantCopyStatus::BadGrantRef,
+            -4 => GrantCopyStatus::BadHandle,
+            -5 => GrantCopyStatus::BadVirtAddr,
+            -6 => GrantCopyStatus::BadDevAddr,
+            -7 => GrantCopyStatus::NoDeviceSpace,
+            -8 => GrantCopyStatus::PermissionDenied,
+            -9 => GrantCopyStatus::BadPage,
+            -10 => GrantCopyStatus::BadCopyArg,
+            -11 => GrantCopyStatus::AddressTooBig,
+            -12 => GrantCopyStatus::Again,
+            other => GrantCopyStatus::Unknown(other),
+        }
+    }
+}
+
+pub trait GrantCopier {
+    // performs every segment, returning a status for each of them in order.
+    fn grant_copy(&self, segments: &mut [GrantCopySegment]) -> Result<Vec<GrantCopyStatus>>;
+}
+
+impl GrantCopier for GrantDevice {
+    fn grant_copy(&self, segments: &mut [GrantCopySegment]) -> Result<Vec<GrantCopyStatus>> {
+        GrantDevice::grant_copy(self, segments)
+    }
+}
+
+type FakePages = HashMap<(u16, u32), Box<[u8]>>;
+
+// an in-process grant table, where grants are plain pages keyed by domain and reference.
+#[derive(Default)]
+pub struct FakeGrantTable {
+    pages: Mutex<FakePages>,
+}
+
+impl FakeGrantTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn grant(&self, domid: u16, reference: u32, contents: &[u8]) {
+        let mut page = vec![0u8; PAGE_SIZE].into_boxed_slice();
+        let len = contents.len().min(PAGE_SIZE);
+        page[..len].copy_from_slice(&contents[..len]);
+        self.lock().insert((domid, reference), page);
+    }
+
+    pub fn revoke(&self, domid: u16, reference: u32) {
+        self.lock().remove(&(domid, reference));
+    }
+
+    pub fn contents(&self, domid: u16, reference: u32) -> Option<Vec<u8>> {
+        self.lock()
+            .get(&(domid, reference))
+            .map(|page| page.to_vec())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FakePages> {
+        self.pages
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl GrantCopier for FakeGrantTable {
+    fn grant_copy(&self, segments: &mut [GrantCopySegment]) -> Result<Vec<GrantCopyStatus>> {
+        for segment in segments.iter() {
+            segment.validate()?;
+        }
+
+        let mut pages = self.lock();
+        let mut statuses = Vec::with_capacity(segments.len());
+        for segment in segments.iter_mut() {
+            let len = segment.len as usize;
+            let data = match &segment.source {
+                GrantCopySource::Local(buffer) => buffer[..len].to_vec(),
+                GrantCopySource::Grant(grant) => match pages.get(&(grant.domid, grant.reference)) {
+                    Some(page) => page[grant.offset as usize..grant.offset as usize + len].to_vec(),
+                    None => {
+                        statuses.push(GrantCopyStatus::BadGrantRef);
+                        continue;
+                    }
+                },
+            };
+
+            match &mut segment.dest {
+                GrantCopyDest::Local(buffer) => buffer[..len].copy_from_slice(&data),
+                GrantCopyDest::Grant(grant) => {
+                    let Some(page) = pages.get_mut(&(grant.domid, grant.reference)) else {
+                        statuses.push(GrantCopyStatus::BadGrantRef);
+                        continue;
+                    };
+                    page[grant.offset as usize..grant.offset as usize + len].copy_from_slice(&data);
+                }
+            }
+            statuses.push(GrantCopyStatus::Okay);
+        }
+        Ok(statuses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        FakeGrantTable, ForeignGrant, GrantCopier, GrantCopyDest, GrantCopySegment,
+        GrantCopySource, GrantCopyStatus,
+    };
+    use crate::{error::Error, PAGE_SIZE};
+
+    fn grant(reference: u32, offset: u16) -> ForeignGrant {
+        ForeignGrant {
+            domid: 1,
+            reference,
+            offset,
+        }
+    }
+
+    #[test]
+    fn copies_between_grants_and_local_buffers() {
+        let table = FakeGrantTable::new();
+        table.grant(1, 10, b"hello world");
+        table.grant(1, 11, &[]);
+
+        let mut local = [0u8; 5];
+        let statuses = table
+            .grant_copy(&mut [
+                GrantCopySegment {
+                    source: GrantCopySource::Grant(grant(10, 6)),
+                    dest: GrantCopyDest::Local(&mut local),
+                    len: 5,
+                },
+                GrantCopySegment {
+                    source: GrantCopySource::Local(b"xen"),
+                    dest: GrantCopyDest::Grant(grant(11, 100)),
+                    len: 3,
+                },
+                GrantCopySegment {
+                    source: GrantCopySource::Grant(grant(10, 0)),
+                    dest: GrantCopyDest::Grant(grant(11, 0)),
+                    len: 5,
+                },
+            ])
+            .unwrap();
+        assert_eq!(statuses, vec![GrantCopyStatus::Okay; 3]);
+        assert_eq!(&local, b"world");
+        let page = table.contents(1, 11).unwrap();
+        assert_eq!(&page[..5], b"hello");
+        assert_eq!(&page[100..103], b"xen");
+    }
+
+    #[test]
+    fn reports_a_status_per_segment() {
+        let table = FakeGrantTable::new();
+        table.grant(1, 10, b"data");
+        table.grant(1, 11, b"more");
+        table.revoke(1, 11);
+
+        let mut first = [0u8; 4];
+        let mut second = [0u8; 4];
+        let statuses = table
+            .grant_copy(&mut [
+                GrantCopySegment {
+                    source: GrantCopySource::Grant(grant(11, 0)),
+                    dest: GrantCopyDest::Local(&mut first),
+                    len: 4,
+                },
+                GrantCopySegment {
+                    source: GrantCopySource::Grant(grant(10, 0)),
+                    dest: GrantCopyDest::Local(&mut second),
+                    len: 4,
+                },
+                GrantCopySegment {
+                    source: GrantCopySource::Local(b"data"),
+                    dest: GrantCopyDest::Grant(grant(12, 0)),
+                    len: 4,
+                },
+            ])
+            .unwrap();
+        assert_eq!(
+            statuses,
+            vec![
+                GrantCopyStatus::BadGrantRef,
+                GrantCopyStatus::Okay,
+                GrantCopyStatus::BadGrantRef,
+            ]
+        );
+        assert_eq!(first, [0; 4]);
+        assert_eq!(&second, b"data");
+    }
+
+    #[test]
+    fn rejects_segments_crossing_a_page() {
+        let table = FakeGrantTable::new();
+        table.grant(1, 10, &[]);
+        let mut local = [0u8; 16];
+        let result = table.grant_copy(&mut [GrantCopySegment {
+            source: GrantCopySource::Grant(grant(10, (PAGE_SIZE - 8) as u16)),
+            dest: GrantCopyDest::Local(&mut local),
+            len: 16,
+        }]);
+        assert!(matches!(result, Err(Error::InvalidGrantCopy(_))));
+
+        let result = table.grant_copy(&mut [GrantCopySegment {
+            source: GrantCopySource::Local(&[0u8; 16]),
+            dest: GrantCopyDest::Grant(grant(10, (PAGE_SIZE - 8) as u16)),
+            len: 16,
+        }]);
+        assert!(matches!(result, Err(Error::InvalidGrantCopy(_))));
+    }
+
+    #[test]
+    fn rejects_short_local_buffers_before_copying() {
+        let table = FakeGrantTable::new();
+        table.grant(1, 10, &[]);
+        let mut short = [0u8; 2];
+        let result = table.grant_copy(&mut [
+            GrantCopySegment {
+                source: GrantCopySource::Local(b"first"),
+                dest: GrantCopyDest::Grant(grant(10, 0)),
+                len: 5,
+            },
+            GrantCopySegment {
+                source: GrantCopySource::Grant(grant(10, 0)),
+                dest: GrantCopyDest::Local(&mut short),
+                len: 4,
+            },
+        ]);
+        assert!(matches!(result, Err(Error::InvalidGrantCopy(_))));
+        // the valid segment ahead of the bad one was not performed either.
+        assert_eq!(&table.contents(1, 10).unwrap()[..5], &[0; 5]);
+
+        let result = table.grant_copy(&mut [GrantCopySegment {
+            source: GrantCopySource::Local(b"abc"),
+            dest: GrantCopyDest::Grant(grant(10, 0)),
+            len: 4,
+        }]);
+        assert!(matches!(result, Err(Error::InvalidGrantCopy(_))));
+    }
+
+    #[test]
+    fn maps_hypervisor_status_codes() {
+        assert!(GrantCopyStatus::from(0).is_ok());
+        assert_eq!(GrantCopyStatus::from(-3), GrantCopyStatus::BadGrantRef);
+        assert_eq!(GrantCopyStatus::from(-12), GrantCopyStatus::Again);
+        assert_eq!(GrantCopyStatus::from(-42), GrantCopyStatus::Unknown(-42));
+        assert!(!GrantCopyStatus::from(-1).is_ok());
+    }
+}
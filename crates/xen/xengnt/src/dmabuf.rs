@@ -0,0 +1,81 @@
+use std::{
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    time::Duration,
+};
+
+use nix::errno::Errno;
+
+use crate::{
+    error::Result,
+    sys::{self, DmabufExportWaitReleased, DmabufImportRelease},
+    GrantDevice,
+};
+
+// a dma-buf backed by pages another domain granted to us.
+pub struct ExportedDmabuf {
+    pub(crate) device: GrantDevice,
+    pub(crate) fd: OwnedFd,
+}
+
+impl ExportedDmabuf {
+    // closes our descriptor and waits for every other user of the buffer to
+    // release it, after which the grants are unmapped.
+    pub fn close_and_wait(self, timeout: Duration) -> Result<()> {
+        let ExportedDmabuf { device, fd } = self;
+        let mut request = DmabufExportWaitReleased {
+            fd: fd.as_raw_fd() as u32,
+            wait_to_ms: timeout.as_millis().min(u32::MAX as u128) as u32,
+        };
+        // our descriptor holds a reference itself, so it has to be gone before
+        // waiting. when it was the last one the kernel no longer knows the
+        // buffer, which means it was already released.
+        drop(fd);
+        match unsafe { sys::dmabuf_export_wait_released(device.handle.as_raw_fd(), &mut request) } {
+            Ok(_) | Err(Errno::ENOENT) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl AsFd for ExportedDmabuf {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for ExportedDmabuf {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+// a local dma-buf whose pages are granted to another domain until dropped.
+// the kernel knows the import by its descriptor, so the borrowed one is kept
+// rather than a duplicate.
+pub struct ImportedDmabuf<'a> {
+    pub(crate) device: GrantDevice,
+    pub(crate) fd: BorrowedFd<'a>,
+    pub(crate) refs: Vec<u32>,
+}
+
+impl ImportedDmabuf<'_> {
+    pub fn refs(&self) -> &[u32] {
+        &self.refs
+    }
+}
+
+impl AsFd for ImportedDmabuf<'_> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd
+    }
+}
+
+impl Drop for ImportedDmabuf<'_> {
+    fn drop(&mut self) {
+        let mut request = DmabufImportRelease {
+            fd: self.fd.as_raw_fd() as u32,
+            reserved: 0,
+        };
+        let _ = unsafe { sys::dmabuf_import_release(self.device.handle.as_raw_fd(), &mut request) };
+    }
+}
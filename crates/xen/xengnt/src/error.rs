@@ -10,6 +10,26 @@ pub enum Error {
     StructureReadFailed,
     #[error("mmap failed: {0}")]
     MmapFailed(nix::errno::Errno),
+    #[error("access of {size} bytes at offset {offset} is outside of {length} mapped bytes")]
+    OutOfBounds {
+        offset: usize,
+        size: usize,
+        length: usize,
+    },
+    #[error("access at offset {offset} is not aligned to {align} bytes")]
+    Misaligned { offset: usize, align: usize },
+    #[error("mapping is read-only")]
+    ReadOnlyMapping,
+    #[error("invalid grant copy: {0}")]
+    InvalidGrantCopy(String),
+    #[error("invalid dma-buf: {0}")]
+    InvalidDmabuf(String),
+    #[error("invalid ring: {0}")]
+    InvalidRing(String),
+    #[error("ring is full")]
+    RingFull,
+    #[error("ring requests overflowed the available slots")]
+    RingOverflow,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
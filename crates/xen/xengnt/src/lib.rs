@@ -1,23 +1,201 @@
+pub mod copy;
+pub mod dmabuf;
 pub mod error;
+pub mod memory;
+pub mod pod;
+pub mod ring;
 pub mod sys;
 
+use copy::{GrantCopyDest, GrantCopySegment, GrantCopySource, GrantCopyStatus};
+use dmabuf::{ExportedDmabuf, ImportedDmabuf};
 use error::{Error, Result};
 use nix::errno::Errno;
+use pod::Pod;
 use std::{
     fs::{File, OpenOptions},
     marker::PhantomData,
-    os::{fd::AsRawFd, raw::c_void},
-    sync::Arc,
-    thread::sleep,
+    mem::{align_of, size_of},
+    os::{
+        fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+        raw::c_void,
+    },
+    sync::{atomic::AtomicU32, Arc},
     time::Duration,
 };
 use sys::{
-    AllocGref, DeallocGref, GetOffsetForVaddr, GrantRef, MapGrantRef, SetMaxGrants, UnmapGrantRef,
-    UnmapNotify, UNMAP_NOTIFY_CLEAR_BYTE, UNMAP_NOTIFY_SEND_EVENT,
+    AllocGref, DeallocGref, DmabufExportFromRefs, DmabufImportToRefs, GetOffsetForVaddr, GrantCopy,
+    GrantCopyForeign, GrantCopyPtr, GrantRef, MapGrantRef, SetMaxGrants, UnmapGrantRef,
+    UnmapNotify, GNTALLOC_FLAG_WRITABLE, GNTCOPY_DEST_GREF, GNTCOPY_SOURCE_GREF,
+    UNMAP_NOTIFY_CLEAR_BYTE, UNMAP_NOTIFY_SEND_EVENT,
 };
 
 use libc::{mmap, munmap, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
 
+// accessors shared by every region mapped into this process, which is
+// described by an addr, a length and whether it was mapped writable.
+macro_rules! mapped_region_accessors {
+    () => {
+        pub fn len(&self) -> usize {
+            self.length
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.length == 0
+        }
+
+        pub fn is_writable(&self) -> bool {
+            self.writable
+        }
+
+        pub fn ptr(&self) -> *mut c_void {
+            self.addr as *mut c_void
+        }
+
+        pub fn page_count(&self) -> usize {
+            self.length / PAGE_SIZE
+        }
+
+        // exclusive views, as nothing else in this process can reach the region
+        // meanwhile. the other domain may still change the contents underneath.
+        pub fn as_mut_slice(&mut self) -> Result<&mut [u8]> {
+            self.check_writable()?;
+            Ok(unsafe { std::slice::from_raw_parts_mut(self.addr as *mut u8, self.length) })
+        }
+
+        pub fn page_mut(&mut self, index: usize) -> Result<&mut [u8]> {
+            let offset = self.check_page(index)?;
+            Ok(&mut self.as_mut_slice()?[offset..offset + PAGE_SIZE])
+        }
+
+        // shared access only ever copies in and out, so the region can be
+        // handed between threads.
+        pub fn read_page(&self, index: usize) -> Result<Vec<u8>> {
+            let offset = self.check_page(index)?;
+            let mut page = vec![0u8; PAGE_SIZE];
+            self.read_bytes(offset, &mut page)?;
+            Ok(page)
+        }
+
+        pub fn read<T: Pod>(&self, offset: usize) -> Result<T> {
+            self.check_access::<T>(offset)?;
+            Ok(unsafe {
+                (self.addr as *const u8)
+                    .add(offset)
+                    .cast::<T>()
+                    .read_volatile()
+            })
+        }
+
+        pub fn write<T: Pod>(&self, offset: usize, value: T) -> Result<()> {
+            self.check_writable()?;
+            self.check_access::<T>(offset)?;
+            unsafe {
+                (self.addr as *mut u8)
+                    .add(offset)
+                    .cast::<T>()
+                    .write_volatile(value)
+            };
+            Ok(())
+        }
+
+        pub fn read_bytes(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+            self.check_range(offset, buffer.len())?;
+            unsafe { volatile_copy_from((self.addr as *const u8).add(offset), buffer) };
+            Ok(())
+        }
+
+        pub fn write_bytes(&self, offset: usize, buffer: &[u8]) -> Result<()> {
+            self.check_writable()?;
+            self.check_range(offset, buffer.len())?;
+            unsafe { volatile_copy_to(buffer, (self.addr as *mut u8).add(offset)) };
+            Ok(())
+        }
+
+        // an index shared with the other domain, such as a ring producer or consumer.
+        pub fn atomic_u32(&self, offset: usize) -> Result<&AtomicU32> {
+            self.check_access::<u32>(offset)?;
+            Ok(unsafe { &*((self.addr as *const u8).add(offset) as *const AtomicU32) })
+        }
+
+        fn unmap_notify_request(
+            &self,
+            index: u64,
+            clear_offset: Option<usize>,
+            port: Option<u32>,
+        ) -> Result<UnmapNotify> {
+            let mut action = 0;
+            let mut offset = 0;
+            if let Some(clear_offset) = clear_offset {
+                self.check_range(clear_offset, 1)?;
+                action |= UNMAP_NOTIFY_CLEAR_BYTE;
+                offset = clear_offset as u64;
+            }
+            if port.is_some() {
+                action |= UNMAP_NOTIFY_SEND_EVENT;
+            }
+            Ok(UnmapNotify {
+                index: index + offset,
+                action,
+                port: port.unwrap_or_default(),
+            })
+        }
+
+        fn check_writable(&self) -> Result<()> {
+            if !self.writable {
+                return Err(Error::ReadOnlyMapping);
+            }
+            Ok(())
+        }
+
+        fn check_range(&self, offset: usize, size: usize) -> Result<()> {
+            if offset.checked_add(size).is_none_or(|end| end > self.length) {
+                return Err(Error::OutOfBounds {
+                    offset,
+                    size,
+                    length: self.length,
+                });
+            }
+            Ok(())
+        }
+
+        fn check_access<T: Pod>(&self, offset: usize) -> Result<()> {
+            self.check_range(offset, size_of::<T>())?;
+            if !(self.addr as usize + offset).is_multiple_of(align_of::<T>()) {
+                return Err(Error::Misaligned {
+                    offset,
+                    align: align_of::<T>(),
+                });
+            }
+            Ok(())
+        }
+
+        fn check_page(&self, index: usize) -> Result<usize> {
+            if index >= self.page_count() {
+                return Err(Error::OutOfBounds {
+                    offset: index * PAGE_SIZE,
+                    size: PAGE_SIZE,
+                    length: self.length,
+                });
+            }
+            Ok(index * PAGE_SIZE)
+        }
+    };
+}
+
+// copies out of memory another domain may be writing, one volatile access
+// at a time so the compiler never assumes the contents are stable.
+unsafe fn volatile_copy_from(source: *const u8, buffer: &mut [u8]) {
+    for (index, byte) in buffer.iter_mut().enumerate() {
+        *byte = source.add(index).read_volatile();
+    }
+}
+
+unsafe fn volatile_copy_to(buffer: &[u8], dest: *mut u8) {
+    for (index, byte) in buffer.iter().enumerate() {
+        dest.add(index).write_volatile(*byte);
+    }
+}
+
 #[derive(Clone)]
 pub struct GrantDevice {
     handle: Arc<File>,
@@ -92,6 +270,104 @@ impl GrantDevice {
         }
         Ok(())
     }
+
+    pub fn grant_copy(&self, segments: &mut [GrantCopySegment]) -> Result<Vec<GrantCopyStatus>> {
+        let mut raw = Vec::with_capacity(segments.len());
+        for segment in segments.iter_mut() {
+            segment.validate()?;
+            let mut flags = 0;
+            let source = match &segment.source {
+                GrantCopySource::Grant(grant) => {
+                    flags |= GNTCOPY_SOURCE_GREF;
+                    GrantCopyPtr {
+                        foreign: GrantCopyForeign {
+                            reference: grant.reference,
+                            offset: grant.offset,
+                            domid: grant.domid,
+                        },
+                    }
+                }
+                GrantCopySource::Local(buffer) => GrantCopyPtr {
+                    virt: buffer.as_ptr() as *mut c_void,
+                },
+            };
+            let dest = match &mut segment.dest {
+                GrantCopyDest::Grant(grant) => {
+                    flags |= GNTCOPY_DEST_GREF;
+                    GrantCopyPtr {
+                        foreign: GrantCopyForeign {
+                            reference: grant.reference,
+                            offset: grant.offset,
+                            domid: grant.domid,
+                        },
+                    }
+                }
+                GrantCopyDest::Local(buffer) => GrantCopyPtr {
+                    virt: buffer.as_mut_ptr() as *mut c_void,
+                },
+            };
+            raw.push(sys::GrantCopySegment {
+                source,
+                dest,
+                len: segment.len,
+                flags,
+                status: 0,
+            });
+        }
+
+        let mut request = GrantCopy {
+            count: raw.len() as u32,
+            segments: raw.as_mut_ptr(),
+        };
+        unsafe {
+            sys::grant_copy(self.handle.as_raw_fd(), &mut request)?;
+        }
+        Ok(raw
+            .iter()
+            .map(|segment| GrantCopyStatus::from(segment.status))
+            .collect())
+    }
+
+    // maps pages granted by domid and exports them as a dma-buf, see the
+    // GNTDEV_DMA_FLAG_* flags.
+    pub fn export_dmabuf(&self, domid: u32, refs: &[u32], flags: u32) -> Result<ExportedDmabuf> {
+        if refs.is_empty() {
+            return Err(Error::InvalidDmabuf(
+                "no grant references to export".to_string(),
+            ));
+        }
+        let mut request = DmabufExportFromRefs::write(flags, domid, refs);
+        unsafe {
+            sys::dmabuf_export_from_refs(self.handle.as_raw_fd(), request.as_mut_ptr())?;
+        }
+        let fd = DmabufExportFromRefs::read(&request).ok_or(Error::StructureReadFailed)?;
+        Ok(ExportedDmabuf {
+            device: self.clone(),
+            fd: unsafe { OwnedFd::from_raw_fd(fd as i32) },
+        })
+    }
+
+    // grants the pages of a local dma-buf to domid.
+    pub fn import_dmabuf<'a>(
+        &self,
+        fd: BorrowedFd<'a>,
+        domid: u32,
+        count: u32,
+    ) -> Result<ImportedDmabuf<'a>> {
+        if count == 0 {
+            return Err(Error::InvalidDmabuf("no pages to import".to_string()));
+        }
+        let mut request = DmabufImportToRefs::write(fd.as_raw_fd() as u32, domid, count);
+        unsafe {
+            sys::dmabuf_import_to_refs(self.handle.as_raw_fd(), request.as_mut_ptr())?;
+        }
+        let refs = DmabufImportToRefs::read(count, &request).ok_or(Error::StructureReadFailed)?;
+        Ok(ImportedDmabuf {
+            device: self.clone(),
+            fd,
+            refs,
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -145,6 +421,76 @@ impl GrantAlloc {
         }
         Ok(())
     }
+
+    // grants fresh zeroed pages to a domain and maps them into this process.
+    pub fn grant_pages(&self, domid: u16, count: u32, writable: bool) -> Result<GrantedPages> {
+        let flags = if writable { GNTALLOC_FLAG_WRITABLE } else { 0 };
+        let (index, refs) = self.alloc_gref(domid, flags, count)?;
+        let length = PAGE_SIZE * refs.len();
+        let addr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                length,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                self.handle.as_raw_fd(),
+                index as i64,
+            )
+        };
+        if addr == MAP_FAILED {
+            let errno = Errno::last();
+            let _ = self.dealloc_gref(index, count);
+            return Err(Error::MmapFailed(errno));
+        }
+        Ok(GrantedPages {
+            alloc: self.clone(),
+            index,
+            refs,
+            addr: addr as u64,
+            length,
+            writable: true,
+        })
+    }
+}
+
+// pages this domain granted to another, which stay mapped and granted
+// until dropped.
+pub struct GrantedPages {
+    alloc: GrantAlloc,
+    index: u64,
+    refs: Vec<u32>,
+    addr: u64,
+    length: usize,
+    writable: bool,
+}
+
+// shared references only reach the pages through volatile and atomic accesses.
+unsafe impl Send for GrantedPages {}
+unsafe impl Sync for GrantedPages {}
+
+impl GrantedPages {
+    mapped_region_accessors!();
+
+    pub fn refs(&self) -> &[u32] {
+        &self.refs
+    }
+
+    // when the other domain is gone or the pages are dropped, clear the byte
+    // at clear_offset and/or send an event on port.
+    pub fn unmap_notify(&self, clear_offset: Option<usize>, port: Option<u32>) -> Result<()> {
+        let mut request = self.unmap_notify_request(self.index, clear_offset, port)?;
+        unsafe {
+            sys::unmap_notify(self.alloc.handle.as_raw_fd(), &mut request)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GrantedPages {
+    fn drop(&mut self) {
+        let _ = unsafe { munmap(self.addr as *mut c_void, self.length) };
+        let _ = self.alloc.dealloc_gref(self.index, self.refs.len() as u32);
+    }
 }
 
 #[derive(Clone)]
@@ -152,25 +498,36 @@ pub struct GrantTab {
     device: GrantDevice,
 }
 
-const PAGE_SIZE: usize = 4096;
+pub const PAGE_SIZE: usize = 4096;
+
+// how long to wait between attempts while the kernel is still paging in a mapping.
+const MAP_RETRY_INITIAL: Duration = Duration::from_micros(500);
+const MAP_RETRY_MAX: Duration = Duration::from_millis(50);
 
-#[allow(clippy::len_without_is_empty)]
 pub struct MappedMemory<'a> {
     gnttab: GrantTab,
     length: usize,
     addr: u64,
+    writable: bool,
     _ptr: PhantomData<&'a c_void>,
 }
 
+// shared references only reach the memory through volatile and atomic accesses.
 unsafe impl Send for MappedMemory<'_> {}
+unsafe impl Sync for MappedMemory<'_> {}
 
 impl MappedMemory<'_> {
-    pub fn len(&self) -> usize {
-        self.length
-    }
+    mapped_region_accessors!();
 
-    pub fn ptr(&self) -> *mut c_void {
-        self.addr as *mut c_void
+    // when the granting domain is gone or the memory is unmapped, clear the
+    // byte at clear_offset and/or send an event on port.
+    pub fn unmap_notify(&self, clear_offset: Option<usize>, port: Option<u32>) -> Result<()> {
+        let (index, _) = self.gnttab.device.get_offset_for_vaddr(self.addr)?;
+        let mut request = self.unmap_notify_request(index, clear_offset, port)?;
+        unsafe {
+            sys::unmap_notify(self.gnttab.device.handle.as_raw_fd(), &mut request)?;
+        }
+        Ok(())
     }
 }
 
@@ -187,50 +544,53 @@ impl GrantTab {
         })
     }
 
-    pub fn map_grant_refs<'a>(
+    pub async fn map_grant_refs<'a>(
         &self,
         refs: Vec<GrantRef>,
         read: bool,
         write: bool,
     ) -> Result<MappedMemory<'a>> {
         let (index, refs) = self.device.map_grant_ref(refs)?;
-        unsafe {
-            let mut flags: i32 = 0;
-            if read {
-                flags |= PROT_READ;
-            }
+        let mut flags: i32 = 0;
+        if read {
+            flags |= PROT_READ;
+        }
 
-            if write {
-                flags |= PROT_WRITE;
-            }
+        if write {
+            flags |= PROT_WRITE;
+        }
 
-            let addr = loop {
-                let addr = mmap(
+        let mut backoff = MAP_RETRY_INITIAL;
+        let addr = loop {
+            let addr = unsafe {
+                mmap(
                     std::ptr::null_mut(),
                     PAGE_SIZE * refs.len(),
                     flags,
                     MAP_SHARED,
                     self.device.handle.as_raw_fd(),
                     index as i64,
-                );
-                let errno = Errno::last();
-                if addr == MAP_FAILED {
-                    if errno == Errno::EAGAIN {
-                        sleep(Duration::from_micros(1000));
-                        continue;
-                    }
-                    return Err(Error::MmapFailed(errno));
-                }
-                break addr;
+                )
             };
+            if addr != MAP_FAILED {
+                break addr;
+            }
+            let errno = Errno::last();
+            if errno != Errno::EAGAIN {
+                let _ = self.device.unmap_grant_ref(index, refs.len() as u32);
+                return Err(Error::MmapFailed(errno));
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAP_RETRY_MAX);
+        };
 
-            Ok(MappedMemory {
-                gnttab: self.clone(),
-                addr: addr as u64,
-                length: PAGE_SIZE * refs.len(),
-                _ptr: PhantomData,
-            })
-        }
+        Ok(MappedMemory {
+            gnttab: self.clone(),
+            addr: addr as u64,
+            length: PAGE_SIZE * refs.len(),
+            writable: write,
+            _ptr: PhantomData,
+        })
     }
 
     fn unmap(&self, memory: &MappedMemory<'_>) -> Result<()> {
@@ -0,0 +1,89 @@
+use std::{
+    alloc::{alloc_zeroed, dealloc, Layout},
+    ptr::NonNull,
+    sync::Arc,
+};
+
+use crate::{GrantedPages, MappedMemory, PAGE_SIZE};
+
+/// Memory that may be shared with another domain.
+///
+/// # Safety
+/// `ptr()` must point to `len()` bytes that stay valid, and at the same
+/// address, for as long as the implementor is alive.
+pub unsafe trait SharedMemory: Send + Sync {
+    fn ptr(&self) -> *mut u8;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+unsafe impl SharedMemory for MappedMemory<'_> {
+    fn ptr(&self) -> *mut u8 {
+        MappedMemory::ptr(self) as *mut u8
+    }
+
+    fn len(&self) -> usize {
+        MappedMemory::len(self)
+    }
+}
+
+unsafe impl SharedMemory for GrantedPages {
+    fn ptr(&self) -> *mut u8 {
+        GrantedPages::ptr(self) as *mut u8
+    }
+
+    fn len(&self) -> usize {
+        GrantedPages::len(self)
+    }
+}
+
+unsafe impl<T: SharedMemory> SharedMemory for Arc<T> {
+    fn ptr(&self) -> *mut u8 {
+        self.as_ref().ptr()
+    }
+
+    fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+}
+
+// page-aligned memory local to this process, standing in for grant
+// mappings when both ends of a protocol live in the same address space.
+pub struct LocalMemory {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for LocalMemory {}
+unsafe impl Sync for LocalMemory {}
+
+impl LocalMemory {
+    pub fn new(pages: usize) -> LocalMemory {
+        let layout = Layout::from_size_align(pages.max(1) * PAGE_SIZE, PAGE_SIZE)
+            .expect("local memory layout is valid");
+        let ptr = unsafe { alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            std::alloc::handle_alloc_error(layout);
+        };
+        LocalMemory { ptr, layout }
+    }
+}
+
+unsafe impl SharedMemory for LocalMemory {
+    fn ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    fn len(&self) -> usize {
+        self.layout.size()
+    }
+}
+
+impl Drop for LocalMemory {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
@@ -0,0 +1,16 @@
+/// Types that can be copied in and out of memory shared with another domain.
+///
+/// # Safety
+/// Implementors must be plain old data: every bit pattern is a valid value,
+/// and the layout must be fixed (repr(C) or repr(transparent)).
+pub unsafe trait Pod: Copy + Send + Sync + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i8 {}
+unsafe impl Pod for i16 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
@@ -0,0 +1,433 @@
+use std::{
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+use crate::{
+    error::{Error, Result},
+    memory::SharedMemory,
+    pod::Pod,
+    sys::GrantRef,
+    GrantTab, MappedMemory,
+};
+
+// layout of the sring header from xen/include/public/io/ring.h.
+const SRING_REQ_PROD: usize = 0;
+const SRING_REQ_EVENT: usize = 4;
+const SRING_RSP_PROD: usize = 8;
+const SRING_RSP_EVENT: usize = 12;
+const SRING_HEADER_SIZE: usize = 64;
+
+// the standard xen split-driver ring, where request and response slots
+// share the same array of entries.
+pub struct SharedRing<Req: Pod, Rsp: Pod, M: SharedMemory = MappedMemory<'static>> {
+    memory: M,
+    size: u32,
+    entry_size: usize,
+    _entries: PhantomData<(Req, Rsp)>,
+}
+
+impl<Req: Pod, Rsp: Pod> SharedRing<Req, Rsp, MappedMemory<'static>> {
+    // maps a ring that another domain granted across one or more pages.
+    pub async fn map(gnttab: &GrantTab, domid: u32, refs: &[u32]) -> Result<Self> {
+        let memory = gnttab
+            .map_grant_refs(
+                refs.iter()
+                    .map(|reference| GrantRef {
+                        domid,
+                        reference: *reference,
+                    })
+                    .collect(),
+                true,
+                true,
+            )
+            .await?;
+        SharedRing::new(memory)
+    }
+}
+
+impl<Req: Pod, Rsp: Pod, M: SharedMemory> SharedRing<Req, Rsp, M> {
+    // attaches to a ring that has already been initialised by the other end.
+    pub fn new(memory: M) -> Result<Self> {
+        let align = align_of::<Req>().max(align_of::<Rsp>());
+        let entry_size = size_of::<Req>()
+            .max(size_of::<Rsp>())
+            .next_multiple_of(align);
+        if entry_size == 0 {
+            return Err(Error::InvalidRing(
+                "ring entries are zero sized".to_string(),
+            ));
+        }
+        if align > SRING_HEADER_SIZE || !(memory.ptr() as usize).is_multiple_of(align) {
+            return Err(Error::InvalidRing(format!(
+                "ring memory is not aligned to {} bytes",
+                align
+            )));
+        }
+
+        let entries = memory.len().saturating_sub(SRING_HEADER_SIZE) / entry_size;
+        if entries == 0 {
+            return Err(Error::InvalidRing(format!(
+                "{} bytes cannot hold a ring of {} byte entries",
+                memory.len(),
+                entry_size
+            )));
+        }
+        // the ring size is always rounded down to a power of two.
+        let size = 1u32 << (usize::BITS - 1 - entries.leading_zeros()).min(31);
+
+        Ok(SharedRing {
+            memory,
+            size,
+            entry_size,
+            _entries: PhantomData,
+        })
+    }
+
+    // attaches to the memory and resets it to an empty ring.
+    pub fn init(memory: M) -> Result<Self> {
+        let ring = SharedRing::new(memory)?;
+        unsafe { std::ptr::write_bytes(ring.memory.ptr(), 0, SRING_HEADER_SIZE) };
+        ring.index(SRING_REQ_EVENT).store(1, Ordering::Relaxed);
+        ring.index(SRING_RSP_EVENT).store(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        Ok(ring)
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn front(self) -> FrontRing<Req, Rsp, M> {
+        let req_prod_pvt = self.index(SRING_REQ_PROD).load(Ordering::Acquire);
+        let rsp_cons = self.index(SRING_RSP_PROD).load(Ordering::Acquire);
+        FrontRing {
+            ring: self,
+            req_prod_pvt,
+            rsp_cons,
+        }
+    }
+
+    pub fn back(self) -> BackRing<Req, Rsp, M> {
+        let rsp_prod_pvt = self.index(SRING_RSP_PROD).load(Ordering::Acquire);
+        let req_cons = rsp_prod_pvt;
+        BackRing {
+            ring: self,
+            rsp_prod_pvt,
+            req_cons,
+        }
+    }
+
+    fn index(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*(self.memory.ptr().add(offset) as *const AtomicU32) }
+    }
+
+    fn slot(&self, index: u32) -> *mut u8 {
+        let slot = (index & (self.size - 1)) as usize;
+        unsafe {
+            self.memory
+                .ptr()
+                .add(SRING_HEADER_SIZE + slot * self.entry_size)
+        }
+    }
+
+    fn read_slot<T: Pod>(&self, index: u32) -> T {
+        unsafe { (self.slot(index) as *const T).read_volatile() }
+    }
+
+    fn write_slot<T: Pod>(&self, index: u32, value: T) {
+        unsafe { (self.slot(index) as *mut T).write_volatile(value) }
+    }
+
+    // publishes a private producer index, returning whether the other end asked to be notified.
+    fn push(&self, prod: usize, event: usize, new: u32) -> bool {
+        let old = self.index(prod).load(Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.index(prod).store(new, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        let event = self.index(event).load(Ordering::Relaxed);
+        new.wrapping_sub(event) < new.wrapping_sub(old)
+    }
+}
+
+// the end of a ring that produces requests and consumes responses.
+pub struct FrontRing<Req: Pod, Rsp: Pod, M: SharedMemory = MappedMemory<'static>> {
+    ring: SharedRing<Req, Rsp, M>,
+    req_prod_pvt: u32,
+    rsp_cons: u32,
+}
+
+impl<Req: Pod, Rsp: Pod, M: SharedMemory> FrontRing<Req, Rsp, M> {
+    pub fn ring(&self) -> &SharedRing<Req, Rsp, M> {
+        &self.ring
+    }
+
+    pub fn free_requests(&self) -> u32 {
+        self.ring.size - self.req_prod_pvt.wrapping_sub(self.rsp_cons)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.free_requests() == 0
+    }
+
+    // queues a request, which is not visible to the back end until pushed.
+    pub fn queue_request(&mut self, request: Req) -> Result<()> {
+        if self.is_full() {
+            return Err(Error::RingFull);
+        }
+        self.ring.write_slot(self.req_prod_pvt, request);
+        self.req_prod_pvt = self.req_prod_pvt.wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn push_requests_and_check_notify(&mut self) -> bool {
+        self.ring
+            .push(SRING_REQ_PROD, SRING_REQ_EVENT, self.req_prod_pvt)
+    }
+
+    pub fn unconsumed_responses(&self) -> u32 {
+        let rsp_prod = self.ring.index(SRING_RSP_PROD).load(Ordering::Acquire);
+        rsp_prod.wrapping_sub(self.rsp_cons)
+    }
+
+    pub fn take_response(&mut self) -> Option<Rsp> {
+        if self.unconsumed_responses() == 0 {
+            return None;
+        }
+        let response = self.ring.read_slot(self.rsp_cons);
+        self.rsp_cons = self.rsp_cons.wrapping_add(1);
+        Some(response)
+    }
+
+    // asks for a notification on the next response, unless one arrived meanwhile.
+    pub fn final_check_for_responses(&mut self) -> bool {
+        if self.unconsumed_responses() != 0 {
+            return true;
+        }
+        self.ring
+            .index(SRING_RSP_EVENT)
+            .store(self.rsp_cons.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        self.unconsumed_responses() != 0
+    }
+}
+
+// the end of a ring that consumes requests and produces responses.
+pub struct BackRing<Req: Pod, Rsp: Pod, M: SharedMemory = MappedMemory<'static>> {
+    ring: SharedRing<Req, Rsp, M>,
+    rsp_prod_pvt: u32,
+    req_cons: u32,
+}
+
+impl<Req: Pod, Rsp: Pod, M: SharedMemory> BackRing<Req, Rsp, M> {
+    pub fn ring(&self) -> &SharedRing<Req, Rsp, M> {
+        &self.ring
+    }
+
+    pub fn unconsumed_requests(&self) -> u32 {
+        let req_prod = self.ring.index(SRING_REQ_PROD).load(Ordering::Acquire);
+        let requests = req_prod.wrapping_sub(self.req_cons);
+        // never trust the front end to produce more than there are free slots.
+        let space = self.ring.size - self.req_cons.wrapping_sub(self.rsp_prod_pvt);
+        requests.min(space)
+    }
+
+    pub fn take_request(&mut self) -> Result<Option<Req>> {
+        if self.req_cons.wrapping_sub(self.rsp_prod_pvt) >= self.ring.size {
+            return Err(Error::RingOverflow);
+        }
+        if self.unconsumed_requests() == 0 {
+            return Ok(None);
+        }
+        let request = self.ring.read_slot(self.req_cons);
+        self.req_cons = self.req_cons.wrapping_add(1);
+        Ok(Some(request))
+    }
+
+    // queues a response, which is not visible to the front end until pushed.
+    pub fn queue_response(&mut self, response: Rsp) -> Result<()> {
+        if self.rsp_prod_pvt == self.req_cons {
+            return Err(Error::RingFull);
+        }
+        self.ring.write_slot(self.rsp_prod_pvt, response);
+        self.rsp_prod_pvt = self.rsp_prod_pvt.wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn push_responses_and_check_notify(&mut self) -> bool {
+        self.ring
+            .push(SRING_RSP_PROD, SRING_RSP_EVENT, self.rsp_prod_pvt)
+    }
+
+    // asks for a notification on the next request, unless one arrived meanwhile.
+    pub fn final_check_for_requests(&mut self) -> bool {
+        if self.unconsumed_requests() != 0 {
+            return true;
+        }
+        self.ring
+            .index(SRING_REQ_EVENT)
+            .store(self.req_cons.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        self.unconsumed_requests() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Arc};
+
+    use super::{
+        BackRing, FrontRing, SharedRing, SRING_REQ_EVENT, SRING_REQ_PROD, SRING_RSP_EVENT,
+        SRING_RSP_PROD,
+    };
+    use crate::{error::Error, memory::LocalMemory};
+
+    type Memory = Arc<LocalMemory>;
+
+    // both ends of one ring over the same local page, with every index
+    // starting at the given value.
+    fn rings(start: u32) -> (FrontRing<u64, u64, Memory>, BackRing<u64, u64, Memory>) {
+        let memory = Arc::new(LocalMemory::new(1));
+        let ring = SharedRing::<u64, u64, Memory>::init(memory.clone()).unwrap();
+        for offset in [SRING_REQ_PROD, SRING_RSP_PROD] {
+            ring.index(offset).store(start, Ordering::Relaxed);
+        }
+        for offset in [SRING_REQ_EVENT, SRING_RSP_EVENT] {
+            ring.index(offset)
+                .store(start.wrapping_add(1), Ordering::Relaxed);
+        }
+        let back = SharedRing::new(memory).unwrap().back();
+        (ring.front(), back)
+    }
+
+    #[test]
+    fn size_is_rounded_down_to_a_power_of_two() {
+        let (front, _) = rings(0);
+        // (4096 - 64) / 8 entries fit in the page.
+        assert_eq!(front.ring().size(), 256);
+        assert_eq!(front.free_requests(), 256);
+    }
+
+    #[test]
+    fn requests_and_responses_pass_through() {
+        let (mut front, mut back) = rings(0);
+        for request in 1..=3u64 {
+            front.queue_request(request).unwrap();
+        }
+        // nothing is visible until pushed.
+        assert_eq!(back.unconsumed_requests(), 0);
+        assert!(front.push_requests_and_check_notify());
+        assert_eq!(back.unconsumed_requests(), 3);
+
+        for expected in 1..=3u64 {
+            let request = back.take_request().unwrap().unwrap();
+            assert_eq!(request, expected);
+            back.queue_response(request * 10).unwrap();
+        }
+        assert!(back.take_request().unwrap().is_none());
+        assert!(matches!(back.queue_response(0), Err(Error::RingFull)));
+        assert!(back.push_responses_and_check_notify());
+
+        assert_eq!(front.unconsumed_responses(), 3);
+        for expected in [10, 20, 30] {
+            assert_eq!(front.take_response(), Some(expected));
+        }
+        assert_eq!(front.take_response(), None);
+        assert_eq!(front.free_requests(), 256);
+    }
+
+    #[test]
+    fn front_stops_at_the_ring_size() {
+        let (mut front, mut back) = rings(0);
+        for request in 0..256u64 {
+            front.queue_request(request).unwrap();
+        }
+        assert!(front.is_full());
+        assert!(matches!(front.queue_request(0), Err(Error::RingFull)));
+        front.push_requests_and_check_notify();
+
+        // a slot is only free again once its response was consumed.
+        back.take_request().unwrap().unwrap();
+        back.queue_response(0).unwrap();
+        back.push_responses_and_check_notify();
+        assert!(front.is_full());
+        front.take_response().unwrap();
+        assert_eq!(front.free_requests(), 1);
+    }
+
+    #[test]
+    fn notifies_only_past_the_requested_event() {
+        let (mut front, mut back) = rings(0);
+        front.queue_request(1).unwrap();
+        assert!(front.push_requests_and_check_notify());
+        back.take_request().unwrap().unwrap();
+
+        // the back end has not asked for more, so the next push is silent.
+        front.queue_request(2).unwrap();
+        assert!(!front.push_requests_and_check_notify());
+        back.take_request().unwrap().unwrap();
+
+        assert!(!back.final_check_for_requests());
+        front.queue_request(3).unwrap();
+        assert!(front.push_requests_and_check_notify());
+
+        // a request pushed before final_check is reported instead of waited for.
+        assert!(back.final_check_for_requests());
+        back.take_request().unwrap().unwrap();
+
+        back.queue_response(1).unwrap();
+        assert!(back.push_responses_and_check_notify());
+        front.take_response().unwrap();
+        back.queue_response(2).unwrap();
+        assert!(!back.push_responses_and_check_notify());
+        front.take_response().unwrap();
+        assert!(!front.final_check_for_responses());
+        back.queue_response(3).unwrap();
+        assert!(back.push_responses_and_check_notify());
+    }
+
+    #[test]
+    fn indexes_wrap_around() {
+        let (mut front, mut back) = rings(u32::MAX - 100);
+        let mut next = 0u64;
+        let mut expected = 0u64;
+        // several laps of the ring, crossing the u32 wrap of every index.
+        for _ in 0..8 {
+            while !front.is_full() {
+                front.queue_request(next).unwrap();
+                next += 1;
+            }
+            front.push_requests_and_check_notify();
+            while let Some(request) = back.take_request().unwrap() {
+                back.queue_response(request).unwrap();
+            }
+            back.push_responses_and_check_notify();
+            while let Some(response) = front.take_response() {
+                assert_eq!(response, expected);
+                expected += 1;
+            }
+        }
+        assert_eq!(expected, next);
+        assert_eq!(next, 8 * 256);
+    }
+
+    #[test]
+    fn back_never_reads_more_than_the_ring_holds() {
+        let (front, mut back) = rings(0);
+        // a front end claiming more requests than there are slots.
+        front
+            .ring()
+            .index(SRING_REQ_PROD)
+            .store(1000, Ordering::Release);
+        assert_eq!(back.unconsumed_requests(), 256);
+        for _ in 0..256 {
+            back.take_request().unwrap().unwrap();
+        }
+        assert!(matches!(back.take_request(), Err(Error::RingOverflow)));
+    }
+}
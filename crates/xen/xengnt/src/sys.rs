@@ -1,4 +1,4 @@
-use std::mem::size_of;
+use std::{mem::size_of, os::raw::c_void};
 
 use nix::{ioc, ioctl_readwrite_bad};
 
@@ -83,6 +83,85 @@ pub struct UnmapNotify {
     pub port: u32,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct GrantCopyForeign {
+    pub reference: u32,
+    pub offset: u16,
+    pub domid: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union GrantCopyPtr {
+    pub virt: *mut c_void,
+    pub foreign: GrantCopyForeign,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct GrantCopySegment {
+    pub source: GrantCopyPtr,
+    pub dest: GrantCopyPtr,
+    pub len: u16,
+    pub flags: u16,
+    pub status: i16,
+}
+
+#[repr(C)]
+pub struct GrantCopy {
+    pub count: u32,
+    pub segments: *mut GrantCopySegment,
+}
+
+pub const GNTCOPY_SOURCE_GREF: u16 = 0x1;
+pub const GNTCOPY_DEST_GREF: u16 = 0x2;
+
+pub struct DmabufExportFromRefs;
+
+impl DmabufExportFromRefs {
+    pub fn write(flags: u32, domid: u32, refs: &[u32]) -> Vec<u32> {
+        let mut values = vec![flags, refs.len() as u32, 0, domid];
+        values.extend_from_slice(refs);
+        values
+    }
+
+    pub fn read(data: &[u32]) -> Option<u32> {
+        data.get(2).copied()
+    }
+}
+
+#[repr(C)]
+pub struct DmabufExportWaitReleased {
+    pub fd: u32,
+    pub wait_to_ms: u32,
+}
+
+pub struct DmabufImportToRefs;
+
+impl DmabufImportToRefs {
+    pub fn write(fd: u32, domid: u32, count: u32) -> Vec<u32> {
+        let mut values = vec![fd, count, domid, 0];
+        values.resize(4 + count.max(1) as usize, 0);
+        values
+    }
+
+    pub fn read(count: u32, data: &[u32]) -> Option<Vec<u32>> {
+        data.get(4..4 + count as usize).map(|refs| refs.to_vec())
+    }
+}
+
+#[repr(C)]
+pub struct DmabufImportRelease {
+    pub fd: u32,
+    pub reserved: u32,
+}
+
+pub const GNTDEV_DMA_FLAG_WC: u32 = 0x1;
+pub const GNTDEV_DMA_FLAG_COHERENT: u32 = 0x2;
+
+pub const GNTALLOC_FLAG_WRITABLE: u16 = 0x1;
+
 pub const UNMAP_NOTIFY_CLEAR_BYTE: u32 = 0x1;
 pub const UNMAP_NOTIFY_SEND_EVENT: u32 = 0x2;
 
@@ -112,6 +191,41 @@ ioctl_readwrite_bad!(
     ioc!(nix::sys::ioctl::NONE, 'G', 7, size_of::<UnmapNotify>()),
     UnmapNotify
 );
+ioctl_readwrite_bad!(
+    grant_copy,
+    ioc!(nix::sys::ioctl::NONE, 'G', 8, size_of::<GrantCopy>()),
+    GrantCopy
+);
+ioctl_readwrite_bad!(
+    dmabuf_export_from_refs,
+    ioc!(nix::sys::ioctl::NONE, 'G', 9, 20),
+    u32
+);
+ioctl_readwrite_bad!(
+    dmabuf_export_wait_released,
+    ioc!(
+        nix::sys::ioctl::NONE,
+        'G',
+        10,
+        size_of::<DmabufExportWaitReleased>()
+    ),
+    DmabufExportWaitReleased
+);
+ioctl_readwrite_bad!(
+    dmabuf_import_to_refs,
+    ioc!(nix::sys::ioctl::NONE, 'G', 11, 20),
+    u32
+);
+ioctl_readwrite_bad!(
+    dmabuf_import_release,
+    ioc!(
+        nix::sys::ioctl::NONE,
+        'G',
+        12,
+        size_of::<DmabufImportRelease>()
+    ),
+    DmabufImportRelease
+);
 
 #[repr(C)]
 pub struct AllocGref {
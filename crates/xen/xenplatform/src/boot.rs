@@ -7,6 +7,7 @@ use xencall::{sys::CreateDomain, XenCall};
 use crate::{
     error::{Error, Result},
     mem::PhysicalPages,
+    numa::MemoryPlacement,
     sys::XEN_PAGE_SHIFT,
     ImageLoader, PlatformKernelConfig, PlatformResourcesConfig,
 };
@@ -39,6 +40,7 @@ pub struct BootDomain {
     pub console_evtchn: u32,
     pub console_mfn: u64,
     pub cmdline: String,
+    pub placement: MemoryPlacement,
 }
 
 impl BootDomain {
@@ -187,10 +189,11 @@ pub trait BootSetupPlatform {
     ) -> Result<()> {
         self.initialize_early(domain).await?;
 
-        let mut initrd_segment = if !domain.image_info.unmapped_initrd && kernel.initrd.is_some() {
-            Some(domain.alloc_module(kernel.initrd.as_ref().unwrap()).await?)
-        } else {
-            None
+        let mut initrd_segment = match kernel.initrd.as_ref() {
+            Some(initrd) if !domain.image_info.unmapped_initrd => {
+                Some(domain.alloc_module(initrd).await?)
+            }
+            _ => None,
         };
 
         let mut kernel_segment = if self.needs_early_kernel() {
@@ -206,8 +209,12 @@ pub trait BootSetupPlatform {
             kernel_segment = Some(self.load_kernel_segment(image_loader, domain).await?);
         }
 
-        if domain.image_info.unmapped_initrd && kernel.initrd.is_some() {
-            initrd_segment = Some(domain.alloc_module(kernel.initrd.as_ref().unwrap()).await?);
+        if let Some(initrd) = kernel
+            .initrd
+            .as_ref()
+            .filter(|_| domain.image_info.unmapped_initrd)
+        {
+            initrd_segment = Some(domain.alloc_module(initrd).await?);
         }
 
         domain.initrd_segment = initrd_segment;
@@ -230,6 +237,7 @@ pub trait BootSetupPlatform {
         let target_pages = resources.assigned_memory_mb << (20 - self.page_shift());
         let total_pages = resources.max_memory_mb << (20 - self.page_shift());
         let image_info = image_loader.parse(self.hvm()).await?;
+        let placement = MemoryPlacement::plan(&call, resources, total_pages).await?;
         let mut domain = BootDomain {
             domid,
             call: call.clone(),
@@ -248,6 +256,7 @@ pub trait BootSetupPlatform {
             store_evtchn: 0,
             store_mfn: 0,
             cmdline: kernel.cmdline.clone(),
+            placement,
         };
         match self
             .initialize_internal(domid, call, image_loader, &mut domain, kernel)
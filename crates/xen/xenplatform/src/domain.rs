@@ -1,11 +1,12 @@
 use std::sync::Arc;
 
 use crate::{
-    boot::BootDomain, elfloader::ElfImageLoader, error::Error, ImageLoader, RuntimePlatform,
-    RuntimePlatformType,
+    boot::BootDomain, elfloader::ElfImageLoader, error::Error, numa::PlacementPolicy, ImageLoader,
+    RuntimePlatform, RuntimePlatformType,
 };
 use log::warn;
 use uuid::Uuid;
+use xencall::bitmap::{CpuMap, NodeMap};
 use xencall::XenCall;
 
 use crate::error::Result;
@@ -51,6 +52,18 @@ impl PlatformDomainManager {
                 PlatformDomainManager::max_memory_kb(&config.resources),
             )
             .await?;
+        // node affinity decides where the memory of the domain comes from, so it
+        // has to be in place before any is allocated.
+        if let Some(nodes) = &config.resources.node_affinity {
+            self.call.set_node_affinity(domid, nodes).await?;
+        }
+        let hard = config.resources.cpu_affinity.as_ref();
+        let soft = config.resources.soft_cpu_affinity.as_ref();
+        if hard.is_some() || soft.is_some() {
+            for vcpu in 0..config.resources.max_vcpus {
+                self.call.set_vcpu_affinity(domid, vcpu, hard, soft).await?;
+            }
+        }
         Ok(())
     }
 
@@ -135,6 +148,16 @@ pub struct PlatformResourcesConfig {
     pub assigned_vcpus: u32,
     pub max_memory_mb: u64,
     pub assigned_memory_mb: u64,
+    // physical cpus every vcpu may run on.
+    pub cpu_affinity: Option<CpuMap>,
+    // physical cpus every vcpu prefers to run on, within cpu_affinity.
+    pub soft_cpu_affinity: Option<CpuMap>,
+    // numa nodes the memory of the domain is allocated from.
+    pub node_affinity: Option<NodeMap>,
+    // how memory is spread over the nodes in node_affinity while booting.
+    pub placement: PlacementPolicy,
+    // tells the domain which of its memory and vcpus belong to which node.
+    pub vnuma: bool,
 }
 
 #[derive(Clone, Debug)]
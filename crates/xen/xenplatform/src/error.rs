@@ -42,6 +42,8 @@ pub enum Error {
     GenericError(String),
     #[error("failed to parse int: {0}")]
     ParseIntError(#[from] std::num::ParseIntError),
+    #[error("invalid memory placement: {0}")]
+    InvalidPlacement(String),
     #[error("failed to join async task: {0}")]
     AsyncJoinError(#[from] tokio::task::JoinError),
 }
@@ -2,12 +2,16 @@ pub mod boot;
 pub mod elfloader;
 pub mod error;
 pub mod mem;
+pub mod numa;
 pub mod sys;
 
 use boot::{BootDomain, BootImageInfo, BootImageLoader, BootSetupPlatform};
 use domain::{PlatformKernelConfig, PlatformResourcesConfig};
 use elfloader::ElfImageLoader;
 use error::Result;
+use sys::XEN_IO_PROTO_ABI_NATIVE;
+#[cfg(target_arch = "x86_64")]
+use sys::XEN_IO_PROTO_ABI_X86_64;
 use unsupported::UnsupportedPlatform;
 use xencall::{sys::CreateDomain, XenCall};
 
@@ -55,6 +59,14 @@ impl RuntimePlatformType {
         }
     }
 
+    pub fn io_protocol(&self) -> &'static str {
+        match self {
+            RuntimePlatformType::Unsupported => XEN_IO_PROTO_ABI_NATIVE,
+            #[cfg(target_arch = "x86_64")]
+            RuntimePlatformType::Pv => XEN_IO_PROTO_ABI_X86_64,
+        }
+    }
+
     pub fn supported() -> RuntimePlatformType {
         #[cfg(target_arch = "x86_64")]
         return RuntimePlatformType::Pv;
@@ -0,0 +1,389 @@
+use log::debug;
+use xencall::{
+    sys::{memflags_exact_node, memflags_node, NumaNodeInfo, VmemRange},
+    XenCall,
+};
+
+use crate::{
+    domain::PlatformResourcesConfig,
+    error::{Error, Result},
+    sys::XEN_PAGE_SHIFT,
+};
+
+// node ranges start on a 2mb boundary so they can still be backed by superpages.
+const RANGE_ALIGN_PAGES: u64 = 512;
+// interleaved memory alternates between nodes every 64mb.
+const INTERLEAVE_CHUNK_PAGES: u64 = 512 * 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlacementPolicy {
+    // prefers the nodes in node_affinity, letting xen fall back to others when they are full.
+    #[default]
+    BestEffort,
+    // only takes memory from the nodes in node_affinity, failing the boot when they are full.
+    Strict,
+    // spreads memory across the nodes in node_affinity, or across every node when there is none.
+    Interleave,
+}
+
+// a part of the guest physical address space and the node hint used to populate it.
+#[derive(Clone, Debug)]
+pub struct PlacedRange {
+    pub start_pfn: u64,
+    pub pages: u64,
+    pub mem_flags: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct VnumaLayout {
+    pub vdistance: Vec<u32>,
+    pub vcpu_to_vnode: Vec<u32>,
+    pub vnode_to_pnode: Vec<u32>,
+    pub vmemranges: Vec<VmemRange>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryPlacement {
+    pub ranges: Vec<PlacedRange>,
+    pub vnuma: Option<VnumaLayout>,
+}
+
+impl MemoryPlacement {
+    // all memory in one range, leaving the choice of nodes to xen.
+    pub fn unplaced(total_pages: u64) -> MemoryPlacement {
+        MemoryPlacement {
+            ranges: vec![PlacedRange {
+                start_pfn: 0,
+                pages: total_pages,
+                mem_flags: 0,
+            }],
+            vnuma: None,
+        }
+    }
+
+    pub async fn plan(
+        call: &XenCall,
+        resources: &PlatformResourcesConfig,
+        total_pages: u64,
+    ) -> Result<MemoryPlacement> {
+        let policy = resources.placement;
+        if resources.node_affinity.is_none()
+            && policy == PlacementPolicy::BestEffort
+            && !resources.vnuma
+        {
+            return Ok(MemoryPlacement::unplaced(total_pages));
+        }
+
+        let numa = call.numa_info().await?;
+        MemoryPlacement::layout(&numa, resources, total_pages)
+    }
+
+    // spreads total_pages over the nodes the policy picks out of numa.
+    fn layout(
+        numa: &[NumaNodeInfo],
+        resources: &PlatformResourcesConfig,
+        total_pages: u64,
+    ) -> Result<MemoryPlacement> {
+        let policy = resources.placement;
+        let online = |node: u32| numa.get(node as usize).is_some_and(NumaNodeInfo::is_online);
+        let nodes: Vec<u32> = match &resources.node_affinity {
+            Some(affinity) => {
+                let nodes: Vec<u32> = affinity.iter().collect();
+                if let Some(node) = nodes.iter().find(|node| !online(**node)) {
+                    return Err(Error::InvalidPlacement(format!(
+                        "numa node {} has no memory",
+                        node
+                    )));
+                }
+                nodes
+            }
+            None if policy == PlacementPolicy::Strict => {
+                return Err(Error::InvalidPlacement(
+                    "strict placement needs a node affinity".to_string(),
+                ));
+            }
+            None => (0..numa.len() as u32)
+                .filter(|node| online(*node))
+                .collect(),
+        };
+        if nodes.is_empty() {
+            return Err(Error::InvalidPlacement(
+                "no numa node to place memory on".to_string(),
+            ));
+        }
+
+        let mem_flags = |node: u32| match policy {
+            PlacementPolicy::Strict => memflags_exact_node(node),
+            _ => memflags_node(node),
+        };
+        let mut ranges = Vec::new();
+        // the physical node behind each range, which becomes vnode n for range n.
+        let mut vnodes = Vec::new();
+        if policy == PlacementPolicy::Interleave {
+            let mut start_pfn = 0;
+            for node in nodes.iter().cycle() {
+                if start_pfn >= total_pages {
+                    break;
+                }
+                let pages = INTERLEAVE_CHUNK_PAGES.min(total_pages - start_pfn);
+                ranges.push(PlacedRange {
+                    start_pfn,
+                    pages,
+                    mem_flags: mem_flags(*node),
+                });
+                start_pfn += pages;
+            }
+        } else {
+            let share = (total_pages / nodes.len() as u64) & !(RANGE_ALIGN_PAGES - 1);
+            let mut start_pfn = 0;
+            for (vnode, node) in nodes.iter().enumerate() {
+                let pages = if vnode == nodes.len() - 1 {
+                    total_pages - start_pfn
+                } else {
+                    share
+                };
+                if pages == 0 {
+                    continue;
+                }
+                ranges.push(PlacedRange {
+                    start_pfn,
+                    pages,
+                    mem_flags: mem_flags(*node),
+                });
+                vnodes.push(*node);
+                start_pfn += pages;
+            }
+        }
+
+        let vnuma = if resources.vnuma {
+            if policy == PlacementPolicy::Interleave {
+                return Err(Error::InvalidPlacement(
+                    "vnuma cannot describe interleaved memory".to_string(),
+                ));
+            }
+            Some(MemoryPlacement::vnuma_layout(
+                numa,
+                &vnodes,
+                &ranges,
+                resources.max_vcpus,
+            ))
+        } else {
+            None
+        };
+
+        debug!(
+            "memory placement policy={:?} nodes={:?} ranges={:?} vnuma={:?}",
+            policy, nodes, ranges, vnuma
+        );
+        Ok(MemoryPlacement { ranges, vnuma })
+    }
+
+    // one vnode per physical node holding a range, with the vcpus split evenly between them.
+    fn vnuma_layout(
+        numa: &[NumaNodeInfo],
+        vnodes: &[u32],
+        ranges: &[PlacedRange],
+        max_vcpus: u32,
+    ) -> VnumaLayout {
+        let count = vnodes.len();
+        let mut vdistance = Vec::with_capacity(count * count);
+        for from in vnodes {
+            for to in vnodes {
+                vdistance.push(numa[*from as usize].distances[*to as usize]);
+            }
+        }
+        let vcpu_to_vnode = (0..max_vcpus as u64)
+            .map(|vcpu| (vcpu * count as u64 / max_vcpus as u64) as u32)
+            .collect();
+        let vmemranges = ranges
+            .iter()
+            .enumerate()
+            .map(|(vnode, range)| VmemRange {
+                start: range.start_pfn << XEN_PAGE_SHIFT,
+                end: (range.start_pfn + range.pages) << XEN_PAGE_SHIFT,
+                flags: 0,
+                nid: vnode as u32,
+            })
+            .collect();
+        VnumaLayout {
+            vdistance,
+            vcpu_to_vnode,
+            vnode_to_pnode: vnodes.to_vec(),
+            vmemranges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xencall::{
+        bitmap::NodeMap,
+        sys::{memflags_exact_node, memflags_node, NumaNodeInfo, XEN_INVALID_MEM_SIZE},
+    };
+
+    use super::{MemoryPlacement, PlacementPolicy, INTERLEAVE_CHUNK_PAGES};
+    use crate::{domain::PlatformResourcesConfig, error::Error};
+
+    fn numa(nodes: &[bool]) -> Vec<NumaNodeInfo> {
+        (0..nodes.len())
+            .map(|node| NumaNodeInfo {
+                node: node as u32,
+                memsize: if nodes[node] {
+                    1 << 34
+                } else {
+                    XEN_INVALID_MEM_SIZE
+                },
+                memfree: 1 << 33,
+                distances: (0..nodes.len())
+                    .map(|other| if other == node { 10 } else { 20 })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn resources(
+        placement: PlacementPolicy,
+        node_affinity: Option<&[u32]>,
+        vnuma: bool,
+    ) -> PlatformResourcesConfig {
+        PlatformResourcesConfig {
+            max_vcpus: 4,
+            assigned_vcpus: 4,
+            max_memory_mb: 1024,
+            assigned_memory_mb: 1024,
+            cpu_affinity: None,
+            soft_cpu_affinity: None,
+            node_affinity: node_affinity.map(|nodes| nodes.iter().copied().collect::<NodeMap>()),
+            placement,
+            vnuma,
+        }
+    }
+
+    fn spans(placement: &MemoryPlacement) -> Vec<(u64, u64, u32)> {
+        placement
+            .ranges
+            .iter()
+            .map(|range| (range.start_pfn, range.pages, range.mem_flags))
+            .collect()
+    }
+
+    #[test]
+    fn splits_evenly_with_the_remainder_on_the_last_node() {
+        let placement = MemoryPlacement::layout(
+            &numa(&[true, true]),
+            &resources(PlacementPolicy::BestEffort, None, false),
+            2000,
+        )
+        .unwrap();
+        // each share is aligned down to 512 pages.
+        assert_eq!(
+            spans(&placement),
+            [(0, 512, memflags_node(0)), (512, 1488, memflags_node(1))]
+        );
+
+        let placement = MemoryPlacement::layout(
+            &numa(&[true, true]),
+            &resources(PlacementPolicy::Strict, Some(&[1]), false),
+            2000,
+        )
+        .unwrap();
+        assert_eq!(spans(&placement), [(0, 2000, memflags_exact_node(1))]);
+    }
+
+    #[test]
+    fn interleaves_in_chunks() {
+        let total = INTERLEAVE_CHUNK_PAGES * 2 + 100;
+        let placement = MemoryPlacement::layout(
+            &numa(&[true, true]),
+            &resources(PlacementPolicy::Interleave, None, false),
+            total,
+        )
+        .unwrap();
+        assert_eq!(
+            spans(&placement),
+            [
+                (0, INTERLEAVE_CHUNK_PAGES, memflags_node(0)),
+                (
+                    INTERLEAVE_CHUNK_PAGES,
+                    INTERLEAVE_CHUNK_PAGES,
+                    memflags_node(1)
+                ),
+                (INTERLEAVE_CHUNK_PAGES * 2, 100, memflags_node(0)),
+            ]
+        );
+        assert!(placement.vnuma.is_none());
+    }
+
+    #[test]
+    fn skips_nodes_without_pages() {
+        // 1000 pages over three nodes leaves a zero share for all but the last.
+        let placement = MemoryPlacement::layout(
+            &numa(&[true, true, true]),
+            &resources(PlacementPolicy::BestEffort, None, true),
+            1000,
+        )
+        .unwrap();
+        assert_eq!(spans(&placement), [(0, 1000, memflags_node(2))]);
+        let vnuma = placement.vnuma.unwrap();
+        assert_eq!(vnuma.vnode_to_pnode, [2]);
+        assert_eq!(vnuma.vmemranges.len(), 1);
+        assert_eq!(vnuma.vmemranges[0].nid, 0);
+        assert_eq!(vnuma.vdistance, [10]);
+        assert_eq!(vnuma.vcpu_to_vnode, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_strict_placement_without_affinity() {
+        assert!(matches!(
+            MemoryPlacement::layout(
+                &numa(&[true]),
+                &resources(PlacementPolicy::Strict, None, false),
+                1024,
+            ),
+            Err(Error::InvalidPlacement(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_offline_nodes() {
+        let numa = numa(&[true, false]);
+        assert!(matches!(
+            MemoryPlacement::layout(
+                &numa,
+                &resources(PlacementPolicy::BestEffort, Some(&[1]), false),
+                1024,
+            ),
+            Err(Error::InvalidPlacement(_))
+        ));
+        assert!(matches!(
+            MemoryPlacement::layout(
+                &numa,
+                &resources(PlacementPolicy::BestEffort, Some(&[2]), false),
+                1024,
+            ),
+            Err(Error::InvalidPlacement(_))
+        ));
+    }
+
+    #[test]
+    fn splits_vcpus_between_vnodes() {
+        let placement = MemoryPlacement::layout(
+            &numa(&[true, true]),
+            &resources(PlacementPolicy::BestEffort, None, true),
+            2048,
+        )
+        .unwrap();
+        let vnuma = placement.vnuma.unwrap();
+        assert_eq!(vnuma.vcpu_to_vnode, [0, 0, 1, 1]);
+        assert_eq!(vnuma.vnode_to_pnode, [0, 1]);
+        assert_eq!(vnuma.vdistance, [10, 20, 20, 10]);
+        assert_eq!(
+            vnuma
+                .vmemranges
+                .iter()
+                .map(|range| (range.start, range.end, range.nid))
+                .collect::<Vec<_>>(),
+            [(0, 1024 << 12, 0), (1024 << 12, 2048 << 12, 1)]
+        );
+    }
+}
@@ -131,6 +131,25 @@ pub struct GrantEntry {
     pub frame: u32,
 }
 
+pub const XEN_IO_PROTO_ABI_X86_32: &str = "x86_32-abi";
+pub const XEN_IO_PROTO_ABI_X86_64: &str = "x86_64-abi";
+pub const XEN_IO_PROTO_ABI_ARM: &str = "arm-abi";
+
+#[cfg(target_arch = "x86_64")]
+pub const XEN_IO_PROTO_ABI_NATIVE: &str = XEN_IO_PROTO_ABI_X86_64;
+#[cfg(target_arch = "x86")]
+pub const XEN_IO_PROTO_ABI_NATIVE: &str = XEN_IO_PROTO_ABI_X86_32;
+#[cfg(any(target_arch = "aarch64", target_arch = "arm"))]
+pub const XEN_IO_PROTO_ABI_NATIVE: &str = XEN_IO_PROTO_ABI_ARM;
+// other targets have no blkif abi of their own, assume the 64-bit x86 layout.
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "arm"
+)))]
+pub const XEN_IO_PROTO_ABI_NATIVE: &str = XEN_IO_PROTO_ABI_X86_64;
+
 pub const XEN_HVM_START_MAGIC_VALUE: u32 = 0x336ec578;
 
 pub const HVM_PARAM_STORE_PFN: u32 = 1;
@@ -138,8 +138,7 @@ pub struct SharedInfo {
 struct VmemRange {
     start: u64,
     end: u64,
-    _flags: u32,
-    _nid: u32,
+    mem_flags: u32,
 }
 
 #[derive(Default, Clone)]
@@ -323,14 +322,16 @@ impl BootSetupPlatform for X86PvPlatform {
             .call
             .claim_pages(domain.domid, domain.total_pages)
             .await?;
-        let mut vmemranges: Vec<VmemRange> = Vec::new();
-        let stub = VmemRange {
-            start: 0,
-            end: domain.total_pages << XEN_PAGE_SHIFT,
-            _flags: 0,
-            _nid: 0,
-        };
-        vmemranges.push(stub);
+        let vmemranges: Vec<VmemRange> = domain
+            .placement
+            .ranges
+            .iter()
+            .map(|range| VmemRange {
+                start: range.start_pfn << XEN_PAGE_SHIFT,
+                end: (range.start_pfn + range.pages) << XEN_PAGE_SHIFT,
+                mem_flags: range.mem_flags,
+            })
+            .collect();
         let mut p2m_size: u64 = 0;
         let mut total: u64 = 0;
         for range in &vmemranges {
@@ -376,7 +377,7 @@ impl BootSetupPlatform for X86PvPlatform {
                         domain.domid,
                         count,
                         SUPERPAGE_2MB_SHIFT as u32,
-                        0,
+                        range.mem_flags,
                         &extents_init_slice[0usize..count as usize],
                     )
                     .await?;
@@ -403,7 +404,13 @@ impl BootSetupPlatform for X86PvPlatform {
                 let input_extent_starts = &p2m[p2m_idx..p2m_end_idx];
                 let result = domain
                     .call
-                    .populate_physmap(domain.domid, allocsz, 0, 0, input_extent_starts)
+                    .populate_physmap(
+                        domain.domid,
+                        allocsz,
+                        0,
+                        range.mem_flags,
+                        input_extent_starts,
+                    )
                     .await?;
 
                 if result.len() != allocsz as usize {
@@ -425,6 +432,18 @@ impl BootSetupPlatform for X86PvPlatform {
 
         domain.phys.load_p2m(p2m);
         domain.call.claim_pages(domain.domid, 0).await?;
+        if let Some(vnuma) = &domain.placement.vnuma {
+            domain
+                .call
+                .set_vnuma_info(
+                    domain.domid,
+                    &vnuma.vdistance,
+                    &vnuma.vcpu_to_vnode,
+                    &vnuma.vnode_to_pnode,
+                    &vnuma.vmemranges,
+                )
+                .await?;
+        }
         Ok(())
     }
 
@@ -557,20 +576,19 @@ impl BootSetupPlatform for X86PvPlatform {
                     let p_e = (std::cmp::min(to, lvl.to) - from)
                         >> (X86_PAGE_SHIFT + l as u64 * X86_PGTABLE_LEVEL_SHIFT);
                     let rhs = X86_PAGE_SHIFT as usize + l * X86_PGTABLE_LEVEL_SHIFT as usize;
-                    let mut pfn = ((std::cmp::max(from, lvl.from) - lvl.from) >> rhs) + lvl.pfn;
+                    let first_pfn = ((std::cmp::max(from, lvl.from) - lvl.from) >> rhs) + lvl.pfn;
 
                     debug!(
                         "setup_page_tables lvl={} map_1={} map_2={} pfn={:#x} p_s={:#x} p_e={:#x}",
-                        l, m1, m2, pfn, p_s, p_e
+                        l, m1, m2, first_pfn, p_s, p_e
                     );
 
                     let pg = unsafe { slice::from_raw_parts_mut(pg_ptr, (p_e + 1) as usize) };
-                    for p in p_s..p_e + 1 {
+                    for (pfn, p) in (first_pfn..).zip(p_s..p_e + 1) {
                         let prot = self.get_pg_prot(l, pfn);
                         let pfn_paddr = domain.phys.p2m[pfn as usize] << X86_PAGE_SHIFT;
                         let value = pfn_paddr | prot;
                         pg[p as usize] = value;
-                        pfn += 1;
                     }
                 }
             }
@@ -5,8 +5,8 @@ pub mod sys;
 use crate::bus::XsdSocket;
 use crate::error::{Error, Result};
 use crate::sys::{
-    XSD_DIRECTORY, XSD_GET_DOMAIN_PATH, XSD_INTRODUCE, XSD_MKDIR, XSD_READ, XSD_RM, XSD_SET_PERMS,
-    XSD_TRANSACTION_END, XSD_TRANSACTION_START, XSD_WATCH, XSD_WRITE,
+    XSD_DIRECTORY, XSD_GET_DOMAIN_PATH, XSD_INTRODUCE, XSD_MKDIR, XSD_READ, XSD_RELEASE, XSD_RM,
+    XSD_SET_PERMS, XSD_TRANSACTION_END, XSD_TRANSACTION_START, XSD_WATCH, XSD_WRITE,
 };
 use log::trace;
 use std::ffi::CString;
@@ -218,6 +218,15 @@ impl XsdClient {
         response.parse_bool()
     }
 
+    pub async fn release_domain(&self, domid: u32) -> Result<bool> {
+        trace!("release domain domid={domid}");
+        let response = self
+            .socket
+            .send(0, XSD_RELEASE, &[domid.to_string().as_str()])
+            .await?;
+        response.parse_bool()
+    }
+
     pub async fn create_multi_watch(&self) -> Result<XsdMultiWatchHandle> {
         let (id, receiver, unwatch_sender) = self.socket.add_watch().await?;
         Ok(XsdMultiWatchHandle {
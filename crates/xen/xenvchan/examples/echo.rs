@@ -0,0 +1,31 @@
+use std::{env, str::FromStr};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use xenvchan::{error::Result, server::VchanServer};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    let domid = u32::from_str(args.get(1).map(|s| s.as_str()).unwrap_or("1"))?;
+    let path = args
+        .get(2)
+        .cloned()
+        .unwrap_or_else(|| format!("/local/domain/{}/data/vchan", domid));
+
+    let mut stream = VchanServer::new(domid, &path).listen().await?;
+    println!("waiting for domain {} to connect to {}", domid, path);
+    if !stream.wait_for_peer().await {
+        return Ok(());
+    }
+
+    let mut buffer = vec![0u8; 4096];
+    loop {
+        let size = stream.read(&mut buffer).await?;
+        if size == 0 {
+            break;
+        }
+        stream.write_all(&buffer[..size]).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+use xenevtchn::EventChannelService;
+use xengnt::{sys::GrantRef, GrantTab, MappedMemory};
+use xenstore::{XsdClient, XsdInterface};
+
+use crate::{
+    error::{Error, Result},
+    ring::{ring_pages, VchanRing, INTF_CLI_LIVE},
+    stream::VchanStream,
+};
+
+// connects to a vchan a server in another domain advertised under a xenstore path.
+pub struct VchanClient;
+
+impl VchanClient {
+    pub async fn connect(
+        domid: u32,
+        path: impl AsRef<str>,
+    ) -> Result<VchanStream<MappedMemory<'static>>> {
+        let path = path.as_ref();
+        let store = XsdClient::open().await?;
+        let read_u32 = |key: &'static str| {
+            let store = &store;
+            async move {
+                let value = store
+                    .read_string(format!("{}/{}", path, key))
+                    .await?
+                    .ok_or_else(|| Error::ParameterMissing(key.to_string()))?;
+                Ok::<u32, Error>(u32::from_str(value.trim())?)
+            }
+        };
+        let ring_ref = read_u32("ring-ref").await?;
+        let port = read_u32("event-channel").await?;
+
+        let gnttab = GrantTab::open()?;
+        let map = |refs: &[u32]| {
+            gnttab.map_grant_refs(
+                refs.iter()
+                    .map(|reference| GrantRef {
+                        domid,
+                        reference: *reference,
+                    })
+                    .collect(),
+                true,
+                true,
+            )
+        };
+        let interface = map(&[ring_ref]).await?;
+        let (left_order, _, grants) = VchanRing::layout(&interface)?;
+        let (left_grants, right_grants) = grants.split_at(ring_pages(left_order));
+        let left = match left_grants {
+            [] => None,
+            refs => Some(map(refs).await?),
+        };
+        let right = match right_grants {
+            [] => None,
+            refs => Some(map(refs).await?),
+        };
+
+        let evtchn = EventChannelService::open().await?;
+        let channel = evtchn.bind(domid, port).await?;
+        // the server sees us as closed if this process goes away.
+        interface.unmap_notify(Some(INTF_CLI_LIVE), Some(channel.local_port))?;
+        let ring = VchanRing::new(interface, left, right, false)?;
+        ring.connect();
+
+        let stream = VchanStream::new(ring, channel);
+        stream.kick();
+        Ok(stream)
+    }
+}
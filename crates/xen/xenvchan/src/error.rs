@@ -0,0 +1,31 @@
+use std::io;
+use std::num::ParseIntError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io issue encountered: {0}")]
+    Io(#[from] io::Error),
+    #[error("xenstore error: {0}")]
+    XenStore(#[from] xenstore::error::Error),
+    #[error("grant table error: {0}")]
+    GrantTable(#[from] xengnt::error::Error),
+    #[error("event channel error: {0}")]
+    EventChannel(#[from] xenevtchn::error::Error),
+    #[error("unable to parse integer: {0}")]
+    ParseInt(#[from] ParseIntError),
+    #[error("invalid vchan ring: {0}")]
+    InvalidRing(String),
+    #[error("vchan parameter missing: {0}")]
+    ParameterMissing(String),
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(error) => error,
+            error => io::Error::other(error),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
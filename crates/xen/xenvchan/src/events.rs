@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use xenevtchn::BoundEventChannel;
+
+use crate::error::Result;
+
+// how the two ends of a vchan signal each other.
+#[async_trait::async_trait]
+pub trait VchanEvents: Send + Sync + 'static {
+    async fn notify(&self) -> Result<()>;
+    async fn wait(&self) -> Result<()>;
+}
+
+#[async_trait::async_trait]
+impl VchanEvents for BoundEventChannel {
+    async fn notify(&self) -> Result<()> {
+        self.service.notify(self.local_port).await?;
+        Ok(())
+    }
+
+    async fn wait(&self) -> Result<()> {
+        self.receiver.notified().await;
+        self.unmask().await?;
+        Ok(())
+    }
+}
+
+// events between two ends of a vchan living in the same process.
+pub struct LocalVchanEvents {
+    local: Arc<Notify>,
+    peer: Arc<Notify>,
+}
+
+impl LocalVchanEvents {
+    pub fn pair() -> (LocalVchanEvents, LocalVchanEvents) {
+        let left = Arc::new(Notify::new());
+        let right = Arc::new(Notify::new());
+        (
+            LocalVchanEvents {
+                local: left.clone(),
+                peer: right.clone(),
+            },
+            LocalVchanEvents {
+                local: right,
+                peer: left,
+            },
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl VchanEvents for LocalVchanEvents {
+    async fn notify(&self) -> Result<()> {
+        self.peer.notify_one();
+        Ok(())
+    }
+
+    async fn wait(&self) -> Result<()> {
+        self.local.notified().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+pub mod client;
+pub mod error;
+pub mod events;
+pub mod ring;
+pub mod server;
+pub mod stream;
@@ -0,0 +1,372 @@
+use std::{
+    ptr::copy_nonoverlapping,
+    sync::atomic::{fence, AtomicU16, AtomicU32, AtomicU8, Ordering},
+};
+
+use xengnt::{memory::SharedMemory, PAGE_SIZE};
+
+use crate::error::{Error, Result};
+
+// layout of struct vchan_interface from xen/include/public/io/libxenvchan.h.
+const INTF_LEFT_CONS: usize = 0;
+const INTF_LEFT_PROD: usize = 4;
+const INTF_RIGHT_CONS: usize = 8;
+const INTF_RIGHT_PROD: usize = 12;
+const INTF_LEFT_ORDER: usize = 16;
+const INTF_RIGHT_ORDER: usize = 18;
+pub const INTF_CLI_LIVE: usize = 20;
+pub const INTF_SRV_LIVE: usize = 21;
+const INTF_CLI_NOTIFY: usize = 22;
+const INTF_SRV_NOTIFY: usize = 23;
+const INTF_GRANTS: usize = 24;
+
+pub const VCHAN_NOTIFY_WRITE: u8 = 0x1;
+pub const VCHAN_NOTIFY_READ: u8 = 0x2;
+
+// rings of these orders live inside the interface page itself.
+pub const SMALL_RING_SHIFT: u16 = 10;
+pub const LARGE_RING_SHIFT: u16 = 11;
+const SMALL_RING_OFFSET: usize = 1024;
+const LARGE_RING_OFFSET: usize = 2048;
+pub const MAX_RING_SHIFT: u16 = 20;
+const PAGE_SHIFT: u16 = 12;
+
+// live states of each end, where a client that has not yet connected is 2.
+const LIVE_CLOSED: u8 = 0;
+const LIVE_OPEN: u8 = 1;
+const LIVE_WAITING: u8 = 2;
+
+// picks the ring orders the same way libxenvchan_server_init does.
+pub fn ring_orders(left_min: usize, right_min: usize) -> (u16, u16) {
+    let small = 1usize << SMALL_RING_SHIFT;
+    let large = 1usize << LARGE_RING_SHIFT;
+    if left_min <= small && right_min <= large {
+        (SMALL_RING_SHIFT, LARGE_RING_SHIFT)
+    } else if left_min <= large && right_min <= small {
+        (LARGE_RING_SHIFT, SMALL_RING_SHIFT)
+    } else if left_min <= large {
+        (LARGE_RING_SHIFT, page_order(right_min))
+    } else if right_min <= large {
+        (page_order(left_min), LARGE_RING_SHIFT)
+    } else {
+        (page_order(left_min), page_order(right_min))
+    }
+}
+
+fn page_order(size: usize) -> u16 {
+    let mut order = PAGE_SHIFT;
+    while (1usize << order) < size {
+        order += 1;
+    }
+    order
+}
+
+// the number of separately granted pages a ring of this order needs.
+pub fn ring_pages(order: u16) -> usize {
+    if order >= PAGE_SHIFT {
+        1 << (order - PAGE_SHIFT)
+    } else {
+        0
+    }
+}
+
+struct RingBuffer<M: SharedMemory> {
+    pages: Option<M>,
+    offset: usize,
+    size: usize,
+    cons: usize,
+    prod: usize,
+}
+
+impl<M: SharedMemory> RingBuffer<M> {
+    fn new(order: u16, pages: Option<M>, cons: usize, prod: usize) -> Result<Self> {
+        let (offset, expected) = match order {
+            SMALL_RING_SHIFT => (SMALL_RING_OFFSET, 0),
+            LARGE_RING_SHIFT => (LARGE_RING_OFFSET, 0),
+            order if (PAGE_SHIFT..=MAX_RING_SHIFT).contains(&order) => (0, ring_pages(order)),
+            order => {
+                return Err(Error::InvalidRing(format!(
+                    "ring order {} is outside of {}..={}",
+                    order, SMALL_RING_SHIFT, MAX_RING_SHIFT
+                )))
+            }
+        };
+        let provided = pages.as_ref().map(|pages| pages.len() / PAGE_SIZE);
+        if provided.unwrap_or_default() != expected {
+            return Err(Error::InvalidRing(format!(
+                "ring of order {} needs {} pages, {} were provided",
+                order,
+                expected,
+                provided.unwrap_or_default()
+            )));
+        }
+        Ok(RingBuffer {
+            pages,
+            offset,
+            size: 1 << order,
+            cons,
+            prod,
+        })
+    }
+
+    fn base(&self, interface: &M) -> *mut u8 {
+        match &self.pages {
+            Some(pages) => pages.ptr(),
+            None => unsafe { interface.ptr().add(self.offset) },
+        }
+    }
+}
+
+// the shared state of a vchan as seen from one of its two ends. the server
+// reads from the left ring and writes to the right, the client the reverse.
+pub struct VchanRing<M: SharedMemory> {
+    interface: M,
+    read: RingBuffer<M>,
+    write: RingBuffer<M>,
+    server: bool,
+}
+
+impl<M: SharedMemory> VchanRing<M> {
+    // resets the interface page for a server with the given ring orders, and
+    // records the grants of any separately granted ring pages.
+    pub fn init_server(
+        interface: &M,
+        left_order: u16,
+        right_order: u16,
+        grants: &[u32],
+    ) -> Result<()> {
+        let pages = ring_pages(left_order) + ring_pages(right_order);
+        if interface.len() < PAGE_SIZE {
+            return Err(Error::InvalidRing(
+                "interface is smaller than a page".to_string(),
+            ));
+        }
+        if grants.len() != pages || INTF_GRANTS + pages * 4 > interface.len() {
+            return Err(Error::InvalidRing(format!(
+                "{} grants cannot describe {} ring pages",
+                grants.len(),
+                pages
+            )));
+        }
+        unsafe {
+            std::ptr::write_bytes(interface.ptr(), 0, INTF_GRANTS);
+            copy_nonoverlapping(
+                grants.as_ptr() as *const u8,
+                interface.ptr().add(INTF_GRANTS),
+                pages * 4,
+            );
+        }
+        atomic_u16(interface, INTF_LEFT_ORDER).store(left_order, Ordering::Relaxed);
+        atomic_u16(interface, INTF_RIGHT_ORDER).store(right_order, Ordering::Relaxed);
+        atomic_u8(interface, INTF_CLI_LIVE).store(LIVE_WAITING, Ordering::Relaxed);
+        atomic_u8(interface, INTF_SRV_LIVE).store(LIVE_OPEN, Ordering::Relaxed);
+        atomic_u8(interface, INTF_CLI_NOTIFY).store(VCHAN_NOTIFY_WRITE, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+
+    // the ring orders and grants a server published, as read by a client.
+    pub fn layout(interface: &M) -> Result<(u16, u16, Vec<u32>)> {
+        if interface.len() < PAGE_SIZE {
+            return Err(Error::InvalidRing(
+                "interface is smaller than a page".to_string(),
+            ));
+        }
+        let left_order = atomic_u16(interface, INTF_LEFT_ORDER).load(Ordering::Acquire);
+        let right_order = atomic_u16(interface, INTF_RIGHT_ORDER).load(Ordering::Acquire);
+        let pages = ring_pages(left_order) + ring_pages(right_order);
+        if left_order > MAX_RING_SHIFT
+            || right_order > MAX_RING_SHIFT
+            || INTF_GRANTS + pages * 4 > interface.len()
+        {
+            return Err(Error::InvalidRing(format!(
+                "ring orders {} and {} are too large",
+                left_order, right_order
+            )));
+        }
+        let grants = (0..pages)
+            .map(|index| atomic_u32(interface, INTF_GRANTS + index * 4).load(Ordering::Relaxed))
+            .collect();
+        Ok((left_order, right_order, grants))
+    }
+
+    // attaches to an initialised interface page, given the separately
+    // granted pages of the left and right rings, if any.
+    pub fn new(interface: M, left: Option<M>, right: Option<M>, server: bool) -> Result<Self> {
+        let (left_order, right_order, _) = VchanRing::layout(&interface)?;
+        if left_order == right_order && left_order < PAGE_SHIFT {
+            return Err(Error::InvalidRing(format!(
+                "both rings of order {} would share the interface page",
+                left_order
+            )));
+        }
+        let left = RingBuffer::new(left_order, left, INTF_LEFT_CONS, INTF_LEFT_PROD)?;
+        let right = RingBuffer::new(right_order, right, INTF_RIGHT_CONS, INTF_RIGHT_PROD)?;
+        let (read, write) = if server { (left, right) } else { (right, left) };
+        Ok(VchanRing {
+            interface,
+            read,
+            write,
+            server,
+        })
+    }
+
+    pub fn is_server(&self) -> bool {
+        self.server
+    }
+
+    pub fn read_size(&self) -> usize {
+        self.read.size
+    }
+
+    pub fn write_size(&self) -> usize {
+        self.write.size
+    }
+
+    // marks a client as connected to the server.
+    pub fn connect(&self) {
+        if !self.server {
+            self.live(INTF_CLI_LIVE).store(LIVE_OPEN, Ordering::Relaxed);
+            self.notify_flags(INTF_SRV_NOTIFY)
+                .store(VCHAN_NOTIFY_WRITE, Ordering::Relaxed);
+            fence(Ordering::SeqCst);
+        }
+    }
+
+    pub fn close(&self) {
+        fence(Ordering::SeqCst);
+        self.live(self.own_live())
+            .store(LIVE_CLOSED, Ordering::Release);
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.live(self.own_live()).load(Ordering::Acquire) != LIVE_CLOSED
+    }
+
+    // a server considers a client that has yet to connect as open.
+    pub fn is_peer_open(&self) -> bool {
+        self.live(self.peer_live()).load(Ordering::Acquire) != LIVE_CLOSED
+    }
+
+    pub fn is_peer_connected(&self) -> bool {
+        self.live(self.peer_live()).load(Ordering::Acquire) == LIVE_OPEN
+    }
+
+    pub fn data_ready(&self) -> usize {
+        let cons = self.index(self.read.cons).load(Ordering::Relaxed);
+        let prod = self.index(self.read.prod).load(Ordering::Acquire);
+        // never trust the peer to produce more than the ring holds.
+        (prod.wrapping_sub(cons) as usize).min(self.read.size)
+    }
+
+    pub fn buffer_space(&self) -> usize {
+        let prod = self.index(self.write.prod).load(Ordering::Relaxed);
+        let cons = self.index(self.write.cons).load(Ordering::Acquire);
+        self.write
+            .size
+            .saturating_sub(prod.wrapping_sub(cons) as usize)
+    }
+
+    // copies out as much as is ready, returning how many bytes were read.
+    pub fn read(&self, buffer: &mut [u8]) -> usize {
+        let length = buffer.len().min(self.data_ready());
+        if length == 0 {
+            return 0;
+        }
+        let cons = self.index(self.read.cons).load(Ordering::Relaxed);
+        let start = cons as usize & (self.read.size - 1);
+        let first = length.min(self.read.size - start);
+        let base = self.read.base(&self.interface);
+        unsafe {
+            copy_nonoverlapping(base.add(start), buffer.as_mut_ptr(), first);
+            copy_nonoverlapping(base, buffer[first..].as_mut_ptr(), length - first);
+        }
+        fence(Ordering::SeqCst);
+        self.index(self.read.cons)
+            .store(cons.wrapping_add(length as u32), Ordering::Release);
+        length
+    }
+
+    // copies in as much as there is space for, returning how many bytes were written.
+    pub fn write(&self, buffer: &[u8]) -> usize {
+        let length = buffer.len().min(self.buffer_space());
+        if length == 0 {
+            return 0;
+        }
+        let prod = self.index(self.write.prod).load(Ordering::Relaxed);
+        let start = prod as usize & (self.write.size - 1);
+        let first = length.min(self.write.size - start);
+        let base = self.write.base(&self.interface);
+        unsafe {
+            copy_nonoverlapping(buffer.as_ptr(), base.add(start), first);
+            copy_nonoverlapping(buffer[first..].as_ptr(), base, length - first);
+        }
+        fence(Ordering::SeqCst);
+        self.index(self.write.prod)
+            .store(prod.wrapping_add(length as u32), Ordering::Release);
+        length
+    }
+
+    // asks the peer to notify us once it does what the bit describes.
+    pub fn request_notify(&self, bit: u8) {
+        let flags = if self.server {
+            INTF_CLI_NOTIFY
+        } else {
+            INTF_SRV_NOTIFY
+        };
+        self.notify_flags(flags).fetch_or(bit, Ordering::SeqCst);
+        fence(Ordering::SeqCst);
+    }
+
+    // clears a request from the peer, returning whether it wanted an event.
+    pub fn send_notify(&self, bit: u8) -> bool {
+        fence(Ordering::SeqCst);
+        let flags = if self.server {
+            INTF_SRV_NOTIFY
+        } else {
+            INTF_CLI_NOTIFY
+        };
+        self.notify_flags(flags).fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    fn own_live(&self) -> usize {
+        if self.server {
+            INTF_SRV_LIVE
+        } else {
+            INTF_CLI_LIVE
+        }
+    }
+
+    fn peer_live(&self) -> usize {
+        if self.server {
+            INTF_CLI_LIVE
+        } else {
+            INTF_SRV_LIVE
+        }
+    }
+
+    fn index(&self, offset: usize) -> &AtomicU32 {
+        atomic_u32(&self.interface, offset)
+    }
+
+    fn live(&self, offset: usize) -> &AtomicU8 {
+        atomic_u8(&self.interface, offset)
+    }
+
+    fn notify_flags(&self, offset: usize) -> &AtomicU8 {
+        atomic_u8(&self.interface, offset)
+    }
+}
+
+// the header offsets are all within the first page, which every interface holds.
+fn atomic_u8<M: SharedMemory>(memory: &M, offset: usize) -> &AtomicU8 {
+    unsafe { &*(memory.ptr().add(offset) as *const AtomicU8) }
+}
+
+fn atomic_u16<M: SharedMemory>(memory: &M, offset: usize) -> &AtomicU16 {
+    unsafe { &*(memory.ptr().add(offset) as *const AtomicU16) }
+}
+
+fn atomic_u32<M: SharedMemory>(memory: &M, offset: usize) -> &AtomicU32 {
+    unsafe { &*(memory.ptr().add(offset) as *const AtomicU32) }
+}
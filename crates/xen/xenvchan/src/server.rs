@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+use log::warn;
+use xenevtchn::{BoundEventChannel, EventChannelService};
+use xengnt::{GrantAlloc, GrantedPages};
+use xenstore::{XsPermission, XsdClient, XsdInterface, XS_PERM_NONE, XS_PERM_READ};
+
+use crate::{
+    error::Result,
+    ring::{ring_orders, ring_pages, VchanRing, INTF_SRV_LIVE},
+    stream::VchanStream,
+};
+
+// offers a vchan to a client domain, advertised under a xenstore path.
+pub struct VchanServer {
+    domid: u32,
+    path: String,
+    read_min: usize,
+    write_min: usize,
+}
+
+// removes the advertisement of a vchan once its stream is gone.
+struct VchanAdvertisement {
+    store: XsdClient,
+    path: String,
+}
+
+impl Drop for VchanAdvertisement {
+    fn drop(&mut self) {
+        let store = self.store.clone();
+        let path = self.path.clone();
+        tokio::task::spawn(async move {
+            if let Err(error) = store.rm(&path).await {
+                warn!("failed to remove vchan advertisement {}: {}", path, error);
+            }
+        });
+    }
+}
+
+impl VchanServer {
+    pub fn new(domid: u32, path: impl AsRef<str>) -> VchanServer {
+        VchanServer {
+            domid,
+            path: path.as_ref().to_string(),
+            read_min: 0,
+            write_min: 0,
+        }
+    }
+
+    pub fn read_min(&mut self, read_min: usize) -> &mut Self {
+        self.read_min = read_min;
+        self
+    }
+
+    pub fn write_min(&mut self, write_min: usize) -> &mut Self {
+        self.write_min = write_min;
+        self
+    }
+
+    pub async fn listen(&self) -> Result<VchanStream<GrantedPages>> {
+        let store = XsdClient::open().await?;
+        let alloc = GrantAlloc::open()?;
+        let evtchn = EventChannelService::open().await?;
+        let (left_order, right_order) = ring_orders(self.read_min, self.write_min);
+
+        let port = evtchn.bind_unbound_port(self.domid).await?;
+        let channel = BoundEventChannel {
+            local_port: port,
+            receiver: evtchn.subscribe(port).await?,
+            service: evtchn.clone(),
+        };
+
+        // the client sees us as closed if this process goes away.
+        let interface = alloc.grant_pages(self.domid as u16, 1, true)?;
+        interface.unmap_notify(Some(INTF_SRV_LIVE), Some(port))?;
+        let grant = |order| -> Result<Option<GrantedPages>> {
+            match ring_pages(order) {
+                0 => Ok(None),
+                pages => Ok(Some(alloc.grant_pages(
+                    self.domid as u16,
+                    pages as u32,
+                    true,
+                )?)),
+            }
+        };
+        let left = grant(left_order)?;
+        let right = grant(right_order)?;
+        let grants = left
+            .iter()
+            .chain(right.iter())
+            .flat_map(|pages| pages.refs().iter().copied())
+            .collect::<Vec<_>>();
+        VchanRing::init_server(&interface, left_order, right_order, &grants)?;
+        let ring_ref = interface.refs()[0];
+        let ring = VchanRing::new(interface, left, right, true)?;
+
+        let local_domid = match store.read_string("domid").await? {
+            Some(domid) => u32::from_str(domid.trim())?,
+            None => 0,
+        };
+        let perms = [
+            XsPermission {
+                id: local_domid,
+                perms: XS_PERM_NONE,
+            },
+            XsPermission {
+                id: self.domid,
+                perms: XS_PERM_READ,
+            },
+        ];
+        for (key, value) in [("ring-ref", ring_ref), ("event-channel", port)] {
+            let path = format!("{}/{}", self.path, key);
+            store.write_string(&path, &value.to_string()).await?;
+            store.set_perms(&path, &perms).await?;
+        }
+
+        let mut stream = VchanStream::new(ring, channel);
+        stream.guard(VchanAdvertisement {
+            store,
+            path: self.path.clone(),
+        });
+        Ok(stream)
+    }
+}
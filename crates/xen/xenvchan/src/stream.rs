@@ -0,0 +1,396 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use log::warn;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Notify,
+    task::JoinHandle,
+};
+use xengnt::memory::{LocalMemory, SharedMemory};
+
+use crate::{
+    error::Result,
+    events::{LocalVchanEvents, VchanEvents},
+    ring::{ring_orders, ring_pages, VchanRing, VCHAN_NOTIFY_READ, VCHAN_NOTIFY_WRITE},
+};
+
+#[derive(Default)]
+struct VchanShared {
+    read_waker: Mutex<Option<Waker>>,
+    write_waker: Mutex<Option<Waker>>,
+    events: Notify,
+    kick: Notify,
+    closing: AtomicBool,
+    broken: AtomicBool,
+}
+
+impl VchanShared {
+    fn wake(&self) {
+        for waker in [&self.read_waker, &self.write_waker] {
+            if let Some(waker) = waker.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                waker.wake();
+            }
+        }
+        self.events.notify_waiters();
+    }
+
+    fn register(waker: &Mutex<Option<Waker>>, cx: &Context<'_>) {
+        *waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(cx.waker().clone());
+    }
+}
+
+// a byte stream over one end of a vchan. events from the peer are waited on
+// and sent by two background tasks, so the stream must live within a tokio runtime.
+pub struct VchanStream<M: SharedMemory> {
+    ring: Arc<VchanRing<M>>,
+    shared: Arc<VchanShared>,
+    waiter: JoinHandle<()>,
+    guard: Option<Box<dyn Send + Sync>>,
+}
+
+impl<M: SharedMemory + 'static> VchanStream<M> {
+    pub fn new<E: VchanEvents>(ring: VchanRing<M>, events: E) -> VchanStream<M> {
+        let ring = Arc::new(ring);
+        let events = Arc::new(events);
+        let shared = Arc::new(VchanShared::default());
+
+        let waiter = {
+            let events = events.clone();
+            let shared = shared.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    if let Err(error) = events.wait().await {
+                        warn!("failed to wait for vchan events: {}", error);
+                        shared.broken.store(true, Ordering::Release);
+                        shared.wake();
+                        break;
+                    }
+                    shared.wake();
+                }
+            })
+        };
+
+        // the notifier keeps the ring alive until the peer has been told we closed.
+        {
+            let ring = ring.clone();
+            let shared = shared.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    shared.kick.notified().await;
+                    if let Err(error) = events.notify().await {
+                        warn!("failed to notify vchan peer: {}", error);
+                    }
+                    if shared.closing.load(Ordering::Acquire) {
+                        break;
+                    }
+                }
+                drop(ring);
+            });
+        }
+
+        VchanStream {
+            ring,
+            shared,
+            waiter,
+            guard: None,
+        }
+    }
+}
+
+impl<M: SharedMemory> VchanStream<M> {
+    // keeps a resource, such as an advertisement of the vchan, alive with the stream.
+    pub(crate) fn guard(&mut self, guard: impl Send + Sync + 'static) {
+        self.guard = Some(Box::new(guard));
+    }
+
+    // sends the peer an event, regardless of whether it asked for one.
+    pub(crate) fn kick(&self) {
+        self.shared.kick.notify_one();
+    }
+
+    pub fn ring(&self) -> &VchanRing<M> {
+        &self.ring
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.ring.is_open() && self.ring.is_peer_open()
+    }
+
+    // waits for a client to connect, returning false if it went away first.
+    pub async fn wait_for_peer(&self) -> bool {
+        loop {
+            let notified = self.shared.events.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.ring.is_peer_connected() {
+                return true;
+            }
+            if !self.ring.is_peer_open() || self.shared.broken.load(Ordering::Acquire) {
+                return false;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn close(&self) {
+        if self.ring.is_open() {
+            self.ring.close();
+            self.shared.closing.store(true, Ordering::Release);
+            self.shared.kick.notify_one();
+        }
+    }
+
+    fn notify(&self, bit: u8) {
+        if self.ring.send_notify(bit) {
+            self.shared.kick.notify_one();
+        }
+    }
+
+    // returns None when the caller has to wait for the peer.
+    fn try_read(&self, buf: &mut ReadBuf<'_>) -> Option<io::Result<()>> {
+        if buf.remaining() == 0 {
+            return Some(Ok(()));
+        }
+        let length = self.ring.read(buf.initialize_unfilled());
+        if length > 0 {
+            buf.advance(length);
+            self.notify(VCHAN_NOTIFY_READ);
+            return Some(Ok(()));
+        }
+        // whatever the peer wrote before closing has been drained, once a read
+        // after seeing the close still comes back empty.
+        if !self.ring.is_open() || !self.ring.is_peer_open() {
+            let length = self.ring.read(buf.initialize_unfilled());
+            buf.advance(length);
+            return Some(Ok(()));
+        }
+        if self.shared.broken.load(Ordering::Acquire) {
+            return Some(Err(io::Error::other("vchan event channel failed")));
+        }
+        None
+    }
+
+    fn try_write(&self, buf: &[u8]) -> Option<io::Result<usize>> {
+        if !self.ring.is_open() || !self.ring.is_peer_open() {
+            return Some(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Some(Ok(0));
+        }
+        let length = self.ring.write(buf);
+        if length > 0 {
+            self.notify(VCHAN_NOTIFY_WRITE);
+            return Some(Ok(length));
+        }
+        if self.shared.broken.load(Ordering::Acquire) {
+            return Some(Err(io::Error::other("vchan event channel failed")));
+        }
+        None
+    }
+}
+
+pub type LocalVchanStream = VchanStream<Arc<LocalMemory>>;
+
+impl LocalVchanStream {
+    // connects a server and a client over memory within this process.
+    pub fn pair(left_min: usize, right_min: usize) -> Result<(LocalVchanStream, LocalVchanStream)> {
+        let (left_order, right_order) = ring_orders(left_min, right_min);
+        let pages = |order| {
+            let pages = ring_pages(order);
+            (pages > 0).then(|| Arc::new(LocalMemory::new(pages)))
+        };
+        let interface = Arc::new(LocalMemory::new(1));
+        let left = pages(left_order);
+        let right = pages(right_order);
+        let grants = vec![0; ring_pages(left_order) + ring_pages(right_order)];
+        VchanRing::init_server(&interface, left_order, right_order, &grants)?;
+
+        let server = VchanRing::new(interface.clone(), left.clone(), right.clone(), true)?;
+        let client = VchanRing::new(interface, left, right, false)?;
+        client.connect();
+        let (server_events, client_events) = LocalVchanEvents::pair();
+        Ok((
+            VchanStream::new(server, server_events),
+            VchanStream::new(client, client_events),
+        ))
+    }
+}
+
+impl<M: SharedMemory> AsyncRead for VchanStream<M> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(result) = self.try_read(buf) {
+            return Poll::Ready(result);
+        }
+        // register before asking for an event, so one arriving in between still wakes us.
+        VchanShared::register(&self.shared.read_waker, cx);
+        self.ring.request_notify(VCHAN_NOTIFY_WRITE);
+        match self.try_read(buf) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<M: SharedMemory> AsyncWrite for VchanStream<M> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Some(result) = self.try_write(buf) {
+            return Poll::Ready(result);
+        }
+        VchanShared::register(&self.shared.write_waker, cx);
+        self.ring.request_notify(VCHAN_NOTIFY_READ);
+        match self.try_write(buf) {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<M: SharedMemory> Drop for VchanStream<M> {
+    fn drop(&mut self) {
+        self.close();
+        // the notifier exits by itself once the close has been sent.
+        self.shared.closing.store(true, Ordering::Release);
+        self.shared.kick.notify_one();
+        self.waiter.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::ErrorKind, time::Duration};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::LocalVchanStream;
+
+    fn pattern(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index % 251) as u8).collect()
+    }
+
+    // streams length bytes from one end to the other, closing the writer
+    // afterwards so the reader sees the end of the stream.
+    async fn stream(
+        mut writer: LocalVchanStream,
+        mut reader: LocalVchanStream,
+        length: usize,
+    ) -> LocalVchanStream {
+        let data = pattern(length);
+        let sender = tokio::spawn(async move {
+            // odd sized writes, so the ring indexes land everywhere in the ring.
+            for chunk in data.chunks(333) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+            writer
+        });
+        let mut received = Vec::new();
+        reader.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, pattern(length));
+        sender.await.unwrap();
+        reader
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (mut server, mut client) = LocalVchanStream::pair(0, 0).unwrap();
+        assert!(server.is_open() && client.is_open());
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buffer = [0u8; 4];
+        server.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        server.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"pong");
+    }
+
+    #[tokio::test]
+    async fn wraps_the_small_in_page_ring() {
+        // the client writes into the 1024 byte left ring.
+        let (server, client) = LocalVchanStream::pair(0, 0).unwrap();
+        assert_eq!(server.ring().read_size(), 1024);
+        stream(client, server, 10 * 1024 + 17).await;
+    }
+
+    #[tokio::test]
+    async fn wraps_the_large_in_page_ring() {
+        // the server writes into the 2048 byte right ring.
+        let (server, client) = LocalVchanStream::pair(0, 0).unwrap();
+        assert_eq!(client.ring().read_size(), 2048);
+        stream(server, client, 10 * 2048 + 17).await;
+    }
+
+    #[tokio::test]
+    async fn wraps_a_multi_page_ring() {
+        let (server, client) = LocalVchanStream::pair(8192, 0).unwrap();
+        assert_eq!(server.ring().read_size(), 8192);
+        stream(client, server, 5 * 8192 + 17).await;
+    }
+
+    #[tokio::test]
+    async fn writer_waits_for_the_reader() {
+        let (mut server, mut client) = LocalVchanStream::pair(0, 0).unwrap();
+        let data = pattern(4 * 1024);
+        let writer = {
+            let data = data.clone();
+            tokio::spawn(async move {
+                client.write_all(&data).await.unwrap();
+                client
+            })
+        };
+
+        // the ring holds a quarter of the data, so the writer cannot finish alone.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!writer.is_finished());
+
+        let mut received = vec![0u8; data.len()];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, data);
+        tokio::time::timeout(Duration::from_secs(5), writer)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn peer_close_ends_the_stream() {
+        let (mut server, mut client) = LocalVchanStream::pair(0, 0).unwrap();
+        server.write_all(b"last").await.unwrap();
+        drop(server);
+
+        // data written before the close is still delivered.
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"last");
+        assert!(!client.is_open());
+
+        let error = client.write_all(b"more").await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    }
+}